@@ -11,16 +11,23 @@ use std::{
 
 use crate::{
     colors::{cell_color, colors_for_player, head_color},
+    debug::{predicted_capture, DebugOverlay},
+    replay::{ReplayAction, ReplayPlayer, MAX_SPEED, MIN_SPEED},
     state::GameState,
+    stats::{GameStats, PlayerStats},
 };
 
 use anyhow::bail;
 use eframe::egui;
-use egui::{pos2, vec2, Align, Color32, Layout, Rect, RichText, Sense, Slider, Vec2};
+use egui::{
+    pos2, vec2, Align, Align2, Color32, FontId, Layout, Rect, RichText, Sense, Shape, Slider,
+    Stroke, Vec2,
+};
 use num_traits::FromPrimitive;
 use paperio_proto::{
     traits::{JsonRead, JsonWrite},
-    Cell, Command, Direction, Message, PlayerId, PlayerInfo,
+    view::WorldView,
+    Cell, Command, Direction, GameParams, GameReplay, Message, PlayerId, PlayerInfo,
 };
 
 const KEY_MAP: [(egui::Key, Direction); 4] = [
@@ -32,8 +39,9 @@ const KEY_MAP: [(egui::Key, Direction); 4] = [
 
 enum State {
     AwaitForGameStart,
-    Tick(GameState),
-    Ended,
+    /// Ticks received so far, spectators can scrub through them.
+    Tick(ReplayPlayer, GameStats),
+    Ended(GameState, GameStats),
 }
 
 pub struct PaperioApp {
@@ -42,6 +50,8 @@ pub struct PaperioApp {
     tick_duration: Arc<AtomicU64>,
     is_spectator: bool,
     player_nicknames: Option<HashMap<PlayerId, PlayerInfo>>,
+    replay: Option<ReplayPlayer>,
+    overlay: DebugOverlay,
 }
 
 impl PaperioApp {
@@ -56,17 +66,29 @@ impl PaperioApp {
             tick_duration: Arc::new(AtomicU64::new(tick_delay_ms)),
             is_spectator,
             player_nicknames: None,
+            replay: None,
+            overlay: DebugOverlay::default(),
         }
     }
 
+    /// Plays a recorded game instead of connecting to a server.
+    pub fn from_replay(replay: &GameReplay, tick_delay_ms: u64) -> anyhow::Result<Self> {
+        let mut app = Self::new(tick_delay_ms, true);
+        app.set_nicknames(replay.player_infos.clone());
+        app.replay = Some(ReplayPlayer::new(replay)?);
+        Ok(app)
+    }
+
     pub fn set_nicknames(&mut self, nicknames: HashMap<PlayerId, PlayerInfo>) {
         self.player_nicknames = Some(nicknames)
     }
 
-    fn get_nickname(&self, player_id: &PlayerId) -> String {
+    fn get_nickname(&self, params: &GameParams, player_id: &PlayerId) -> String {
         self.player_nicknames
             .as_ref()
-            .and_then(|nicknames| nicknames.get(player_id).map(|i| &i.user_name).cloned())
+            .and_then(|nicknames| nicknames.get(player_id))
+            .or_else(|| params.players.get(player_id))
+            .map(|info| info.user_name.clone())
             .unwrap_or_else(|| {
                 if player_id == "i" {
                     "Me".to_string()
@@ -94,7 +116,7 @@ impl PaperioApp {
             let Message::StartGame(params) = reader.read_message()? else {
                 bail!("first message is not `StartGame`")
             };
-            *state.lock().unwrap() = State::Tick(GameState::new(params));
+            *state.lock().unwrap() = State::Tick(ReplayPlayer::live(params), GameStats::default());
 
             // receive tick msgs
             log::info!("Entering loop of receiving tick messages");
@@ -108,15 +130,22 @@ impl PaperioApp {
                             State::AwaitForGameStart => {
                                 bail!("unexpected tick while waiting for game to start")
                             }
-                            State::Tick(game_field) => {
-                                game_field.update(world);
+                            State::Tick(ticks, stats) => {
+                                stats.record(&world);
+                                ticks.push(world);
                             }
-                            State::Ended => bail!("unexpected tick when game ended"),
+                            State::Ended(..) => bail!("unexpected tick when game ended"),
                         }
                     }
                     Message::EndGame {} => {
                         log::info!("End game message received");
-                        *state.lock().unwrap() = State::Ended;
+                        let mut state_guard = state.lock().unwrap();
+                        let last_state =
+                            std::mem::replace(state_guard.deref_mut(), State::AwaitForGameStart);
+                        let State::Tick(ticks, stats) = last_state else {
+                            bail!("unexpected `EndGame` message");
+                        };
+                        *state_guard = State::Ended(ticks.into_latest_game(), stats);
                         break;
                     }
                 }
@@ -143,23 +172,261 @@ impl PaperioApp {
         }
     }
 
-    fn draw_field(&self, ui: &mut egui::Ui, game: &GameState) {
-        let params = game.params;
+    fn draw_game(
+        &self,
+        ui: &mut egui::Ui,
+        game: &GameState,
+        overlay: Option<&DebugOverlay>,
+        add_controls: impl FnOnce(&mut egui::Ui),
+    ) {
+        ui.with_layout(Layout::left_to_right(Align::Min), |ui| {
+            self.draw_field(ui, game, overlay);
+
+            ui.with_layout(Layout::top_down(Align::Min), |ui| {
+                self.draw_scores(ui, game);
+
+                let tick_ms = self.tick_duration.load(Ordering::Relaxed);
+                let mut slider_tick_ms = tick_ms;
+                ui.add(Slider::new(&mut slider_tick_ms, 0..=1000));
+                ui.label("Tick (ms)");
+                if slider_tick_ms != tick_ms {
+                    self.tick_duration.store(slider_tick_ms, Ordering::Relaxed);
+                }
+
+                add_controls(ui);
+            })
+        });
+    }
+
+    fn draw_scores(&self, ui: &mut egui::Ui, game: &GameState) {
+        let mut scores = game
+            .world
+            .players
+            .iter()
+            .map(|(id, p)| (id, p.score))
+            .collect::<Vec<_>>();
+
+        scores.sort_unstable_by(|(id1, s1), (id2, s2)| s2.cmp(s1).then(id1.cmp(id2)));
+
+        for (id, score) in &scores {
+            let player_name = self.get_nickname(&game.params, id);
+            let text = format!("{player_name}: {score}");
+            let text = RichText::new(text)
+                .size(30.)
+                .color(colors_for_player(id).captured);
+            ui.label(text);
+        }
+    }
+
+    fn draw_summary(&self, ui: &mut egui::Ui, game: &GameState, stats: &GameStats) {
+        ui.heading("Game ended");
+
+        let mut players = game.world.players.iter().collect::<Vec<_>>();
+        players.sort_unstable_by(|(id1, p1), (id2, p2)| p2.score.cmp(&p1.score).then(id1.cmp(id2)));
+        for (place, (id, player)) in players.into_iter().enumerate() {
+            let mut text = format!(
+                "{}. {}: {} ({} cells)",
+                place + 1,
+                self.get_nickname(&game.params, id),
+                player.score,
+                player.territory.len(),
+            );
+            if let Some(tick) = stats.lost_at(id) {
+                text += &format!(", lost at tick {tick}");
+            }
+            ui.label(
+                RichText::new(text)
+                    .size(24.)
+                    .color(colors_for_player(id).captured),
+            );
+        }
+
+        ui.separator();
+        ui.label("Territory");
+        Self::draw_timeline(ui, stats, |player| &player.territory);
+        ui.label("Score");
+        Self::draw_timeline(ui, stats, |player| &player.scores);
+
+        ui.separator();
+        if let Some((id, len, tick)) = stats.longest_trail() {
+            let name = self.get_nickname(&game.params, id);
+            ui.label(format!("Longest trail: {name}, {len} cells at tick {tick}"));
+        }
+        ui.label("Kills:");
+        if stats.kills.is_empty() {
+            ui.label("nobody was killed");
+        }
+        for kill in &stats.kills {
+            let victim = self.get_nickname(&game.params, &kill.victim);
+            let text = match &kill.killer {
+                Some(killer) => {
+                    let killer = self.get_nickname(&game.params, killer);
+                    format!("tick {}: {killer} killed {victim}", kill.tick)
+                }
+                None => format!("tick {}: {victim} crashed", kill.tick),
+            };
+            ui.label(text);
+        }
+    }
+
+    /// Chart of a value of every player over the ticks of the game.
+    fn draw_timeline<T: Copy + Into<f64>>(
+        ui: &mut egui::Ui,
+        stats: &GameStats,
+        values: impl Fn(&PlayerStats) -> &Vec<T>,
+    ) {
+        let size = vec2(ui.available_width().min(800.), 200.);
+        let (response, painter) = ui.allocate_painter(size, Sense::hover());
+        let rect = response.rect;
+        painter.rect_stroke(rect, 0., Stroke::new(1., Color32::GRAY));
+
+        let max = stats
+            .players
+            .values()
+            .flat_map(|player| values(player).iter().map(|&value| value.into()))
+            .fold(1., f64::max);
+        let last_index = (stats.ticks.len().max(2) - 1) as f32;
+        for (id, player) in &stats.players {
+            let points = values(player)
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| {
+                    let x = rect.left() + rect.width() * i as f32 / last_index;
+                    let y = rect.bottom() - rect.height() * (value.into() / max) as f32;
+                    pos2(x, y)
+                })
+                .collect::<Vec<_>>();
+            painter.add(Shape::line(
+                points,
+                Stroke::new(2., colors_for_player(id).captured),
+            ));
+        }
+
+        let font = FontId::proportional(14.);
+        painter.text(
+            rect.left_top(),
+            Align2::LEFT_TOP,
+            max,
+            font.clone(),
+            Color32::GRAY,
+        );
+        if let Some(tick) = stats.ticks.last() {
+            let text = format!("tick {tick}");
+            painter.text(
+                rect.right_bottom(),
+                Align2::RIGHT_BOTTOM,
+                text,
+                font,
+                Color32::GRAY,
+            );
+        }
+    }
+
+    /// Game with the debug view: playback controls and overlays.
+    fn draw_replay(
+        &self,
+        ui: &mut egui::Ui,
+        replay: &mut ReplayPlayer,
+        overlay: &mut DebugOverlay,
+        elapsed_ms: f32,
+    ) {
+        replay.advance(elapsed_ms, self.tick_duration.load(Ordering::Relaxed));
+        let mut actions = vec![];
+        let shown_overlay = overlay.clone();
+        self.draw_game(ui, replay.game(), Some(&shown_overlay), |ui| {
+            actions = Self::draw_replay_controls(ui, replay);
+            self.draw_overlay_controls(ui, replay.game(), overlay);
+        });
+        for action in actions {
+            replay.apply(action);
+        }
+    }
+
+    fn draw_overlay_controls(
+        &self,
+        ui: &mut egui::Ui,
+        game: &GameState,
+        overlay: &mut DebugOverlay,
+    ) {
+        ui.separator();
+        ui.checkbox(&mut overlay.trails, "Trails");
+        ui.checkbox(&mut overlay.captures, "Predicted captures");
+        for id in game.world.players.keys() {
+            let mut shown = overlay.is_shown(id);
+            let name = self.get_nickname(&game.params, id);
+            let text = RichText::new(name).color(colors_for_player(id).captured);
+            if ui.checkbox(&mut shown, text).changed() {
+                overlay.set_shown(id, shown);
+            }
+        }
+    }
+
+    fn draw_replay_controls(ui: &mut egui::Ui, replay: &ReplayPlayer) -> Vec<ReplayAction> {
+        let mut actions = vec![];
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui.button("<").clicked() {
+                actions.push(ReplayAction::Step { forward: false });
+            }
+            let play_label = if replay.is_playing() { "Pause" } else { "Play" };
+            if ui.button(play_label).clicked() {
+                actions.push(ReplayAction::TogglePause);
+            }
+            if ui.button(">").clicked() {
+                actions.push(ReplayAction::Step { forward: true });
+            }
+        });
+
+        if ui.input(|i| i.key_pressed(egui::Key::Space)) {
+            actions.push(ReplayAction::TogglePause);
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::ArrowLeft)) {
+            actions.push(ReplayAction::Step { forward: false });
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::ArrowRight)) {
+            actions.push(ReplayAction::Step { forward: true });
+        }
+
+        let mut position = replay.position();
+        ui.add(Slider::new(
+            &mut position,
+            0..=replay.tick_count().saturating_sub(1),
+        ));
+        ui.label("Tick");
+        if position != replay.position() {
+            actions.push(ReplayAction::Seek(position));
+        }
+
+        let mut speed = replay.speed();
+        ui.add(Slider::new(&mut speed, MIN_SPEED..=MAX_SPEED).logarithmic(true));
+        ui.label("Speed");
+        if speed != replay.speed() {
+            actions.push(ReplayAction::SetSpeed(speed));
+        }
+
+        actions
+    }
+
+    fn draw_field(&self, ui: &mut egui::Ui, game: &GameState, overlay: Option<&DebugOverlay>) {
+        let params = &game.params;
         let size_in_cells = vec2(params.x_cells_count as f32, params.y_cells_count as f32);
         let size_in_pixels = ui.available_size_before_wrap();
         let cell_size_with_border = (size_in_pixels / size_in_cells).floor().min_elem();
         let cell_sizes = Vec2::splat(cell_size_with_border - 1.);
 
-        let (_, painter) =
+        let (response, painter) =
             ui.allocate_painter(size_in_cells * cell_size_with_border, Sense::hover());
 
-        let zero_pos = ui.min_rect().min.to_vec2();
-        let draw_cell = |Cell(x, y): Cell, color: Color32| {
+        let zero_pos = response.rect.min;
+        let cell_rect = |Cell(x, y): Cell| {
             // Game indexation is down-to-top, but we draw top-to-down, so invert Oy here.
             let y = params.y_cells_count - 1 - y as u32;
-            let rect_corner = pos2(x as f32, y as f32) * cell_size_with_border + zero_pos;
-            let rect = Rect::from_min_size(rect_corner, cell_sizes);
-            painter.rect_filled(rect, 0., color);
+            let rect_corner = pos2(x as f32, y as f32) * cell_size_with_border + zero_pos.to_vec2();
+            Rect::from_min_size(rect_corner, cell_sizes)
+        };
+        let draw_cell = |cell: Cell, color: Color32| {
+            painter.rect_filled(cell_rect(cell), 0., color);
         };
 
         for (y, row) in game.field.iter().enumerate() {
@@ -174,52 +441,111 @@ impl PaperioApp {
                 draw_cell(player.position, color)
             }
         }
+
+        let Some(overlay) = overlay else {
+            return;
+        };
+        let view = WorldView::new(&game.world);
+        let mut predictions = vec![];
+        for (id, player) in &game.world.players {
+            if player.has_lost || !overlay.is_shown(id) {
+                continue;
+            }
+            let colors = colors_for_player(id);
+            if overlay.trails {
+                for &cell in &player.lines {
+                    painter.rect_stroke(cell_rect(cell), 0., Stroke::new(2., colors.head));
+                }
+            }
+            if overlay.captures {
+                let captured = predicted_capture(&view, id);
+                for &cell in &captured {
+                    let rect = cell_rect(cell).shrink(cell_size_with_border / 3.);
+                    painter.rect_filled(rect, 0., colors.head);
+                }
+                predictions.push((id, captured));
+            }
+        }
+
+        if let Some(position) = response.hover_pos() {
+            let offset = (position - zero_pos) / cell_size_with_border;
+            let cell = Cell(
+                offset.x.floor() as i32,
+                params.y_cells_count as i32 - 1 - offset.y.floor() as i32,
+            );
+            if cell.in_bounds() {
+                response.on_hover_ui_at_pointer(|ui| {
+                    self.draw_cell_info(ui, game, &view, cell, &predictions);
+                });
+            }
+        }
+    }
+
+    /// Tooltip of the debug view with everything about the cell.
+    fn draw_cell_info(
+        &self,
+        ui: &mut egui::Ui,
+        game: &GameState,
+        view: &WorldView,
+        cell: Cell,
+        predictions: &[(&PlayerId, Vec<Cell>)],
+    ) {
+        let name = |id: &PlayerId| self.get_nickname(&game.params, id);
+        let Cell(x, y) = cell;
+        ui.label(format!("Cell ({x}, {y})"));
+        match view.owner(cell) {
+            Some(id) => ui.label(format!("Territory of {}", name(id))),
+            None => ui.label("Free"),
+        };
+        if let Some(id) = view.trace_owner(cell) {
+            ui.label(format!("Trail of {}", name(id)));
+        }
+        for (id, player) in &game.world.players {
+            if !player.has_lost && player.position == cell {
+                let direction = player
+                    .direction
+                    .map_or("standing".to_string(), |direction| format!("{direction:?}"));
+                ui.label(format!(
+                    "Head of {}, {direction}, score {}",
+                    name(id),
+                    player.score
+                ));
+            }
+        }
+        for (id, captured) in predictions {
+            if captured.contains(&cell) {
+                ui.label(format!("Captured if {} goes home now", name(id)));
+            }
+        }
     }
 }
 
 impl eframe::App for PaperioApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         ctx.request_repaint();
+
+        let elapsed_ms = ctx.input(|i| i.unstable_dt) * 1000.;
+        let mut overlay = self.overlay.clone();
+        if let Some(mut replay) = self.replay.take() {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                self.draw_replay(ui, &mut replay, &mut overlay, elapsed_ms);
+            });
+            self.replay = Some(replay);
+            self.overlay = overlay;
+            return;
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             let mut state_guard = self.state.lock().unwrap();
             match state_guard.deref_mut() {
                 State::AwaitForGameStart => {
                     ui.label("Waiting to 'start_game'");
                 }
-                State::Tick(ref game) => {
-                    ui.with_layout(Layout::left_to_right(Align::Min), |ui| {
-                        self.draw_field(ui, game);
-
-                        ui.with_layout(Layout::top_down(Align::Min), |ui| {
-                            let mut scores = game
-                                .world
-                                .players
-                                .iter()
-                                .map(|(id, p)| (id, p.score))
-                                .collect::<Vec<_>>();
-
-                            scores.sort_unstable_by(|(id1, s1), (id2, s2)| {
-                                s2.cmp(s1).then(id1.cmp(id2))
-                            });
-
-                            for (id, score) in &scores {
-                                let player_name = self.get_nickname(id);
-                                let text = format!("{player_name}: {score}");
-                                let text = RichText::new(text)
-                                    .size(30.)
-                                    .color(colors_for_player(id).captured);
-                                ui.label(text);
-                            }
-
-                            let tick_ms = self.tick_duration.load(Ordering::Relaxed);
-                            let mut slider_tick_ms = tick_ms;
-                            ui.add(Slider::new(&mut slider_tick_ms, 0..=1000));
-                            ui.label("Tick (ms)");
-                            if slider_tick_ms != tick_ms {
-                                self.tick_duration.store(slider_tick_ms, Ordering::Relaxed);
-                            }
-                        })
-                    });
+                State::Tick(ref mut ticks, _) if self.is_spectator => {
+                    self.draw_replay(ui, ticks, &mut overlay, elapsed_ms);
+                }
+                State::Tick(ref ticks, _) => {
+                    self.draw_game(ui, ticks.game(), None, |_| {});
 
                     for (k, d) in KEY_MAP {
                         if ui.input(|i| i.key_pressed(k)) {
@@ -227,12 +553,15 @@ impl eframe::App for PaperioApp {
                         }
                     }
                 }
-                State::Ended => {
-                    ui.label("Game ended");
+                State::Ended(ref game, ref stats) => {
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        self.draw_summary(ui, game, stats);
+                    });
                 }
             }
             drop(state_guard);
         });
+        self.overlay = overlay;
     }
 }
 
@@ -1,4 +1,4 @@
-use egui::Color32;
+use egui::{ecolor::Hsva, Color32};
 use paperio_proto::PlayerId;
 
 use crate::state::CellState;
@@ -39,15 +39,26 @@ pub struct PlayerColors {
 }
 
 pub fn colors_for_player(id: &PlayerId) -> PlayerColors {
-    match id as &str {
-        "1" => COLOR_PALETTE[1],
-        "2" => COLOR_PALETTE[2],
-        "3" => COLOR_PALETTE[3],
-        "4" => COLOR_PALETTE[4],
+    match id.parse::<usize>() {
+        Ok(index @ 1..) if index < COLOR_PALETTE.len() => COLOR_PALETTE[index],
+        Ok(index @ 1..) => generated_colors(index),
         _ => COLOR_PALETTE[0],
     }
 }
 
+/// Colors for players that don't fit into the palette.
+/// Hues are spread with the golden ratio, so neighbouring ids look different.
+fn generated_colors(index: usize) -> PlayerColors {
+    const GOLDEN_RATIO_CONJUGATE: f32 = 0.618_034;
+
+    let hue = (index as f32 * GOLDEN_RATIO_CONJUGATE).fract();
+    PlayerColors {
+        head: Hsva::new(hue, 0.9, 0.6, 1.).into(),
+        captured: Hsva::new(hue, 0.75, 0.85, 1.).into(),
+        traced: Hsva::new(hue, 0.4, 0.95, 1.).into(),
+    }
+}
+
 pub fn head_color(id: &PlayerId) -> Color32 {
     colors_for_player(id).head
 }
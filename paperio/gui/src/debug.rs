@@ -0,0 +1,90 @@
+use std::collections::BTreeSet;
+
+use paperio_proto::{
+    view::{Grid, WorldView},
+    Cell, PlayerId,
+};
+
+/// What the debug view draws over the field for spectators and replays.
+#[derive(Clone)]
+pub struct DebugOverlay {
+    pub trails: bool,
+    pub captures: bool,
+    /// Players whose overlays are turned off.
+    pub hidden: BTreeSet<PlayerId>,
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        Self {
+            trails: true,
+            captures: true,
+            hidden: BTreeSet::new(),
+        }
+    }
+}
+
+impl DebugOverlay {
+    pub fn is_shown(&self, player_id: &PlayerId) -> bool {
+        !self.hidden.contains(player_id)
+    }
+
+    pub fn set_shown(&mut self, player_id: &PlayerId, shown: bool) {
+        if shown {
+            self.hidden.remove(player_id);
+        } else {
+            self.hidden.insert(player_id.clone());
+        }
+    }
+}
+
+/// Cells the player captures if it goes home along the shortest path right now.
+/// Empty if it is at home or can't get there.
+pub fn predicted_capture(view: &WorldView, player_id: &PlayerId) -> Vec<Cell> {
+    let player = &view.world().players[player_id];
+    if player.has_lost || player.lines.is_empty() {
+        return vec![];
+    }
+    // The player can't cross its own trace on the way.
+    let mut trace = Grid::new(false);
+    for &cell in &player.lines {
+        trace[cell] = true;
+    }
+    match view.path_home(player_id, &trace) {
+        Some(path) => view.capture_area_of(player_id, &path),
+        None => vec![],
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::{Direction, Player, World};
+
+    #[test]
+    fn predicts_capture_on_the_way_home() {
+        let territory = (1..=3)
+            .flat_map(|x| (1..=3).map(move |y| Cell(x, y)))
+            .collect();
+        let player = Player {
+            score: 0,
+            territory,
+            position: Cell(4, 3),
+            lines: vec![Cell(4, 1), Cell(4, 2), Cell(4, 3)],
+            direction: Some(Direction::Up),
+            has_lost: false,
+            speed_boost_ticks: 0,
+        };
+        let world = World {
+            players: [("1".to_string(), player)].into(),
+            tick_num: 10,
+        };
+        let view = WorldView::new(&world);
+
+        let mut captured = predicted_capture(&view, &"1".to_string());
+        captured.sort_by_key(|&Cell(x, y)| (x, y));
+        assert_eq!(captured, [Cell(4, 1), Cell(4, 2), Cell(4, 3)]);
+    }
+}
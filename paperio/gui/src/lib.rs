@@ -1,3 +1,6 @@
 pub mod app;
 mod colors;
+mod debug;
+mod replay;
 mod state;
+mod stats;
@@ -1,12 +1,17 @@
 use std::{
     future::Future,
-    io::{BufReader, BufWriter},
+    io::{BufReader, BufWriter, Write},
     net::TcpStream,
+    path::PathBuf,
     thread,
 };
 
 use clap::Parser;
 use paperio_gui::app::PaperioApp;
+use paperio_proto::{
+    traits::{JsonRead, JsonWrite},
+    GameReplay, Handshake, Hello, PROTOCOL_VERSION,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -19,6 +24,12 @@ struct Arguments {
     tick_delay_ms: u64,
     #[arg(short, long, action)]
     spectator: bool,
+    /// Name shown to other players and spectators.
+    #[arg(short, long, default_value_t = String::from("Human"))]
+    name: String,
+    /// Play a recorded replay file instead of connecting to a server.
+    #[arg(short, long)]
+    replay: Option<PathBuf>,
 }
 
 fn main() {
@@ -30,18 +41,43 @@ fn main() {
         .init()
         .expect("failed to initialize stderr logger");
 
-    let stream = TcpStream::connect(format!("{}:{}", args.address, args.port))
-        .expect("failed to connect to tcp socket");
-    let stream_clone = stream.try_clone().expect("failed to clone tcp stream");
-
     // run gui in current thread
     let native_options = eframe::NativeOptions {
         window_builder: Some(Box::new(|b| b.with_inner_size((1200., 980.)))),
         ..Default::default()
     };
+
+    if let Some(path) = args.replay {
+        let replay = GameReplay::load(&path).expect("failed to load replay");
+        let app = PaperioApp::from_replay(&replay, args.tick_delay_ms).expect("invalid replay");
+        eframe::run_native("paperio", native_options, Box::new(|_| Ok(Box::new(app)))).unwrap();
+        return;
+    }
+
+    let stream = TcpStream::connect(format!("{}:{}", args.address, args.port))
+        .expect("failed to connect to tcp socket");
+    let stream_clone = stream.try_clone().expect("failed to clone tcp stream");
+
     let app = PaperioApp::new(args.tick_delay_ms, args.spectator);
-    let reader = BufReader::new(stream);
-    let writer = BufWriter::new(stream_clone);
+    let mut reader = BufReader::new(stream);
+    let mut writer = BufWriter::new(stream_clone);
+
+    let hello = Hello {
+        name: args.name,
+        protocol_version: PROTOCOL_VERSION,
+        features: vec![],
+        session_token: None,
+    };
+    writer
+        .write_handshake(&Handshake::Hello(hello))
+        .and_then(|_| writer.flush())
+        .expect("failed to send hello");
+    let Handshake::Welcome(welcome) = reader.read_handshake().expect("failed to read welcome")
+    else {
+        panic!("expected welcome from server");
+    };
+    log::info!("welcome: {welcome:?}");
+
     let mut backend_future = Box::pin(app.run_backend(reader, writer));
     let handle = thread::spawn(move || {
         let waker = futures::task::noop_waker();
@@ -0,0 +1,172 @@
+use anyhow::{bail, Result};
+use paperio_proto::{GameParams, GameReplay, Message, World};
+
+use crate::state::GameState;
+
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 8.;
+
+pub enum ReplayAction {
+    TogglePause,
+    Step { forward: bool },
+    Seek(usize),
+    SetSpeed(f32),
+}
+
+/// Plays ticks back from a replay file, or from a game in progress
+/// as they arrive from the server, see [`ReplayPlayer::live`].
+pub struct ReplayPlayer {
+    worlds: Vec<World>,
+    position: usize,
+    is_playing: bool,
+    /// Ticks keep arriving, playing follows the latest one.
+    is_live: bool,
+    speed: f32,
+    since_last_tick_ms: f32,
+    game: GameState,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: &GameReplay) -> Result<Self> {
+        let mut messages = replay.messages.iter();
+        let Some(Message::StartGame(params)) = messages.next() else {
+            bail!("replay does not start with `StartGame` message")
+        };
+
+        let worlds = messages
+            .filter_map(|message| match message {
+                Message::Tick(world) => Some(world.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if worlds.is_empty() {
+            bail!("replay has no ticks");
+        }
+
+        let mut game = GameState::new(params.clone());
+        game.update(worlds[0].clone());
+
+        Ok(Self {
+            worlds,
+            position: 0,
+            is_playing: true,
+            is_live: false,
+            speed: 1.,
+            since_last_tick_ms: 0.,
+            game,
+        })
+    }
+
+    /// Follows a game in progress, ticks are added with [`Self::push`].
+    pub fn live(params: GameParams) -> Self {
+        Self {
+            worlds: vec![],
+            position: 0,
+            is_playing: true,
+            is_live: true,
+            speed: 1.,
+            since_last_tick_ms: 0.,
+            game: GameState::new(params),
+        }
+    }
+
+    /// Adds a tick of a live game. It is shown at once unless paused or scrubbed back.
+    pub fn push(&mut self, world: World) {
+        let is_following = self.is_playing && self.position + 1 >= self.worlds.len();
+        self.worlds.push(world);
+        if self.worlds.len() == 1 {
+            self.game.update(self.worlds[0].clone());
+        } else if is_following {
+            self.seek(self.worlds.len() - 1);
+        }
+    }
+
+    /// State at the latest tick.
+    pub fn into_latest_game(mut self) -> GameState {
+        self.seek(self.tick_count().saturating_sub(1));
+        self.game
+    }
+
+    pub fn game(&self) -> &GameState {
+        &self.game
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn tick_count(&self) -> usize {
+        self.worlds.len()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if !self.is_playing && !self.is_live && self.position + 1 == self.tick_count() {
+            // Replay from the start once the end is reached.
+            self.seek(0);
+        }
+        self.is_playing = !self.is_playing;
+    }
+
+    pub fn seek(&mut self, position: usize) {
+        if self.worlds.is_empty() {
+            return;
+        }
+        let position = position.min(self.tick_count() - 1);
+        if position != self.position {
+            self.position = position;
+            self.game.update(self.worlds[position].clone());
+        }
+        self.since_last_tick_ms = 0.;
+    }
+
+    pub fn step(&mut self, forward: bool) {
+        self.is_playing = false;
+        if forward {
+            self.seek(self.position + 1);
+        } else {
+            self.seek(self.position.saturating_sub(1));
+        }
+    }
+
+    pub fn apply(&mut self, action: ReplayAction) {
+        match action {
+            ReplayAction::TogglePause => self.toggle_pause(),
+            ReplayAction::Step { forward } => self.step(forward),
+            ReplayAction::Seek(position) => self.seek(position),
+            ReplayAction::SetSpeed(speed) => self.set_speed(speed),
+        }
+    }
+
+    /// Advances playback by `elapsed_ms` of wall time.
+    pub fn advance(&mut self, elapsed_ms: f32, tick_duration_ms: u64) {
+        if !self.is_playing {
+            return;
+        }
+
+        self.since_last_tick_ms += elapsed_ms * self.speed;
+        let tick_duration_ms = (tick_duration_ms as f32).max(1.);
+        let ticks = (self.since_last_tick_ms / tick_duration_ms) as usize;
+        if ticks > 0 {
+            let since_last_tick_ms = self.since_last_tick_ms - ticks as f32 * tick_duration_ms;
+            self.seek(self.position + ticks);
+            self.since_last_tick_ms = since_last_tick_ms;
+        }
+
+        // A live game waits for the next tick instead.
+        if !self.is_live && self.position + 1 == self.tick_count() {
+            self.is_playing = false;
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+
+use paperio_proto::{PlayerId, World};
+
+/// Scores, territories and kills collected tick by tick while the game runs.
+#[derive(Default)]
+pub struct GameStats {
+    /// Numbers of the recorded ticks.
+    pub ticks: Vec<u32>,
+    pub players: BTreeMap<PlayerId, PlayerStats>,
+    pub kills: Vec<Kill>,
+    previous: Option<World>,
+}
+
+/// Values of a player at each recorded tick. Before the player appears
+/// they are zero, after it disappears they stay the last known ones.
+#[derive(Default)]
+pub struct PlayerStats {
+    pub scores: Vec<u32>,
+    pub territory: Vec<u32>,
+    /// Length of the longest trail and the tick it was reached at.
+    pub longest_trail: (usize, u32),
+}
+
+pub struct Kill {
+    pub tick: u32,
+    pub victim: PlayerId,
+    /// Player whose head ended up on the victim's trail or head,
+    /// `None` if the victim crashed on its own.
+    pub killer: Option<PlayerId>,
+}
+
+impl PlayerStats {
+    fn resize(&mut self, len: usize) {
+        self.scores
+            .resize(len, self.scores.last().copied().unwrap_or(0));
+        self.territory
+            .resize(len, self.territory.last().copied().unwrap_or(0));
+    }
+}
+
+impl GameStats {
+    pub fn record(&mut self, world: &World) {
+        let len = self.ticks.len() + 1;
+        self.ticks.push(world.tick_num);
+        for (id, player) in &world.players {
+            let stats = self.players.entry(id.clone()).or_default();
+            stats.resize(len - 1);
+            stats.scores.push(player.score);
+            stats.territory.push(player.territory.len() as u32);
+            if player.lines.len() > stats.longest_trail.0 {
+                stats.longest_trail = (player.lines.len(), world.tick_num);
+            }
+        }
+        for stats in self.players.values_mut() {
+            stats.resize(len);
+        }
+
+        if let Some(previous) = &self.previous {
+            self.kills.extend(find_kills(previous, world));
+        }
+        self.previous = Some(world.clone());
+    }
+
+    /// Player with the longest trail of the game, its length and the tick.
+    pub fn longest_trail(&self) -> Option<(&PlayerId, usize, u32)> {
+        self.players
+            .iter()
+            .map(|(id, stats)| (id, stats.longest_trail.0, stats.longest_trail.1))
+            .filter(|&(_, len, _)| len > 0)
+            .max_by(|(id1, len1, _), (id2, len2, _)| len1.cmp(len2).then(id2.cmp(id1)))
+    }
+
+    pub fn lost_at(&self, player_id: &PlayerId) -> Option<u32> {
+        self.kills
+            .iter()
+            .find(|kill| &kill.victim == player_id)
+            .map(|kill| kill.tick)
+    }
+}
+
+/// Players who lost between the two worlds, and who got them.
+fn find_kills<'a>(previous: &'a World, world: &'a World) -> impl Iterator<Item = Kill> + 'a {
+    world
+        .players
+        .iter()
+        .filter(|(_, player)| player.has_lost)
+        .filter_map(|(victim_id, _)| {
+            let victim = previous.players.get(victim_id)?;
+            if victim.has_lost {
+                return None;
+            }
+            let killer = world
+                .players
+                .iter()
+                .filter(|(id, _)| *id != victim_id)
+                .find(|(_, player)| {
+                    player.position == victim.position
+                        || player.position == world.players[victim_id].position
+                        || victim.lines.contains(&player.position)
+                })
+                .map(|(id, _)| id.clone());
+            Some(Kill {
+                tick: world.tick_num,
+                victim: victim_id.clone(),
+                killer,
+            })
+        })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::{Cell, Direction, Player};
+
+    fn player(position: Cell, lines: Vec<Cell>, has_lost: bool) -> Player {
+        Player {
+            score: lines.len() as u32,
+            territory: vec![Cell(0, 0)],
+            position,
+            lines,
+            direction: Some(Direction::Right),
+            has_lost,
+            speed_boost_ticks: 0,
+        }
+    }
+
+    fn world(tick_num: u32, players: Vec<(&str, Player)>) -> World {
+        World {
+            players: players
+                .into_iter()
+                .map(|(id, player)| (id.to_string(), player))
+                .collect(),
+            tick_num,
+        }
+    }
+
+    #[test]
+    fn records_kills_and_trails() {
+        let trail = vec![Cell(3, 3), Cell(4, 3), Cell(5, 3)];
+        let mut stats = GameStats::default();
+        stats.record(&world(
+            1,
+            vec![
+                ("1", player(Cell(5, 3), trail.clone(), false)),
+                ("2", player(Cell(4, 4), vec![], false)),
+                ("3", player(Cell(9, 9), vec![Cell(9, 8)], false)),
+            ],
+        ));
+        stats.record(&world(
+            6,
+            vec![
+                ("1", player(Cell(6, 3), vec![], true)),
+                ("2", player(Cell(4, 3), vec![], false)),
+                ("3", player(Cell(9, 9), vec![], true)),
+            ],
+        ));
+
+        assert_eq!(stats.ticks, [1, 6]);
+        assert_eq!(stats.players["1"].scores, [3, 0]);
+        assert_eq!(stats.longest_trail(), Some((&"1".to_string(), 3, 1)));
+        assert_eq!(stats.kills.len(), 2);
+        assert_eq!(stats.kills[0].killer.as_deref(), Some("2"));
+        assert_eq!(stats.kills[1].killer, None);
+        assert_eq!(stats.lost_at(&"3".to_string()), Some(6));
+        assert_eq!(stats.lost_at(&"2".to_string()), None);
+    }
+}
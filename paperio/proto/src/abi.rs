@@ -0,0 +1,65 @@
+//! Typed interface of wasm strategies, an alternative to messages over stdio.
+//! The host calls the strategy directly and doesn't wait for it to read its input.
+//!
+//! A module with this interface exports its memory and the functions:
+//!
+//! * `paperio_input_buffer(len: u32) -> u32` returns a pointer to `len` bytes,
+//!   where the host puts the argument of the next call;
+//! * `paperio_on_start(len: u32)` gets [`GameParams`](crate::GameParams);
+//! * `paperio_on_tick(len: u32) -> i32` gets a [`World`](crate::World) and returns a [`Direction`],
+//!   see [`direction_code`].
+//!
+//! Arguments are encoded with bincode, see [`encode`].
+
+use std::io;
+
+use num_traits::FromPrimitive;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Direction;
+
+pub const INPUT_BUFFER: &str = "paperio_input_buffer";
+pub const ON_START: &str = "paperio_on_start";
+pub const ON_TICK: &str = "paperio_on_tick";
+
+pub fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("protocol types are always serializable")
+}
+
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+    bincode::deserialize(data)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+pub fn direction_code(direction: Direction) -> i32 {
+    direction as i32
+}
+
+/// `None` for codes that don't stand for a direction.
+pub fn decode_direction(code: i32) -> Option<Direction> {
+    Direction::from_i32(code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{GameParams, Rules, World};
+
+    #[test]
+    fn roundtrip() {
+        let params = GameParams {
+            x_cells_count: 31,
+            y_cells_count: 31,
+            rules: Rules::default(),
+            encodings: vec![],
+            players: Default::default(),
+        };
+        assert_eq!(decode::<GameParams>(&encode(&params)).unwrap(), params);
+
+        for code in 0..4 {
+            assert_eq!(decode_direction(code).map(direction_code), Some(code));
+        }
+        assert_eq!(decode_direction(4), None);
+        assert!(decode::<World>(&[1, 2, 3]).is_err());
+    }
+}
@@ -1,16 +1,36 @@
+pub mod abi;
+pub mod replay;
 pub mod traits;
+pub mod view;
 
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
 use strum::EnumIter;
+use traits::Encoding;
 
-use std::{collections::HashMap, ops::Add};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Add,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub const MAP_SIZE_CELLS: i32 = 31;
 
+/// Version of the wire protocol, exchanged in the [`Handshake`].
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional features a client may ask for in [`Hello::features`].
+pub mod features {
+    /// Switching the connection encoding with `Command::SetEncoding`.
+    pub const ENCODINGS: &str = "encodings";
+    /// Resuming a dropped connection with `Hello::session_token`.
+    pub const RECONNECT: &str = "reconnect";
+
+    pub const SUPPORTED: [&str; 2] = [ENCODINGS, RECONNECT];
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
@@ -21,15 +41,99 @@ pub enum Message {
     EndGame {},
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+/// Exchange that precedes `Message::StartGame`. A client may send `Hello` right after
+/// connecting and the server answers with `Welcome`. Clients that send nothing are
+/// served as before and get no `Welcome`. Handshake is always in JSON.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(tag = "type", content = "params", rename_all = "snake_case")]
+pub enum Handshake {
+    Hello(Hello),
+    Welcome(Welcome),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Hello {
+    /// Name shown to spectators and written to replays.
+    pub name: String,
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Token from an earlier `Welcome`, sent to take the seat back after a disconnect.
+    /// The server answers with `StartGame` and continues from the current tick.
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Welcome {
+    /// Id assigned to the player, `None` for spectators.
+    pub player_id: Option<PlayerId>,
+    pub protocol_version: u32,
+    /// Requested features that the server supports.
+    pub features: Vec<String>,
+    /// Given to players if the server accepts reconnects.
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct GameParams {
     pub x_cells_count: u32,
     pub y_cells_count: u32,
+    #[serde(default)]
+    pub rules: Rules,
+    /// Encodings the server can switch to, see [`Command::SetEncoding`].
+    #[serde(default)]
+    pub encodings: Vec<Encoding>,
+    /// Names of the players, known from their handshakes.
+    #[serde(default)]
+    pub players: BTreeMap<PlayerId, PlayerInfo>,
+}
+
+/// Rule variant of the game. Default rules are the classic ones.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Rules {
+    /// Points for each captured free cell.
+    pub free_cell_score: u32,
+    /// Points for each captured enemy cell.
+    pub enemy_cell_score: u32,
+    /// Number of ticks after which a player who lost respawns at his initial position.
+    /// Players are eliminated for good if not set.
+    pub respawn_delay: Option<u32>,
+    /// A player who captures this percentage of the field wins immediately.
+    pub territory_win_percent: Option<u32>,
+    pub speed_boost: Option<SpeedBoost>,
+    /// If the lead is shared when the game is over, up to this many extra ticks are played
+    /// and the game ends as soon as a single leader appears.
+    pub sudden_death_ticks: Option<u32>,
+}
+
+/// Capturing a lot of cells at once lets the player move two cells per tick for a while.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct SpeedBoost {
+    /// Minimal number of cells captured at once to get the boost.
+    pub min_captured_cells: u32,
+    /// Number of ticks the boost lasts.
+    pub duration: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            free_cell_score: 1,
+            enemy_cell_score: 5,
+            respawn_delay: None,
+            territory_win_percent: None,
+            speed_boost: None,
+            sudden_death_ticks: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct World {
-    pub players: HashMap<PlayerId, Player>,
+    pub players: BTreeMap<PlayerId, Player>,
     pub tick_num: u32,
 }
 
@@ -43,6 +147,9 @@ pub struct Player {
     pub lines: Vec<Cell>,
     pub direction: Option<Direction>,
     pub has_lost: bool,
+    /// Ticks left of the speed boost, see [`SpeedBoost`].
+    #[serde(default)]
+    pub speed_boost_ticks: u32,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, FromPrimitive, EnumIter)]
@@ -54,13 +161,16 @@ pub enum Direction {
     Left,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Command {
     ChangeDirection(Direction),
     NoOp,
+    /// Switches the connection to one of `GameParams::encodings`. It is sent right before
+    /// a command, which together with all following messages uses the new encoding.
+    SetEncoding(Encoding),
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Cell(pub i32, pub i32);
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -169,9 +279,30 @@ impl Add<Direction> for Cell {
 pub struct GameReplay {
     pub player_infos: HashMap<PlayerId, PlayerInfo>,
     pub messages: Vec<Message>,
+    /// Seed of the recorded game, if it was set.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Inputs of every tick, enough to re-simulate the game.
+    #[serde(default)]
+    pub tick_logs: Vec<TickLog>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct TickLog {
+    pub tick_num: u32,
+    /// Commands received from players in this tick.
+    pub commands: BTreeMap<PlayerId, Command>,
+    /// Players disqualified in this tick.
+    #[serde(default)]
+    pub disqualified: Vec<PlayerId>,
+    /// Players standing still in this tick because they are disconnected.
+    #[serde(default)]
+    pub stopped: Vec<PlayerId>,
+    /// Hash of the game state after the tick.
+    pub state_hash: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct PlayerInfo {
     pub user_name: String,
 }
@@ -200,6 +331,9 @@ mod test {
             Message::StartGame(GameParams {
                 x_cells_count: 345,
                 y_cells_count: 567,
+                rules: Rules::default(),
+                encodings: vec![],
+                players: BTreeMap::new(),
             })
         );
 
@@ -235,6 +369,7 @@ mod test {
                         lines: vec![Cell(1, 0), Cell(1, 1)],
                         direction: Some(Direction::Left),
                         has_lost: true,
+                        speed_boost_ticks: 0,
                     }
                 )]
                 .into_iter()
@@ -247,4 +382,34 @@ mod test {
             serde_json::from_str::<Message>("{\"type\": \"end_game\", \"params\": {}}").unwrap();
         assert_eq!(end_game, Message::EndGame {});
     }
+
+    #[test]
+    fn handshake_test() {
+        let hello = serde_json::from_str::<Handshake>(
+            r#"{
+                "type": "hello",
+                "params": {"name": "bot", "protocol_version": 2}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            hello,
+            Handshake::Hello(Hello {
+                name: "bot".to_string(),
+                protocol_version: 2,
+                features: vec![],
+                session_token: None,
+            })
+        );
+
+        let welcome = Handshake::Welcome(Welcome {
+            player_id: Some("3".to_string()),
+            protocol_version: PROTOCOL_VERSION,
+            features: vec![features::ENCODINGS.to_string()],
+            session_token: Some("abc".to_string()),
+        });
+        let json = serde_json::to_string(&welcome).unwrap();
+        assert_eq!(serde_json::from_str::<Handshake>(&json).unwrap(), welcome);
+    }
 }
@@ -0,0 +1,257 @@
+//! Replays are stored as JSON lines: the first line holds player infos,
+//! every next line is a single [`Message`] of the spectator stream
+//! or a record of the simulation inputs (the seed and [`TickLog`]s).
+//! Files with `.gz` extension are transparently (de)compressed.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{traits::JsonWrite, GameReplay, Message, PlayerId, PlayerInfo, TickLog};
+
+/// Records have the same layout as messages, but never get to players or spectators.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "params", rename_all = "snake_case")]
+enum Record {
+    Seed(u64),
+    TickLog(TickLog),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Message(Message),
+    Record(Record),
+}
+
+/// Compressed output is kept apart, since the end of the archive
+/// is written by [`GzEncoder::finish`] and its errors must not be lost.
+enum Output {
+    Plain(Box<dyn Write + Send>),
+    Compressed(GzEncoder<BufWriter<File>>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Compressed(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Compressed(writer) => writer.flush(),
+        }
+    }
+}
+
+pub struct ReplayWriter {
+    writer: Output,
+}
+
+impl ReplayWriter {
+    pub fn new(
+        writer: impl Write + Send + 'static,
+        player_infos: &HashMap<PlayerId, PlayerInfo>,
+    ) -> io::Result<Self> {
+        Self::with_output(Output::Plain(Box::new(writer)), player_infos)
+    }
+
+    fn with_output(
+        mut writer: Output,
+        player_infos: &HashMap<PlayerId, PlayerInfo>,
+    ) -> io::Result<Self> {
+        serde_json::to_writer(&mut writer, player_infos)?;
+        writer.write_all(b"\n")?;
+        Ok(Self { writer })
+    }
+
+    pub fn create(
+        path: impl AsRef<Path>,
+        player_infos: &HashMap<PlayerId, PlayerInfo>,
+    ) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path.as_ref())?);
+        if is_compressed(path.as_ref()) {
+            let encoder = GzEncoder::new(file, Compression::default());
+            Self::with_output(Output::Compressed(encoder), player_infos)
+        } else {
+            Self::new(file, player_infos)
+        }
+    }
+
+    pub fn write_message(&mut self, message: &Message) -> io::Result<()> {
+        self.writer.write_message(message)
+    }
+
+    pub fn write_seed(&mut self, seed: u64) -> io::Result<()> {
+        self.write_record(&Record::Seed(seed))
+    }
+
+    pub fn write_tick_log(&mut self, tick_log: TickLog) -> io::Result<()> {
+        self.write_record(&Record::TickLog(tick_log))
+    }
+
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.writer {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Compressed(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl GameReplay {
+    pub fn read_from(mut reader: impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let player_infos = serde_json::from_str(&line)?;
+
+        let mut replay = Self {
+            player_infos,
+            messages: vec![],
+            seed: None,
+            tick_logs: vec![],
+        };
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            match serde_json::from_str(&line)? {
+                Line::Message(message) => replay.messages.push(message),
+                Line::Record(Record::Seed(seed)) => replay.seed = Some(seed),
+                Line::Record(Record::TickLog(tick_log)) => replay.tick_logs.push(tick_log),
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = BufReader::new(File::open(path.as_ref())?);
+        if is_compressed(path.as_ref()) {
+            Self::read_from(BufReader::new(GzDecoder::new(file)))
+        } else {
+            Self::read_from(file)
+        }
+    }
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Command, Direction, GameParams, World};
+
+    use std::{
+        fs,
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let player_infos = HashMap::from([(
+            "1".to_string(),
+            PlayerInfo {
+                user_name: "bot".to_string(),
+            },
+        )]);
+        let messages = vec![
+            Message::StartGame(GameParams {
+                x_cells_count: 31,
+                y_cells_count: 31,
+                rules: Default::default(),
+                encodings: vec![],
+                players: Default::default(),
+            }),
+            Message::Tick(World {
+                players: Default::default(),
+                tick_num: 1,
+            }),
+            Message::EndGame {},
+        ];
+
+        let tick_log = TickLog {
+            tick_num: 1,
+            commands: [("1".to_string(), Command::ChangeDirection(Direction::Up))].into(),
+            disqualified: vec![],
+            stopped: vec![],
+            state_hash: 42,
+        };
+
+        let buffer = SharedBuffer::default();
+        let mut writer = ReplayWriter::new(buffer.clone(), &player_infos).unwrap();
+        writer.write_seed(7).unwrap();
+        for message in &messages {
+            writer.write_message(message).unwrap();
+        }
+        writer.write_tick_log(tick_log.clone()).unwrap();
+        writer.finish().unwrap();
+
+        let data = buffer.0.lock().unwrap().clone();
+        let replay = GameReplay::read_from(Cursor::new(data)).unwrap();
+        assert_eq!(replay.player_infos["1"].user_name, "bot");
+        assert_eq!(replay.messages, messages);
+        assert_eq!(replay.seed, Some(7));
+        assert_eq!(replay.tick_logs, vec![tick_log]);
+    }
+
+    #[test]
+    fn compressed_file() {
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl.gz", std::process::id()));
+        let player_infos = HashMap::new();
+        let messages = (1..=100)
+            .map(|tick_num| {
+                Message::Tick(World {
+                    players: Default::default(),
+                    tick_num,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut writer = ReplayWriter::create(&path, &player_infos).unwrap();
+        writer.write_seed(3).unwrap();
+        for message in &messages {
+            writer.write_message(message).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let replay = GameReplay::load(&path);
+        fs::remove_file(&path).unwrap();
+        let replay = replay.unwrap();
+        assert_eq!(replay.messages, messages);
+        assert_eq!(replay.seed, Some(3));
+    }
+}
@@ -1,15 +1,38 @@
-use std::io::{self, BufRead, Write};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, Write},
+};
 
-use crate::{Command, Message};
+use serde::{Deserialize, Serialize};
+
+use crate::{Cell, Command, Direction, GameParams, Handshake, Message, Player, PlayerId, World};
+
+/// A strategy that can be called directly, without a connection in between.
+pub trait Bot {
+    fn on_start(&mut self, _params: GameParams) {}
+    fn on_tick(&mut self, world: World) -> Direction;
+}
+
+impl<B: Bot + ?Sized> Bot for Box<B> {
+    fn on_start(&mut self, params: GameParams) {
+        (**self).on_start(params)
+    }
+
+    fn on_tick(&mut self, world: World) -> Direction {
+        (**self).on_tick(world)
+    }
+}
 
 pub trait JsonRead {
     fn read_message(&mut self) -> io::Result<Message>;
     fn read_command(&mut self) -> io::Result<Command>;
+    fn read_handshake(&mut self) -> io::Result<Handshake>;
 }
 
 pub trait JsonWrite {
     fn write_message(&mut self, message: &Message) -> io::Result<()>;
     fn write_command(&mut self, command: &Command) -> io::Result<()>;
+    fn write_handshake(&mut self, handshake: &Handshake) -> io::Result<()>;
 }
 
 impl<T: BufRead> JsonRead for T {
@@ -24,6 +47,12 @@ impl<T: BufRead> JsonRead for T {
         self.read_line(&mut line)?;
         serde_json::from_str(&line).map_err(|err| err.into())
     }
+
+    fn read_handshake(&mut self) -> io::Result<Handshake> {
+        let mut line = String::new();
+        self.read_line(&mut line)?;
+        serde_json::from_str(&line).map_err(|err| err.into())
+    }
 }
 
 impl<T: Write> JsonWrite for T {
@@ -36,4 +65,329 @@ impl<T: Write> JsonWrite for T {
         serde_json::to_writer(&mut *self, &command)?;
         self.write_all(b"\n")
     }
+
+    fn write_handshake(&mut self, handshake: &Handshake) -> io::Result<()> {
+        serde_json::to_writer(&mut *self, &handshake)?;
+        self.write_all(b"\n")
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// A JSON object per line.
+    #[default]
+    Json,
+    /// Bincode frames, each prefixed with its length as little endian `u32`.
+    Bincode,
+    /// Same as `Bincode`, but ticks only carry changes since the previous tick.
+    BincodeDelta,
+}
+
+pub const SUPPORTED_ENCODINGS: [Encoding; 3] =
+    [Encoding::Json, Encoding::Bincode, Encoding::BincodeDelta];
+
+/// Frames are rejected above this size, so a broken peer can't make us allocate too much.
+const MAX_FRAME_LEN: usize = 64 << 20;
+
+/// Changes of a player since the previous tick. Scalar fields are always sent.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct PlayerDelta {
+    pub score: u32,
+    pub position: Cell,
+    pub direction: Option<Direction>,
+    pub has_lost: bool,
+    pub speed_boost_ticks: u32,
+    pub territory_added: Vec<Cell>,
+    pub territory_removed: Vec<Cell>,
+    /// The trail is ordered, so it is cut to `lines_kept` cells
+    /// and then `lines_added` are appended.
+    pub lines_kept: u32,
+    pub lines_added: Vec<Cell>,
+}
+
+/// Tick relative to the previous one sent over the same connection.
+/// Players missing from the delta are gone from the world.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct WorldDelta {
+    pub tick_num: u32,
+    pub players: BTreeMap<PlayerId, PlayerDelta>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Frame {
+    StartGame(GameParams),
+    Tick(World),
+    TickDelta(WorldDelta),
+    EndGame,
+    Command(Command),
+}
+
+/// Reads and writes the wire protocol in the current encoding. Both sides start
+/// with JSON and switch right after `Command::SetEncoding` is sent.
+#[derive(Default)]
+pub struct Codec {
+    encoding: Encoding,
+    /// Last world sent or received, the base for delta ticks.
+    last_world: Option<World>,
+}
+
+impl Codec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+        self.last_world = None;
+    }
+
+    pub fn write_message(&mut self, writer: &mut impl Write, message: &Message) -> io::Result<()> {
+        let frame = match (self.encoding, message) {
+            (Encoding::Json, _) => return writer.write_message(message),
+            (Encoding::BincodeDelta, Message::Tick(world)) => {
+                let delta = world_delta(self.last_world.as_ref(), world);
+                self.last_world = Some(world.clone());
+                Frame::TickDelta(delta)
+            }
+            (_, Message::StartGame(params)) => Frame::StartGame(params.clone()),
+            (_, Message::Tick(world)) => Frame::Tick(world.clone()),
+            (_, Message::EndGame {}) => Frame::EndGame,
+        };
+        write_frame(writer, &frame)
+    }
+
+    pub fn read_message(&mut self, reader: &mut impl BufRead) -> io::Result<Message> {
+        if self.encoding == Encoding::Json {
+            return reader.read_message();
+        }
+        match read_frame(reader)? {
+            Frame::StartGame(params) => Ok(Message::StartGame(params)),
+            Frame::Tick(world) => Ok(Message::Tick(world)),
+            Frame::TickDelta(delta) => {
+                let world = apply_world_delta(self.last_world.take(), delta);
+                self.last_world = Some(world.clone());
+                Ok(Message::Tick(world))
+            }
+            Frame::EndGame => Ok(Message::EndGame {}),
+            Frame::Command(_) => Err(invalid_data("expected a message, got a command")),
+        }
+    }
+
+    pub fn write_command(&self, writer: &mut impl Write, command: &Command) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Json => writer.write_command(command),
+            Encoding::Bincode | Encoding::BincodeDelta => {
+                write_frame(writer, &Frame::Command(*command))
+            }
+        }
+    }
+
+    pub fn read_command(&self, reader: &mut impl BufRead) -> io::Result<Command> {
+        match self.encoding {
+            Encoding::Json => reader.read_command(),
+            Encoding::Bincode | Encoding::BincodeDelta => match read_frame(reader)? {
+                Frame::Command(command) => Ok(command),
+                _ => Err(invalid_data("expected a command, got a message")),
+            },
+        }
+    }
+}
+
+fn invalid_data(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn write_frame(writer: &mut impl Write, frame: &Frame) -> io::Result<()> {
+    let data = bincode::serialize(frame).map_err(|err| invalid_data(&err.to_string()))?;
+    if data.len() > MAX_FRAME_LEN {
+        return Err(invalid_data("frame is too large"));
+    }
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(&data)
+}
+
+fn read_frame(reader: &mut impl BufRead) -> io::Result<Frame> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data("frame is too large"));
+    }
+
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    bincode::deserialize(&data).map_err(|err| invalid_data(&err.to_string()))
+}
+
+fn cells_diff(old: &[Cell], new: &[Cell]) -> (Vec<Cell>, Vec<Cell>) {
+    let old = old.iter().copied().collect::<BTreeSet<_>>();
+    let new = new.iter().copied().collect::<BTreeSet<_>>();
+    (
+        new.difference(&old).copied().collect(),
+        old.difference(&new).copied().collect(),
+    )
+}
+
+fn lines_diff(old: &[Cell], new: &[Cell]) -> (u32, Vec<Cell>) {
+    let kept = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    (kept as u32, new[kept..].to_vec())
+}
+
+fn apply_lines_diff(lines: &[Cell], kept: u32, added: Vec<Cell>) -> Vec<Cell> {
+    let mut lines = lines[..(kept as usize).min(lines.len())].to_vec();
+    lines.extend(added);
+    lines
+}
+
+fn apply_cells_diff(cells: &[Cell], added: Vec<Cell>, removed: Vec<Cell>) -> Vec<Cell> {
+    let mut cells = cells.iter().copied().collect::<BTreeSet<_>>();
+    for cell in removed {
+        cells.remove(&cell);
+    }
+    cells.extend(added);
+    cells.into_iter().collect()
+}
+
+fn world_delta(old: Option<&World>, new: &World) -> WorldDelta {
+    let players = new
+        .players
+        .iter()
+        .map(|(player_id, player)| {
+            let old_player = old.and_then(|old| old.players.get(player_id));
+            let (old_territory, old_lines) = match old_player {
+                Some(old_player) => (&old_player.territory[..], &old_player.lines[..]),
+                None => (&[][..], &[][..]),
+            };
+            let (territory_added, territory_removed) = cells_diff(old_territory, &player.territory);
+            let (lines_kept, lines_added) = lines_diff(old_lines, &player.lines);
+
+            let delta = PlayerDelta {
+                score: player.score,
+                position: player.position,
+                direction: player.direction,
+                has_lost: player.has_lost,
+                speed_boost_ticks: player.speed_boost_ticks,
+                territory_added,
+                territory_removed,
+                lines_kept,
+                lines_added,
+            };
+            (player_id.clone(), delta)
+        })
+        .collect();
+
+    WorldDelta {
+        tick_num: new.tick_num,
+        players,
+    }
+}
+
+/// Territories of the resulting world are sorted.
+fn apply_world_delta(old: Option<World>, delta: WorldDelta) -> World {
+    let mut old_players = old.map(|old| old.players).unwrap_or_default();
+    let players = delta
+        .players
+        .into_iter()
+        .map(|(player_id, delta)| {
+            let old_player = old_players.remove(&player_id);
+            let (old_territory, old_lines) = match &old_player {
+                Some(old_player) => (&old_player.territory[..], &old_player.lines[..]),
+                None => (&[][..], &[][..]),
+            };
+
+            let player = Player {
+                score: delta.score,
+                territory: apply_cells_diff(
+                    old_territory,
+                    delta.territory_added,
+                    delta.territory_removed,
+                ),
+                position: delta.position,
+                lines: apply_lines_diff(old_lines, delta.lines_kept, delta.lines_added),
+                direction: delta.direction,
+                has_lost: delta.has_lost,
+                speed_boost_ticks: delta.speed_boost_ticks,
+            };
+            (player_id, player)
+        })
+        .collect();
+
+    World {
+        players,
+        tick_num: delta.tick_num,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn world(tick_num: u32, territory: Vec<Cell>, lines: Vec<Cell>) -> World {
+        let player = Player {
+            score: tick_num,
+            territory,
+            position: Cell(1, 1),
+            lines,
+            direction: Some(Direction::Up),
+            has_lost: false,
+            speed_boost_ticks: 0,
+        };
+        World {
+            players: [("1".to_string(), player)].into(),
+            tick_num,
+        }
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        let messages = vec![
+            Message::Tick(world(1, vec![Cell(0, 0), Cell(0, 1)], vec![])),
+            Message::Tick(world(2, vec![Cell(0, 0), Cell(0, 1)], vec![Cell(1, 1)])),
+            Message::Tick(world(3, vec![Cell(0, 1), Cell(1, 1)], vec![])),
+            Message::Tick(world(4, vec![Cell(0, 1)], vec![Cell(2, 2), Cell(2, 1)])),
+            Message::Tick(world(
+                5,
+                vec![Cell(0, 1)],
+                vec![Cell(2, 2), Cell(2, 1), Cell(1, 1), Cell(1, 0)],
+            )),
+            Message::Tick(world(6, vec![Cell(0, 1)], vec![Cell(2, 2), Cell(3, 2)])),
+            Message::EndGame {},
+        ];
+
+        for encoding in SUPPORTED_ENCODINGS {
+            let mut writer = Codec::new();
+            writer.set_encoding(encoding);
+            let mut data = vec![];
+            for message in &messages {
+                writer.write_message(&mut data, message).unwrap();
+            }
+            writer
+                .write_command(&mut data, &Command::ChangeDirection(Direction::Left))
+                .unwrap();
+
+            let mut reader = Codec::new();
+            reader.set_encoding(encoding);
+            let mut data = Cursor::new(data);
+            for message in &messages {
+                assert_eq!(&reader.read_message(&mut data).unwrap(), message);
+            }
+            assert_eq!(
+                reader.read_command(&mut data).unwrap(),
+                Command::ChangeDirection(Direction::Left)
+            );
+        }
+    }
 }
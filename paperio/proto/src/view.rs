@@ -0,0 +1,425 @@
+//! Indexed view of a [`World`] with constant time cell lookups
+//! and the board queries most strategies need.
+
+use std::{
+    collections::VecDeque,
+    ops::{Index, IndexMut},
+};
+
+use crate::{Cell, Direction, PlayerId, Rules, World, MAP_SIZE_CELLS};
+
+const SIDE: usize = MAP_SIZE_CELLS as usize;
+
+/// A value for every cell of the field.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Grid<T> {
+    cells: Vec<T>,
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            cells: vec![value; SIDE * SIDE],
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    fn offset(Cell(x, y): Cell) -> Option<usize> {
+        Cell(x, y)
+            .in_bounds()
+            .then(|| y as usize * SIDE + x as usize)
+    }
+
+    /// `None` for cells outside the field.
+    pub fn get(&self, cell: Cell) -> Option<&T> {
+        Self::offset(cell).map(|offset| &self.cells[offset])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Cell, &T)> {
+        self.cells
+            .iter()
+            .enumerate()
+            .map(|(offset, value)| (Cell((offset % SIDE) as i32, (offset / SIDE) as i32), value))
+    }
+}
+
+impl<T> Index<Cell> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, cell: Cell) -> &T {
+        let offset = Self::offset(cell).unwrap_or_else(|| panic!("{cell:?} is out of the field"));
+        &self.cells[offset]
+    }
+}
+
+impl<T> IndexMut<Cell> for Grid<T> {
+    fn index_mut(&mut self, cell: Cell) -> &mut T {
+        let offset = Self::offset(cell).unwrap_or_else(|| panic!("{cell:?} is out of the field"));
+        &mut self.cells[offset]
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Number of steps to reach a cell, `None` if it can't be reached.
+pub type DistanceMap = Grid<Option<u32>>;
+
+/// Players that have lost are left out of the view.
+pub struct WorldView<'a> {
+    world: &'a World,
+    /// Ids of players in the view, grids refer to players by index in this list.
+    ids: Vec<&'a PlayerId>,
+    owners: Grid<Option<usize>>,
+    traces: Grid<Option<usize>>,
+}
+
+impl<'a> WorldView<'a> {
+    pub fn new(world: &'a World) -> Self {
+        let mut owners = Grid::new(None);
+        let mut traces = Grid::new(None);
+        let mut ids = vec![];
+        for (id, player) in world.players.iter().filter(|(_, p)| !p.has_lost) {
+            let index = ids.len();
+            ids.push(id);
+            for &cell in &player.territory {
+                owners[cell] = Some(index);
+            }
+            for &cell in &player.lines {
+                traces[cell] = Some(index);
+            }
+        }
+
+        Self {
+            world,
+            ids,
+            owners,
+            traces,
+        }
+    }
+
+    pub fn world(&self) -> &'a World {
+        self.world
+    }
+
+    /// Player who owns the cell, if any.
+    pub fn owner(&self, cell: Cell) -> Option<&'a PlayerId> {
+        self.owners
+            .get(cell)
+            .copied()
+            .flatten()
+            .map(|i| self.ids[i])
+    }
+
+    /// Player whose trace goes through the cell, if any.
+    pub fn trace_owner(&self, cell: Cell) -> Option<&'a PlayerId> {
+        self.traces
+            .get(cell)
+            .copied()
+            .flatten()
+            .map(|i| self.ids[i])
+    }
+
+    pub fn is_mine(&self, cell: Cell) -> bool {
+        self.owner(cell).is_some_and(|id| id == "i")
+    }
+
+    pub fn is_my_trace(&self, cell: Cell) -> bool {
+        self.trace_owner(cell).is_some_and(|id| id == "i")
+    }
+
+    /// Distances from `start` for the given player, who can't step on its own trace.
+    pub fn distances(&self, start: Cell, player_id: &PlayerId) -> DistanceMap {
+        self.bfs(start, None, self.index_of(player_id))
+    }
+
+    /// Distances from the head of the given player. Its first step
+    /// can't go back, same as in the game.
+    pub fn distances_from_head(&self, player_id: &PlayerId) -> DistanceMap {
+        let player = &self.world.players[player_id];
+        let forbidden = player.direction.map(Direction::opposite);
+        self.bfs(player.position, forbidden, self.index_of(player_id))
+    }
+
+    /// Earliest number of steps in which any enemy can reach each cell.
+    /// Speed boosts are not taken into account.
+    pub fn enemy_reach_times(&self) -> DistanceMap {
+        let mut reach_times = Grid::new(None);
+        for (id, _) in self.world.iter_enemies().filter(|(_, p)| !p.has_lost) {
+            for (cell, &distance) in self.distances_from_head(id).iter() {
+                let time: &mut Option<u32> = &mut reach_times[cell];
+                if let Some(distance) = distance {
+                    *time = Some(time.map_or(distance, |time| time.min(distance)));
+                }
+            }
+        }
+        reach_times
+    }
+
+    /// Cells that become mine if I go along `path` and it ends on my territory:
+    /// my trace, the path itself and everything they enclose together with my territory.
+    pub fn capture_area(&self, path: &[Cell]) -> Vec<Cell> {
+        self.capture_area_of(&"i".to_string(), path)
+    }
+
+    /// Same as [`Self::capture_area`], for any player.
+    pub fn capture_area_of(&self, player_id: &PlayerId, path: &[Cell]) -> Vec<Cell> {
+        let mut walls = Grid::new(false);
+        let mut captured = vec![];
+        let player = &self.world.players[player_id];
+        for &cell in player.territory.iter() {
+            walls[cell] = true;
+        }
+        for &cell in player.lines.iter().chain(path) {
+            if cell.in_bounds() && !walls[cell] {
+                walls[cell] = true;
+                captured.push(cell);
+            }
+        }
+
+        // Everything reachable from the border without crossing walls stays outside.
+        let mut outside = Grid::new(false);
+        let mut queue = self
+            .world
+            .iter_cells()
+            .filter(|&Cell(x, y)| x == 0 || y == 0 || x == SIDE as i32 - 1 || y == SIDE as i32 - 1)
+            .filter(|&cell| !walls[cell])
+            .collect::<VecDeque<_>>();
+        for &cell in &queue {
+            outside[cell] = true;
+        }
+        while let Some(cell) = queue.pop_front() {
+            for next in cell.iter_neighbors() {
+                if !walls[next] && !outside[next] {
+                    outside[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        captured.extend(
+            self.world
+                .iter_cells()
+                .filter(|&cell| !walls[cell] && !outside[cell]),
+        );
+        captured
+    }
+
+    /// Shortest path from the head of the given player to its territory,
+    /// at least one step long, that doesn't go through blocked cells
+    /// or turn back at once.
+    pub fn path_home(&self, player_id: &PlayerId, blocked: &Grid<bool>) -> Option<Vec<Cell>> {
+        let player = &self.world.players[player_id];
+        self.path_home_from(player_id, player.position, player.direction, blocked)
+    }
+
+    /// Same as [`Self::path_home`], as if the player were at `from`
+    /// and last moved in `direction`.
+    pub fn path_home_from(
+        &self,
+        player_id: &PlayerId,
+        from: Cell,
+        direction: Option<Direction>,
+        blocked: &Grid<bool>,
+    ) -> Option<Vec<Cell>> {
+        let back = direction.map(|direction| from + direction.opposite());
+        let mut previous = Grid::<Option<Cell>>::new(None);
+        previous[from] = Some(from);
+        let mut queue = VecDeque::from([from]);
+        while let Some(cell) = queue.pop_front() {
+            if cell != from && self.owner(cell) == Some(player_id) {
+                let mut path = vec![];
+                let mut cell = cell;
+                while cell != from {
+                    path.push(cell);
+                    cell = previous[cell].unwrap();
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in cell.iter_neighbors() {
+                if previous[next].is_none()
+                    && !blocked[next]
+                    && !(cell == from && Some(next) == back)
+                {
+                    previous[next] = Some(cell);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Points for capturing the cells under the given rules.
+    pub fn capture_score(&self, cells: &[Cell], rules: &Rules) -> u32 {
+        cells
+            .iter()
+            .filter(|&&cell| !self.is_mine(cell))
+            .map(|&cell| match self.owner(cell) {
+                Some(_) => rules.enemy_cell_score,
+                None => rules.free_cell_score,
+            })
+            .sum()
+    }
+
+    fn index_of(&self, player_id: &PlayerId) -> Option<usize> {
+        self.ids.iter().position(|&id| id == player_id)
+    }
+
+    fn bfs(&self, start: Cell, forbidden: Option<Direction>, walker: Option<usize>) -> DistanceMap {
+        let mut distances = Grid::new(None);
+        if !start.in_bounds() {
+            return distances;
+        }
+        let is_blocked = |cell: Cell| walker.is_some() && self.traces[cell] == walker;
+
+        distances[start] = Some(0);
+        let mut queue = VecDeque::from([start]);
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[cell].unwrap();
+            for direction in [
+                Direction::Up,
+                Direction::Right,
+                Direction::Down,
+                Direction::Left,
+            ] {
+                if cell == start && forbidden == Some(direction) {
+                    continue;
+                }
+                let Some(next) = cell.adjacent(direction) else {
+                    continue;
+                };
+                if distances[next].is_none() && !is_blocked(next) {
+                    distances[next] = Some(distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Player;
+
+    fn player(
+        position: Cell,
+        direction: Direction,
+        territory: Vec<Cell>,
+        lines: Vec<Cell>,
+    ) -> Player {
+        Player {
+            score: 0,
+            territory,
+            position,
+            lines,
+            direction: Some(direction),
+            has_lost: false,
+            speed_boost_ticks: 0,
+        }
+    }
+
+    fn square(Cell(x, y): Cell, side: i32) -> Vec<Cell> {
+        (x..x + side)
+            .flat_map(|x| (y..y + side).map(move |y| Cell(x, y)))
+            .collect()
+    }
+
+    /// I own the 3x3 square at the corner and went out for a walk above it.
+    fn world() -> World {
+        let me = player(
+            Cell(3, 4),
+            Direction::Right,
+            square(Cell(0, 0), 3),
+            vec![Cell(1, 3), Cell(1, 4), Cell(2, 4), Cell(3, 4)],
+        );
+        let enemy = player(Cell(10, 4), Direction::Left, square(Cell(9, 0), 3), vec![]);
+        World {
+            players: [("i".to_string(), me), ("2".to_string(), enemy)].into(),
+            tick_num: 1,
+        }
+    }
+
+    #[test]
+    fn lookups() {
+        let world = world();
+        let view = WorldView::new(&world);
+        assert!(view.is_mine(Cell(0, 0)));
+        assert!(view.is_my_trace(Cell(2, 4)));
+        assert_eq!(view.owner(Cell(10, 1)).map(String::as_str), Some("2"));
+        assert_eq!(view.owner(Cell(5, 5)), None);
+        assert_eq!(view.owner(Cell(-1, 5)), None);
+        assert_eq!(view.trace_owner(Cell(0, 0)), None);
+    }
+
+    #[test]
+    fn distances_avoid_own_trace() {
+        let world = world();
+        let view = WorldView::new(&world);
+
+        let me = "i".to_string();
+        let distances = view.distances(Cell(3, 3), &me);
+        assert_eq!(distances[Cell(3, 3)], Some(0));
+        assert_eq!(distances[Cell(2, 3)], Some(1));
+        assert_eq!(distances[Cell(3, 4)], None);
+        // Going around the trace instead of through it.
+        assert_eq!(distances[Cell(2, 5)], Some(5));
+
+        // The enemy looks left and can't turn back right at once.
+        let reach_times = view.enemy_reach_times();
+        assert_eq!(reach_times[Cell(9, 4)], Some(1));
+        assert_eq!(reach_times[Cell(11, 4)], Some(3));
+        assert_eq!(reach_times[Cell(3, 4)], Some(7));
+    }
+
+    #[test]
+    fn capture_area() {
+        let world = world();
+        let view = WorldView::new(&world);
+
+        // Closing the loop through (3, 3) and (3, 2) captures the trace, the path
+        // and the enclosed cell (2, 3).
+        let path = [Cell(3, 3), Cell(3, 2)];
+        let mut area = view.capture_area(&path);
+        area.sort();
+        let mut expected = vec![
+            Cell(1, 3),
+            Cell(1, 4),
+            Cell(2, 3),
+            Cell(2, 4),
+            Cell(3, 2),
+            Cell(3, 3),
+            Cell(3, 4),
+        ];
+        expected.sort();
+        assert_eq!(area, expected);
+        assert_eq!(view.capture_score(&area, &Rules::default()), 7);
+    }
+
+    #[test]
+    fn path_home() {
+        let world = world();
+        let view = WorldView::new(&world);
+
+        let me = "i".to_string();
+        let mut trace = Grid::new(false);
+        for &cell in &world.players[&me].lines {
+            trace[cell] = true;
+        }
+        let path = view.path_home(&me, &trace).unwrap();
+        assert_eq!(path.len(), 3);
+        assert!(view.is_mine(*path.last().unwrap()));
+
+        // Can't turn back to the territory right below, so goes around.
+        let path = view.path_home_from(&me, Cell(2, 3), Some(Direction::Up), &trace);
+        assert_eq!(path, Some(vec![Cell(3, 3), Cell(3, 2), Cell(2, 2)]));
+
+        let enemy = "2".to_string();
+        let path = view.path_home(&enemy, &Grid::new(false)).unwrap();
+        assert_eq!(path, vec![Cell(10, 3), Cell(10, 2)]);
+    }
+}
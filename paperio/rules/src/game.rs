@@ -0,0 +1,1142 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    f64::consts::{FRAC_PI_4, TAU},
+    num::NonZero,
+};
+
+use paperio_proto::{self, Cell, Direction, GameParams, Rules, World};
+
+use crate::{game_field::GameField, player_vec::PlayerIndexedVector, random::SplitMix64};
+
+const INIT_POS: [Cell; 4] = [Cell(9, 21), Cell(21, 21), Cell(21, 9), Cell(9, 9)];
+const X_CELLS_COUNT: u32 = 31;
+const Y_CELLS_COUNT: u32 = 31;
+const SPAWN_RING_RADIUS: f64 = 12.;
+
+pub const MAX_PLAYER_COUNT: usize = 12;
+
+pub type PlayerId = NonZero<usize>;
+
+#[derive(Clone)]
+struct Player {
+    score: u32,
+    position: Cell,
+    direction: Direction,
+    spawn_position: Cell,
+    boost_ticks: u32,
+    respawn_tick: Option<u32>,
+}
+
+impl Player {
+    fn new(position: Cell) -> Self {
+        Player {
+            score: 0,
+            position,
+            direction: Direction::Left,
+            spawn_position: position,
+            boost_ticks: 0,
+            respawn_tick: None,
+        }
+    }
+}
+
+/// Cloning is cheap enough to branch a game for a search.
+#[derive(Clone)]
+pub struct Game {
+    tick: u32,
+    players: PlayerIndexedVector<Player>,
+    has_lost: PlayerIndexedVector<bool>,
+    stopped: PlayerIndexedVector<bool>,
+    params: GameParams,
+    field: GameField,
+}
+
+/// Up to four players spawn at the classic positions. Larger games place
+/// players evenly on a ring around the center of the field, starting from the
+/// top left corner and going clockwise, same as the classic positions.
+fn spawn_positions(player_count: usize) -> Vec<Cell> {
+    if player_count <= INIT_POS.len() {
+        return INIT_POS[..player_count].to_vec();
+    }
+
+    let center_x = (X_CELLS_COUNT / 2) as f64;
+    let center_y = (Y_CELLS_COUNT / 2) as f64;
+    (0..player_count)
+        .map(|i| {
+            let angle = 3. * FRAC_PI_4 - TAU * i as f64 / player_count as f64;
+            Cell(
+                (center_x + SPAWN_RING_RADIUS * angle.cos()).round() as i32,
+                (center_y + SPAWN_RING_RADIUS * angle.sin()).round() as i32,
+            )
+        })
+        .collect()
+}
+
+/// Cells of the 3x3 square around `center`, which a player gets when it spawns.
+fn square(Cell(x, y): Cell) -> impl Iterator<Item = Cell> {
+    (x - 1..=x + 1).flat_map(move |x| (y - 1..=y + 1).map(move |y| Cell(x, y)))
+}
+
+/// Fisher-Yates shuffle driven by SplitMix64, so that the result depends only on the seed.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = SplitMix64::new(seed);
+    for i in (1..items.len()).rev() {
+        items.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+    }
+}
+
+/// Maps ids of a [`World`] to the ids of the players on the server. Other
+/// players are listed by their server ids, and `"i"` gets the only one missing.
+/// Ids that don't fit this pattern take the free ids in order.
+pub fn world_player_ids(world: &World) -> BTreeMap<String, PlayerId> {
+    let player_count = world.players.len();
+    let mut ids = BTreeMap::new();
+    for id in world.players.keys() {
+        let player_id = id
+            .parse()
+            .ok()
+            .filter(|&index| (1..=player_count).contains(&index))
+            .and_then(PlayerId::new);
+        if let Some(player_id) = player_id {
+            ids.insert(id.clone(), player_id);
+        }
+    }
+
+    let mut free_ids = (1..=player_count)
+        .filter_map(PlayerId::new)
+        .filter(|player_id| !ids.values().any(|id| id == player_id))
+        .collect::<Vec<_>>()
+        .into_iter();
+    for id in world.players.keys() {
+        if !ids.contains_key(id) {
+            ids.insert(id.clone(), free_ids.next().unwrap());
+        }
+    }
+    ids
+}
+
+/// FNV-1a. Unlike `DefaultHasher`, it is guaranteed to stay the same across Rust versions.
+struct StateHasher(u64);
+
+impl StateHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, value: u64) {
+        for byte in value.to_le_bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_cell(&mut self, Cell(x, y): Cell) {
+        self.write(x as u64);
+        self.write(y as u64);
+    }
+}
+
+impl Game {
+    /// Players take spawn positions in order of their ids.
+    pub fn new(player_count: usize, rules: Rules) -> Self {
+        Self::with_spawn_positions(spawn_positions(player_count), rules)
+    }
+
+    /// Spawn positions are assigned to players in an order derived from the seed.
+    pub fn with_seed(player_count: usize, rules: Rules, seed: u64) -> Self {
+        let mut positions = spawn_positions(player_count);
+        shuffle(&mut positions, seed);
+        Self::with_spawn_positions(positions, rules)
+    }
+
+    fn with_spawn_positions(positions: Vec<Cell>, rules: Rules) -> Self {
+        let player_count = positions.len();
+        assert!(
+            (1..=MAX_PLAYER_COUNT).contains(&player_count),
+            "player count should be from 1 to {MAX_PLAYER_COUNT}"
+        );
+
+        let params = GameParams {
+            x_cells_count: X_CELLS_COUNT,
+            y_cells_count: Y_CELLS_COUNT,
+            rules,
+            encodings: vec![],
+            players: BTreeMap::new(),
+        };
+        let mut field = GameField::new(
+            params.x_cells_count as usize,
+            params.y_cells_count as usize,
+            player_count,
+        );
+        let players: PlayerIndexedVector<Player> = positions.into_iter().map(Player::new).collect();
+
+        for (player_id, player) in players.iter() {
+            field.init_player(player_id, player.position);
+        }
+        let has_lost = PlayerIndexedVector::new(player_count);
+        let stopped = PlayerIndexedVector::new(player_count);
+
+        Game {
+            tick: 1,
+            players,
+            has_lost,
+            stopped,
+            params,
+            field,
+        }
+    }
+
+    /// Rebuilds the game from what a player sees. Players get the same ids
+    /// as on the server, see [`world_player_ids`]. The world doesn't tell when
+    /// lost players respawn, so they don't.
+    pub fn from_world(world: &World, rules: Rules) -> Self {
+        let ids = world_player_ids(world);
+        let mut game = Self::new(ids.len(), rules);
+        game.tick = world.tick_num;
+        game.field = GameField::new(X_CELLS_COUNT as usize, Y_CELLS_COUNT as usize, ids.len());
+        for (id, proto_player) in &world.players {
+            let player_id = ids[id];
+            let player = &mut game.players[player_id];
+            player.score = proto_player.score;
+            player.position = proto_player.position;
+            player.direction = proto_player.direction.unwrap_or(Direction::Left);
+            player.boost_ticks = proto_player.speed_boost_ticks;
+            game.has_lost[player_id] = proto_player.has_lost;
+
+            for &cell in &proto_player.territory {
+                game.field.set_captured(cell, player_id);
+            }
+            for &cell in &proto_player.lines {
+                game.field.set_trace(cell, player_id);
+            }
+        }
+        game
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn player_ids(&self) -> impl Iterator<Item = PlayerId> {
+        self.players.iter_player_ids()
+    }
+
+    pub fn player_position(&self, player_id: PlayerId) -> Cell {
+        self.players[player_id].position
+    }
+
+    pub fn player_direction(&self, player_id: PlayerId) -> Direction {
+        self.players[player_id].direction
+    }
+
+    pub fn player_score(&self, player_id: PlayerId) -> u32 {
+        self.players[player_id].score
+    }
+
+    /// Territory and trace of the player.
+    pub fn player_cells(&self, player_id: PlayerId) -> (&BTreeSet<Cell>, &BTreeSet<Cell>) {
+        self.field.get_for_player(player_id)
+    }
+
+    pub fn cell_owner(&self, cell: Cell) -> Option<PlayerId> {
+        self.field[cell].owner()
+    }
+
+    /// Player whose trace goes through the cell.
+    pub fn cell_trace(&self, cell: Cell) -> Option<PlayerId> {
+        self.field[cell].is_traced()
+    }
+
+    /// Points the player would get for closing its trace now.
+    pub fn pending_capture_score(&self, player_id: PlayerId) -> u32 {
+        let rules = &self.params.rules;
+        self.field
+            .pending_capture(player_id)
+            .into_iter()
+            .map(|cell| match self.field[cell].owner() {
+                Some(owner) if owner == player_id => 0,
+                Some(_) => rules.enemy_cell_score,
+                None => rules.free_cell_score,
+            })
+            .sum()
+    }
+
+    pub fn has_lost(&self, i: PlayerId) -> bool {
+        self.has_lost[i]
+    }
+
+    pub fn tick_num(&self) -> u32 {
+        self.tick
+    }
+
+    /// Hash of the full game state. Two games with equal hashes
+    /// continue identically given the same commands.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        hasher.write(self.tick as u64);
+        for (player_id, player) in self.players.iter() {
+            hasher.write(player.score as u64);
+            hasher.write_cell(player.position);
+            hasher.write(player.direction as u64);
+            hasher.write(self.has_lost[player_id] as u64);
+            hasher.write(self.stopped[player_id] as u64);
+            hasher.write(player.boost_ticks as u64);
+            hasher.write(player.respawn_tick.map_or(u64::MAX, u64::from));
+
+            let (territory, lines) = self.field.get_for_player(player_id);
+            for cells in [territory, lines] {
+                hasher.write(cells.len() as u64);
+                for &cell in cells {
+                    hasher.write_cell(cell);
+                }
+            }
+        }
+        hasher.0
+    }
+
+    /// Removes the player from the game for good, e.g. for exceeding its time budget.
+    pub fn disqualify(&mut self, player_id: PlayerId) {
+        self.eliminate(player_id);
+        self.players[player_id].respawn_tick = None;
+    }
+
+    /// A stopped player stands still, e.g. while it is disconnected.
+    pub fn set_stopped(&mut self, player_id: PlayerId, stopped: bool) {
+        self.stopped[player_id] = stopped;
+    }
+
+    fn eliminate(&mut self, player_id: PlayerId) {
+        if self.has_lost[player_id] {
+            return;
+        }
+        self.field.remove_player(player_id);
+        self.has_lost[player_id] = true;
+
+        let player = &mut self.players[player_id];
+        player.boost_ticks = 0;
+        player.respawn_tick = self
+            .params
+            .rules
+            .respawn_delay
+            .map(|delay| self.tick + delay);
+    }
+
+    pub fn get_game_params(&self) -> GameParams {
+        self.params.clone()
+    }
+
+    pub fn try_change_direction(&mut self, player_id: PlayerId, new_direction: Direction) -> bool {
+        let direction = &mut self.players[player_id].direction;
+        if new_direction == direction.opposite() {
+            return false;
+        }
+        *direction = new_direction;
+        true
+    }
+
+    pub fn get_player_scores(&self) -> PlayerIndexedVector<u32> {
+        self.players.iter().map(|(_, p)| p.score).collect()
+    }
+
+    /// Turns the given players and advances the game by one tick, same as
+    /// the server does with their commands.
+    pub fn tick_with(&mut self, directions: impl IntoIterator<Item = (PlayerId, Direction)>) {
+        for (player_id, direction) in directions {
+            self.try_change_direction(player_id, direction);
+        }
+        self.tick();
+    }
+
+    /// Advances the game by one tick. Boosted players make a second step
+    /// after everyone has made the first one.
+    pub fn tick(&mut self) {
+        let mut boosted = PlayerIndexedVector::new(self.players.len());
+        for (player_id, player) in self.players.iter_mut() {
+            if player.boost_ticks > 0 {
+                player.boost_ticks -= 1;
+                boosted[player_id] = true;
+            }
+        }
+
+        let can_move = self
+            .has_lost
+            .iter()
+            .map(|(player_id, &has_lost)| !has_lost && !self.stopped[player_id])
+            .collect::<PlayerIndexedVector<_>>();
+        self.step(&can_move);
+        if boosted.iter().any(|(_, &is_boosted)| is_boosted) {
+            let moving = can_move
+                .iter()
+                .map(|(player_id, &can_move)| {
+                    can_move && !self.has_lost[player_id] && boosted[player_id]
+                })
+                .collect();
+            self.step(&moving);
+        }
+
+        self.respawn_players();
+        self.tick += 1;
+    }
+
+    /// Moves the given players by one cell. The rules are applied in phases,
+    /// and each phase only considers players that haven't lost in the previous ones:
+    ///
+    /// 1. Players leaving the field lose.
+    /// 2. Head to head collisions are resolved.
+    /// 3. Players stepping into their territory capture the enclosed area.
+    /// 4. Trace crossings are resolved.
+    /// 5. Remaining players move, losers are removed from the field.
+    ///
+    /// Players that don't move this step can still lose, e.g. when their trace is crossed.
+    fn step(&mut self, moving: &PlayerIndexedVector<bool>) {
+        let mut next_position = self
+            .players
+            .iter()
+            .map(|(player_id, player)| {
+                if moving[player_id] {
+                    player.position + player.direction
+                } else {
+                    player.position
+                }
+            })
+            .collect();
+        let mut loses_in_this_tick = PlayerIndexedVector::new(self.players.len());
+
+        self.resolve_border_exits(moving, &mut next_position, &mut loses_in_this_tick);
+        self.resolve_head_to_head(moving, &next_position, &mut loses_in_this_tick);
+        self.resolve_captures(moving, &next_position, &mut loses_in_this_tick);
+        self.resolve_trace_crossings(moving, &next_position, &mut loses_in_this_tick);
+        self.move_players(moving, &next_position, &loses_in_this_tick);
+
+        for player_id in self.players.iter_player_ids() {
+            if loses_in_this_tick[player_id] {
+                self.eliminate(player_id);
+            }
+        }
+    }
+
+    /// A player respawns at its spawn position if the 3x3 square around it is free,
+    /// otherwise in the closest free square. If the field has no free square, it
+    /// respawns at its spawn position anyway: players with traces or heads there
+    /// lose, and the territory there is taken from its owners.
+    fn respawn_players(&mut self) {
+        for player_id in self.players.iter_player_ids() {
+            if self.players[player_id]
+                .respawn_tick
+                .is_none_or(|tick| tick > self.tick)
+            {
+                continue;
+            }
+
+            let spawn_position = self.players[player_id].spawn_position;
+            let position = match self.free_spawn_position(spawn_position) {
+                Some(position) => position,
+                None => {
+                    for victim in self.players_in_square(spawn_position) {
+                        self.eliminate(victim);
+                    }
+                    spawn_position
+                }
+            };
+
+            let player = &mut self.players[player_id];
+            player.respawn_tick = None;
+            player.position = position;
+            player.direction = Direction::Left;
+            self.field.init_player(player_id, position);
+            self.has_lost[player_id] = false;
+        }
+    }
+
+    /// Center of the free 3x3 square closest to `spawn_position`. A square is free
+    /// if nobody owns, traces or stands on its cells.
+    fn free_spawn_position(&self, spawn_position: Cell) -> Option<Cell> {
+        let is_free = |center: Cell| {
+            square(center).all(|cell| {
+                cell.in_bounds()
+                    && self.field[cell].owner().is_none()
+                    && self.field[cell].is_traced().is_none()
+            }) && self.players_in_square(center).is_empty()
+        };
+        (0..X_CELLS_COUNT as i32)
+            .flat_map(|x| (0..Y_CELLS_COUNT as i32).map(move |y| Cell(x, y)))
+            .filter(|&center| is_free(center))
+            .min_by_key(|&center| (center.distance_to(spawn_position), center.1, center.0))
+    }
+
+    /// Players in the game whose traces or heads are in the 3x3 square around `center`.
+    fn players_in_square(&self, center: Cell) -> Vec<PlayerId> {
+        self.players
+            .iter()
+            .filter(|&(player_id, player)| {
+                !self.has_lost[player_id]
+                    && square(center).any(|cell| {
+                        player.position == cell
+                            || cell.in_bounds() && self.field[cell].is_traced_by(player_id)
+                    })
+            })
+            .map(|(player_id, _)| player_id)
+            .collect()
+    }
+
+    fn is_moving(
+        player_id: PlayerId,
+        moving: &PlayerIndexedVector<bool>,
+        loses_in_this_tick: &PlayerIndexedVector<bool>,
+    ) -> bool {
+        moving[player_id] && !loses_in_this_tick[player_id]
+    }
+
+    /// A player stepping out of the field loses and stays in place,
+    /// so that later phases never look outside the field.
+    fn resolve_border_exits(
+        &self,
+        moving: &PlayerIndexedVector<bool>,
+        next_position: &mut PlayerIndexedVector<Cell>,
+        loses_in_this_tick: &mut PlayerIndexedVector<bool>,
+    ) {
+        for (player_id, next_position) in next_position.iter_mut() {
+            if moving[player_id] && !next_position.in_bounds() {
+                *next_position = self.players[player_id].position;
+                loses_in_this_tick[player_id] = true;
+            }
+        }
+    }
+
+    /// If two or more players step into the same cell and one of them owns it, the owner wins.
+    /// Otherwise, the player with the shortest trace wins.
+    /// If multiple players have the shortest trace, all of them lose.
+    fn resolve_head_to_head(
+        &self,
+        moving: &PlayerIndexedVector<bool>,
+        next_position: &PlayerIndexedVector<Cell>,
+        loses_in_this_tick: &mut PlayerIndexedVector<bool>,
+    ) {
+        let mut cell_to_contenders = BTreeMap::<Cell, Vec<PlayerId>>::new();
+        for (player_id, &next_position) in next_position.iter() {
+            if Self::is_moving(player_id, moving, loses_in_this_tick) {
+                cell_to_contenders
+                    .entry(next_position)
+                    .or_default()
+                    .push(player_id);
+            }
+        }
+
+        for (&pos, players) in cell_to_contenders.iter() {
+            if players.len() <= 1 {
+                continue;
+            }
+
+            let mut cell_owner = None;
+            let mut shortest_path = usize::MAX;
+            let mut player_with_shortest_path = players[0];
+            let mut multiple_shortest = false;
+            for &player_id in players {
+                if self.field[pos].is_captured_by(player_id) {
+                    cell_owner = Some(player_id);
+                }
+
+                let player_path_len = self.field.traced_cells(player_id).len();
+                match player_path_len.cmp(&shortest_path) {
+                    Ordering::Less => {
+                        multiple_shortest = false;
+                        shortest_path = player_path_len;
+                        player_with_shortest_path = player_id;
+                    }
+                    Ordering::Equal => {
+                        multiple_shortest = true;
+                    }
+                    Ordering::Greater => {}
+                }
+            }
+
+            let winner = cell_owner.or(if multiple_shortest {
+                None
+            } else {
+                Some(player_with_shortest_path)
+            });
+
+            for &player_id in players {
+                if winner != Some(player_id) {
+                    loses_in_this_tick[player_id] = true
+                }
+            }
+        }
+    }
+
+    /// A player stepping into his territory captures his trace and all cells enclosed by it.
+    /// Each free cell gives 1 point, each enemy cell gives 5 points.
+    /// Enemies standing on captured cells or having their trace there lose.
+    fn resolve_captures(
+        &mut self,
+        moving: &PlayerIndexedVector<bool>,
+        next_position: &PlayerIndexedVector<Cell>,
+        loses_in_this_tick: &mut PlayerIndexedVector<bool>,
+    ) {
+        let player_positions = self.players.map(|p| p.position);
+        for player_id in self.players.iter_player_ids() {
+            if !Self::is_moving(player_id, moving, loses_in_this_tick)
+                || !self.field[next_position[player_id]].is_captured_by(player_id)
+            {
+                continue;
+            }
+
+            let (enemy_cells_captured, free_cells_captured, enemies_captured) =
+                self.field.capture_all(player_id, &player_positions);
+            let rules = &self.params.rules;
+            let player = &mut self.players[player_id];
+            player.score += enemy_cells_captured * rules.enemy_cell_score
+                + free_cells_captured * rules.free_cell_score;
+            if let Some(boost) = rules.speed_boost {
+                if enemy_cells_captured + free_cells_captured >= boost.min_captured_cells {
+                    player.boost_ticks = boost.duration;
+                }
+            }
+
+            for &enemy_id in &enemies_captured {
+                loses_in_this_tick[enemy_id] = true;
+            }
+        }
+    }
+
+    /// A player stepping on his own trace loses. A player stepping on someone else's trace
+    /// cuts it and its owner loses. If two players cut each other's traces at the same time,
+    /// the shortest trace wins, and both of them lose on a tie.
+    ///
+    /// All crossings happen simultaneously, so a player who loses in this phase
+    /// still cuts the trace he steps on.
+    fn resolve_trace_crossings(
+        &self,
+        moving: &PlayerIndexedVector<bool>,
+        next_position: &PlayerIndexedVector<Cell>,
+        loses_in_this_tick: &mut PlayerIndexedVector<bool>,
+    ) {
+        let mut crossed = PlayerIndexedVector::<bool>::new(self.players.len());
+        for my_id in self.players.iter_player_ids() {
+            if !Self::is_moving(my_id, moving, loses_in_this_tick) {
+                continue;
+            }
+            let Some(other_id) = self.field[next_position[my_id]].is_traced() else {
+                continue;
+            };
+
+            if other_id == my_id {
+                crossed[my_id] = true;
+            } else if self.field[next_position[other_id]].is_traced_by(my_id)
+                && Self::is_moving(other_id, moving, loses_in_this_tick)
+            {
+                // We cut each other, the other player is handled symmetrically.
+                let my_trace_len = self.field.traced_cells(my_id).len();
+                let other_trace_len = self.field.traced_cells(other_id).len();
+                if my_trace_len >= other_trace_len {
+                    crossed[my_id] = true;
+                }
+            } else {
+                crossed[other_id] = true;
+            }
+        }
+
+        for (player_id, &is_crossed) in crossed.iter() {
+            if is_crossed {
+                loses_in_this_tick[player_id] = true;
+            }
+        }
+    }
+
+    /// Players that haven't lost move, leaving a trace outside of their territory.
+    fn move_players(
+        &mut self,
+        moving: &PlayerIndexedVector<bool>,
+        next_position: &PlayerIndexedVector<Cell>,
+        loses_in_this_tick: &PlayerIndexedVector<bool>,
+    ) {
+        for (player_id, player) in self.players.iter_mut() {
+            if !Self::is_moving(player_id, moving, loses_in_this_tick) {
+                continue;
+            }
+
+            let cell_state = self.field[next_position[player_id]];
+            if !cell_state.is_captured_by(player_id) {
+                self.field.set_trace(next_position[player_id], player_id)
+            }
+            player.position = next_position[player_id];
+        }
+    }
+
+    pub fn get_player_world(&self, i: PlayerId) -> World {
+        let players = self
+            .players
+            .iter()
+            .map(|(id, player)| {
+                let str_id = if id == i {
+                    "i".to_string()
+                } else {
+                    id.get().to_string()
+                };
+
+                let (territory, lines) = self.field.get_for_player(id);
+                let proto_player = paperio_proto::Player {
+                    score: player.score,
+                    territory: territory.iter().copied().collect(),
+                    position: player.position,
+                    lines: lines.iter().copied().collect(),
+                    direction: Some(player.direction),
+                    has_lost: self.has_lost(id),
+                    speed_boost_ticks: player.boost_ticks,
+                };
+
+                (str_id, proto_player)
+            })
+            .collect();
+        World {
+            players,
+            tick_num: self.tick,
+        }
+    }
+
+    pub fn get_spectator_world(&self) -> World {
+        self.get_player_world(NonZero::new(usize::MAX).unwrap())
+    }
+
+    /// Whether the game is over after `tick_count` regular ticks, taking
+    /// the territory win and sudden death rules into account.
+    pub fn is_finished(&self, tick_count: usize) -> bool {
+        if self.territory_winner().is_some() {
+            return true;
+        }
+
+        let ticks_played = (self.tick - 1) as usize;
+        if ticks_played < tick_count {
+            return false;
+        }
+        match self.params.rules.sudden_death_ticks {
+            Some(extra_ticks) if self.leader_id().is_none() => {
+                ticks_played >= tick_count + extra_ticks as usize
+            }
+            _ => true,
+        }
+    }
+
+    pub fn winner(&self) -> Option<PlayerId> {
+        self.territory_winner().or_else(|| self.leader_id())
+    }
+
+    fn territory_winner(&self) -> Option<PlayerId> {
+        let percent = self.params.rules.territory_win_percent?;
+        let field_size = self.params.x_cells_count * self.params.y_cells_count;
+        self.players
+            .iter_player_ids()
+            .map(|player_id| {
+                (
+                    player_id,
+                    self.field.get_for_player(player_id).0.len() as u32,
+                )
+            })
+            .filter(|&(_, territory)| territory * 100 >= percent * field_size)
+            .max_by_key(|&(_, territory)| territory)
+            .map(|(player_id, _)| player_id)
+    }
+
+    pub fn leader_id(&self) -> Option<PlayerId> {
+        let player_id = self
+            .players
+            .iter()
+            .max_by_key(|(_, player)| player.score)
+            .unwrap()
+            .0;
+        let leader_score = self.players[player_id].score;
+        if self
+            .players
+            .iter()
+            .filter(|p| p.1.score == leader_score)
+            .count()
+            > 1
+        {
+            None
+        } else {
+            Some(player_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spawn_positions_do_not_overlap() {
+        for player_count in 1..=MAX_PLAYER_COUNT {
+            let positions = spawn_positions(player_count);
+            assert_eq!(positions.len(), player_count);
+
+            for (i, &Cell(x, y)) in positions.iter().enumerate() {
+                // Initial territory is 3x3 square around the spawn position.
+                assert!(Cell(x - 1, y - 1).in_bounds() && Cell(x + 1, y + 1).in_bounds());
+
+                for &Cell(other_x, other_y) in &positions[i + 1..] {
+                    let distance = (x - other_x).abs().max((y - other_y).abs());
+                    assert!(
+                        distance >= 4,
+                        "players are too close for {player_count} players"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Builds a game from an ASCII map, placed at the bottom left corner of the field.
+    /// `.` is a free cell, `A`..`D` are cells captured by players 1..4, `a`..`d` are their traces
+    /// and `1`..`4` are their heads, which stand on their own traces.
+    fn game_from_map(map: &str, rules: Rules) -> Game {
+        let rows = map
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<_>>();
+        let player_count = map
+            .chars()
+            .filter_map(|c| c.to_digit(10))
+            .max()
+            .expect("map has no players") as usize;
+
+        let mut game = Game::new(player_count, rules);
+        game.field = GameField::new(X_CELLS_COUNT as usize, Y_CELLS_COUNT as usize, player_count);
+        for (row_index, row) in rows.iter().enumerate() {
+            let y = (rows.len() - 1 - row_index) as i32;
+            for (x, c) in row.chars().enumerate() {
+                let cell = Cell(x as i32, y);
+                let player_id = |index: u32| PlayerId::new(index as usize + 1).unwrap();
+                match c {
+                    '.' => {}
+                    'A'..='D' => game
+                        .field
+                        .set_captured(cell, player_id(c as u32 - 'A' as u32)),
+                    'a'..='d' => game.field.set_trace(cell, player_id(c as u32 - 'a' as u32)),
+                    '1'..='4' => {
+                        let player_id = player_id(c as u32 - '1' as u32);
+                        game.field.set_trace(cell, player_id);
+                        game.players[player_id].position = cell;
+                    }
+                    _ => panic!("unexpected map symbol {c:?}"),
+                }
+            }
+        }
+        game
+    }
+
+    struct Scenario {
+        name: &'static str,
+        map: &'static str,
+        moves: &'static [Direction],
+        losers: &'static [usize],
+        scores: &'static [u32],
+    }
+
+    #[test]
+    fn tick_scenarios() {
+        use Direction::*;
+
+        let scenarios = [
+            Scenario {
+                name: "border exit",
+                map: "
+                    1a
+                ",
+                moves: &[Left],
+                losers: &[1],
+                scores: &[0],
+            },
+            Scenario {
+                name: "head to head with equal traces",
+                map: "
+                    a1.2b
+                ",
+                moves: &[Right, Left],
+                losers: &[1, 2],
+                scores: &[0, 0],
+            },
+            Scenario {
+                name: "head to head, shorter trace wins",
+                map: "
+                    aa1.2b
+                ",
+                moves: &[Right, Left],
+                losers: &[1],
+                scores: &[0, 0],
+            },
+            Scenario {
+                name: "head to head, cell owner wins",
+                map: "
+                    a1A2bb
+                ",
+                moves: &[Right, Left],
+                losers: &[2],
+                scores: &[2, 0],
+            },
+            Scenario {
+                name: "tail crossing",
+                map: "
+                    a1..
+                    .bb2
+                ",
+                moves: &[Down, Up],
+                losers: &[2],
+                scores: &[0, 0],
+            },
+            Scenario {
+                name: "mutual crossing, shorter trace wins",
+                map: "
+                    aaa1
+                    ..2b
+                ",
+                moves: &[Down, Up],
+                losers: &[1],
+                scores: &[0, 0],
+            },
+            Scenario {
+                name: "mutual crossing with equal traces",
+                map: "
+                    .a1
+                    .2b
+                ",
+                moves: &[Down, Up],
+                losers: &[1, 2],
+                scores: &[0, 0],
+            },
+            Scenario {
+                name: "self crossing",
+                map: "
+                    aa.
+                    a1.
+                ",
+                moves: &[Up],
+                losers: &[1],
+                scores: &[0],
+            },
+            Scenario {
+                name: "crossings are simultaneous",
+                map: "
+                    a1b2c3
+                    .b.c..
+                ",
+                moves: &[Down, Down, Right],
+                losers: &[2, 3],
+                scores: &[0, 0, 0],
+            },
+            Scenario {
+                name: "capture of an enemy inside the loop",
+                map: "
+                    aaa..
+                    a2a..
+                    aB1..
+                    AAA..
+                ",
+                moves: &[Down, Down],
+                losers: &[2],
+                // One enemy cell, one free inner cell and seven trace cells.
+                scores: &[13, 0],
+            },
+        ];
+
+        for scenario in scenarios {
+            let mut game = game_from_map(scenario.map, Rules::default());
+            for (player_id, &direction) in game.players.iter_player_ids().zip(scenario.moves) {
+                game.players[player_id].direction = direction;
+            }
+            game.tick();
+
+            let losers = game
+                .players
+                .iter_player_ids()
+                .filter(|&player_id| game.has_lost(player_id))
+                .map(PlayerId::get)
+                .collect::<Vec<_>>();
+            assert_eq!(losers, scenario.losers, "losers in {:?}", scenario.name);
+            assert_eq!(
+                game.get_player_scores().into_vec(),
+                scenario.scores,
+                "scores in {:?}",
+                scenario.name
+            );
+        }
+    }
+
+    #[test]
+    fn rule_variants() {
+        use paperio_proto::SpeedBoost;
+
+        let capture_map = "
+            aaa..
+            a2a..
+            aB1..
+            AAA..
+        ";
+        let one = PlayerId::new(1).unwrap();
+
+        // Custom weights, speed boost and territory win.
+        let rules = Rules {
+            free_cell_score: 2,
+            enemy_cell_score: 10,
+            speed_boost: Some(SpeedBoost {
+                min_captured_cells: 5,
+                duration: 2,
+            }),
+            territory_win_percent: Some(1),
+            ..Default::default()
+        };
+        let mut game = game_from_map(capture_map, rules);
+        game.players[one].direction = Direction::Down;
+        assert!(!game.is_finished(300));
+        game.tick();
+        assert_eq!(game.get_player_scores()[one], 26);
+        assert!(game.is_finished(300));
+        assert_eq!(game.winner(), Some(one));
+
+        game.players[one].direction = Direction::Right;
+        game.tick();
+        assert_eq!(game.players[one].position, Cell(4, 0));
+        assert_eq!(game.players[one].boost_ticks, 1);
+
+        // Respawn after a delay.
+        let rules = Rules {
+            respawn_delay: Some(1),
+            ..Default::default()
+        };
+        let mut game = game_from_map("1a", rules);
+        game.players[one].direction = Direction::Left;
+        game.tick();
+        assert!(game.has_lost(one));
+        game.tick();
+        assert!(!game.has_lost(one));
+        assert_eq!(game.players[one].position, game.players[one].spawn_position);
+        assert_eq!(game.field.get_for_player(one).0.len(), 9);
+
+        // Respawn onto occupied cells goes to the closest free square...
+        let two = PlayerId::new(2).unwrap();
+        let mut game = game_from_map(
+            "
+            BBB....
+            BBB....
+            BBB2b1a
+            ",
+            rules,
+        );
+        game.players[one].spawn_position = Cell(1, 1);
+        game.players[one].direction = Direction::Down;
+        game.players[two].direction = Direction::Up;
+        game.tick();
+        assert!(game.has_lost(one));
+        game.tick();
+        assert!(!game.has_lost(one));
+        assert_eq!(game.players[one].position, Cell(1, 4));
+        assert_eq!(game.field.get_for_player(two).0.len(), 9);
+
+        // ...or, if there is none, players in the way lose.
+        let mut game = game_from_map("2b1a", rules);
+        for x in 0..X_CELLS_COUNT as i32 {
+            for y in 0..Y_CELLS_COUNT as i32 {
+                if game.field[Cell(x, y)].is_traced().is_none() {
+                    game.field.set_captured(Cell(x, y), two);
+                }
+            }
+        }
+        game.players[one].spawn_position = Cell(1, 1);
+        game.players[one].direction = Direction::Down;
+        game.players[two].direction = Direction::Up;
+        game.tick();
+        game.tick();
+        assert!(!game.has_lost(one));
+        assert!(game.has_lost(two));
+        assert_eq!(game.players[one].position, Cell(1, 1));
+        assert_eq!(game.field.get_for_player(one).0.len(), 9);
+
+        // Sudden death continues the game while the lead is shared.
+        let rules = Rules {
+            sudden_death_ticks: Some(5),
+            ..Default::default()
+        };
+        assert!(!Game::new(2, rules).is_finished(0));
+        assert!(Game::new(2, Rules::default()).is_finished(0));
+    }
+
+    #[test]
+    fn stopped_player_stands_still() {
+        let mut game = Game::new(2, Rules::default());
+        let (one, two) = (PlayerId::new(1).unwrap(), PlayerId::new(2).unwrap());
+        let position = game.players[one].position;
+
+        game.set_stopped(one, true);
+        game.tick();
+        assert_eq!(game.players[one].position, position);
+        assert_ne!(game.players[two].position, game.players[two].spawn_position);
+
+        game.set_stopped(one, false);
+        game.tick();
+        assert_eq!(game.players[one].position, position + Direction::Left);
+    }
+
+    #[test]
+    fn simulation_is_deterministic() {
+        let play = |seed| {
+            let mut game = Game::with_seed(6, Rules::default(), seed);
+            let directions = [
+                Direction::Up,
+                Direction::Left,
+                Direction::Down,
+                Direction::Right,
+            ];
+            let mut hashes = vec![];
+            for tick in 0..40 {
+                for player_id in game.players.iter_player_ids() {
+                    let direction = directions[(tick / 3 + player_id.get()) % directions.len()];
+                    game.try_change_direction(player_id, direction);
+                }
+                game.tick();
+                hashes.push(game.state_hash());
+            }
+            hashes
+        };
+
+        assert_eq!(play(1), play(1));
+        assert_ne!(play(1), play(2));
+    }
+
+    #[test]
+    fn game_from_world() {
+        let rules = Rules::default();
+        let mut game = Game::with_seed(4, rules, 7);
+        let directions = |tick: usize| {
+            [
+                Direction::Up,
+                Direction::Left,
+                Direction::Down,
+                Direction::Right,
+            ]
+            .into_iter()
+            .cycle()
+            .skip(tick / 4)
+            .take(4)
+            .enumerate()
+            .map(|(i, direction)| (PlayerId::new(i + 1).unwrap(), direction))
+            .collect::<Vec<_>>()
+        };
+        for tick in 0..10 {
+            game.tick_with(directions(tick));
+        }
+
+        let two = PlayerId::new(2).unwrap();
+        let world = game.get_player_world(two);
+        assert_eq!(world_player_ids(&world)["i"], two);
+        let mut copy = Game::from_world(&world, rules);
+        assert_eq!(copy.get_player_world(two), world);
+
+        let branch = copy.clone();
+        for tick in 10..30 {
+            game.tick_with(directions(tick));
+            copy.tick_with(directions(tick));
+            assert_eq!(copy.get_player_world(two), game.get_player_world(two));
+        }
+        assert_eq!(branch.get_player_world(two), world);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    collections::HashSet,
+    collections::BTreeSet,
+    mem,
     ops::{Index, IndexMut},
 };
 
@@ -13,6 +14,10 @@ pub struct CellState {
 }
 
 impl CellState {
+    pub fn owner(&self) -> Option<PlayerId> {
+        self.captured
+    }
+
     pub fn is_traced(&self) -> Option<PlayerId> {
         self.traced
     }
@@ -26,6 +31,7 @@ impl CellState {
     }
 }
 
+#[derive(Clone)]
 struct Array2D<T> {
     width: usize,
     height: usize,
@@ -56,10 +62,11 @@ impl<T> IndexMut<Cell> for Array2D<T> {
     }
 }
 
+#[derive(Clone)]
 pub struct GameField {
     field: Array2D<CellState>,
-    captured_cells: PlayerIndexedVector<HashSet<Cell>>,
-    traced_cells: PlayerIndexedVector<HashSet<Cell>>,
+    captured_cells: PlayerIndexedVector<BTreeSet<Cell>>,
+    traced_cells: PlayerIndexedVector<BTreeSet<Cell>>,
 }
 
 impl Index<Cell> for GameField {
@@ -83,7 +90,7 @@ impl GameField {
         }
     }
 
-    pub fn traced_cells(&self, player_id: PlayerId) -> &HashSet<Cell> {
+    pub fn traced_cells(&self, player_id: PlayerId) -> &BTreeSet<Cell> {
         &self.traced_cells[player_id]
     }
 
@@ -167,21 +174,31 @@ impl GameField {
         inner_cells
     }
 
+    /// Cells the player would capture by closing its trace now.
+    pub fn pending_capture(&self, player_id: PlayerId) -> Vec<Cell> {
+        if self.traced_cells[player_id].is_empty() {
+            return vec![];
+        }
+        let mut cells = self.find_inner_cells(player_id);
+        cells.extend(self.traced_cells[player_id].iter().copied());
+        cells
+    }
+
     pub fn capture_all(
         &mut self,
         player_id: PlayerId,
         players_positions: &PlayerIndexedVector<Cell>,
-    ) -> (u32, u32, HashSet<PlayerId>) {
+    ) -> (u32, u32, BTreeSet<PlayerId>) {
         if self.traced_cells[player_id].is_empty() {
-            return (0, 0, HashSet::new());
+            return (0, 0, BTreeSet::new());
         }
 
         let mut captured_cells = self.find_inner_cells(player_id);
-        captured_cells.extend(self.traced_cells[player_id].drain());
+        captured_cells.extend(mem::take(&mut self.traced_cells[player_id]));
 
         let mut enemy_cells_captured = 0;
         let mut free_cells_captured = 0;
-        let mut captured_enemies = HashSet::<PlayerId>::new();
+        let mut captured_enemies = BTreeSet::<PlayerId>::new();
 
         for &cell in captured_cells.iter() {
             let cell_state = self.field[cell];
@@ -211,15 +228,15 @@ impl GameField {
     }
 
     pub fn remove_player(&mut self, player_id: PlayerId) {
-        for traced_cell in self.traced_cells[player_id].drain() {
+        for traced_cell in mem::take(&mut self.traced_cells[player_id]) {
             self.field[traced_cell].traced = None;
         }
-        for captured_cell in self.captured_cells[player_id].drain() {
+        for captured_cell in mem::take(&mut self.captured_cells[player_id]) {
             self.field[captured_cell].captured = None;
         }
     }
 
-    pub fn get_for_player(&self, player_id: PlayerId) -> (&HashSet<Cell>, &HashSet<Cell>) {
+    pub fn get_for_player(&self, player_id: PlayerId) -> (&BTreeSet<Cell>, &BTreeSet<Cell>) {
         (
             &self.captured_cells[player_id],
             &self.traced_cells[player_id],
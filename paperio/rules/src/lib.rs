@@ -0,0 +1,7 @@
+//! Rules of the game, shared by the server and strategies that want
+//! to simulate the game ahead with exactly the server's logic.
+
+pub mod game;
+mod game_field;
+pub mod player_vec;
+pub mod random;
@@ -5,6 +5,7 @@ use std::{
 
 use crate::game::PlayerId;
 
+#[derive(Clone)]
 pub struct PlayerIndexedVector<T> {
     data: Vec<T>,
 }
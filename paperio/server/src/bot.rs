@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use paperio_proto::{traits::Bot, Cell, Direction, World};
+use paperio_rules::random::SplitMix64;
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+/// A weak built-in bot for filling empty seats. It wanders around randomly
+/// and heads home once its trace gets long.
+pub struct SimpleBot {
+    rng: SplitMix64,
+    max_trace_len: usize,
+}
+
+impl SimpleBot {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: SplitMix64::new(seed),
+            max_trace_len: 8,
+        }
+    }
+}
+
+impl Bot for SimpleBot {
+    fn on_tick(&mut self, world: World) -> Direction {
+        let me = world.me();
+        let current = me.direction.unwrap_or(Direction::Up);
+        let lines = me.lines.iter().copied().collect::<HashSet<_>>();
+
+        let allowed = DIRECTIONS
+            .into_iter()
+            .filter(|&direction| me.direction != Some(direction.opposite()))
+            .filter(|&direction| {
+                me.position
+                    .adjacent(direction)
+                    .is_some_and(|cell| !lines.contains(&cell))
+            })
+            .collect::<Vec<_>>();
+        if allowed.is_empty() {
+            return current;
+        }
+
+        if me.lines.len() >= self.max_trace_len {
+            let distance_home = |cell: Cell| {
+                me.territory
+                    .iter()
+                    .map(|&home| home.distance_to(cell))
+                    .min()
+                    .unwrap_or(0)
+            };
+            return allowed
+                .into_iter()
+                .min_by_key(|&direction| distance_home(me.position.adjacent_unchecked(direction)))
+                .unwrap();
+        }
+
+        let turn = self.rng.next_u64().is_multiple_of(4);
+        if !turn && allowed.contains(&current) {
+            return current;
+        }
+        allowed[(self.rng.next_u64() % allowed.len() as u64) as usize]
+    }
+}
@@ -1,7 +1,7 @@
 use std::io::{self, BufRead, Write};
 
 use paperio_proto::{
-    traits::{JsonRead, JsonWrite},
+    traits::{Bot, Codec},
     Command, Message,
 };
 
@@ -10,7 +10,7 @@ pub trait Endpoint {
     fn get_command(&mut self) -> io::Result<Command>;
 }
 
-impl<'a, T: Endpoint> Endpoint for &'a mut T {
+impl<T: Endpoint> Endpoint for &mut T {
     fn send_message(&mut self, message: &Message) -> io::Result<()> {
         T::send_message(self, message)
     }
@@ -20,24 +20,78 @@ impl<'a, T: Endpoint> Endpoint for &'a mut T {
     }
 }
 
-pub struct JsonEndpoint<R, W> {
+impl<T: Endpoint + ?Sized> Endpoint for Box<T> {
+    fn send_message(&mut self, message: &Message) -> io::Result<()> {
+        T::send_message(self, message)
+    }
+
+    fn get_command(&mut self) -> io::Result<Command> {
+        T::get_command(self)
+    }
+}
+
+/// Talks to a client over a byte stream. The protocol is JSON until
+/// the client switches to another encoding with `Command::SetEncoding`.
+pub struct StreamEndpoint<R, W> {
     reader: R,
     writer: W,
+    codec: Codec,
 }
 
-impl<R: BufRead, W: Write> JsonEndpoint<R, W> {
+impl<R: BufRead, W: Write> StreamEndpoint<R, W> {
     pub fn new(reader: R, writer: W) -> Self {
-        Self { reader, writer }
+        Self {
+            reader,
+            writer,
+            codec: Codec::new(),
+        }
     }
 }
 
-impl<R: BufRead, W: Write> Endpoint for JsonEndpoint<R, W> {
+impl<R: BufRead, W: Write> Endpoint for StreamEndpoint<R, W> {
     fn send_message(&mut self, message: &Message) -> io::Result<()> {
-        self.writer.write_message(message)?;
+        self.codec.write_message(&mut self.writer, message)?;
         self.writer.flush()
     }
 
     fn get_command(&mut self) -> io::Result<Command> {
-        self.reader.read_command()
+        loop {
+            match self.codec.read_command(&mut self.reader)? {
+                Command::SetEncoding(encoding) => self.codec.set_encoding(encoding),
+                command => return Ok(command),
+            }
+        }
+    }
+}
+
+/// Calls the bot in the server thread, without any serialization.
+pub struct BotEndpoint<B> {
+    bot: B,
+    command: Option<Command>,
+}
+
+impl<B: Bot> BotEndpoint<B> {
+    pub fn new(bot: B) -> Self {
+        Self { bot, command: None }
+    }
+}
+
+impl<B: Bot> Endpoint for BotEndpoint<B> {
+    fn send_message(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::StartGame(params) => self.bot.on_start(params.clone()),
+            Message::Tick(world) => {
+                let direction = self.bot.on_tick(world.clone());
+                self.command = Some(Command::ChangeDirection(direction));
+            }
+            Message::EndGame {} => {}
+        }
+        Ok(())
+    }
+
+    fn get_command(&mut self) -> io::Result<Command> {
+        self.command.take().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "bot has not received a tick")
+        })
     }
 }
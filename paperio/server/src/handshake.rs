@@ -0,0 +1,182 @@
+//! Server side of the optional [`Handshake`]. A client that sends nothing
+//! in time is a legacy one and is served without it. A client that starts
+//! the handshake has to finish it in time, or its connection is rejected.
+
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use paperio_proto::{
+    features,
+    traits::{JsonRead, JsonWrite},
+    Handshake, Hello, Welcome, PROTOCOL_VERSION,
+};
+
+use crate::game::PlayerId;
+
+/// Time for a new client to start the handshake.
+pub const HELLO_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Time for a client to send the whole `Hello` once it has started it.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAX_NAME_LEN: usize = 32;
+
+const MAX_HELLO_LEN: usize = 8 << 10;
+
+/// Returns `Hello` of the client, or `None` if it didn't send one in time.
+pub fn read_hello(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Hello>> {
+    reader.get_ref().set_read_timeout(Some(HELLO_TIMEOUT))?;
+    let has_hello = match reader.fill_buf() {
+        Ok(buf) => !buf.is_empty(),
+        Err(err) if is_timeout(&err) => false,
+        Err(err) => return Err(err),
+    };
+    let line = if has_hello {
+        read_line_until(reader, Instant::now() + HANDSHAKE_TIMEOUT)
+    } else {
+        Ok(vec![])
+    };
+    reader.get_ref().set_read_timeout(None)?;
+    let line = line?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    match line.as_slice().read_handshake()? {
+        Handshake::Hello(hello) => Ok(Some(hello)),
+        Handshake::Welcome(_) => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "expected `hello` handshake",
+        )),
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Reads a line, failing if it doesn't come in full before the deadline.
+fn read_line_until(reader: &mut BufReader<TcpStream>, deadline: Instant) -> io::Result<Vec<u8>> {
+    let mut line = vec![];
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "handshake timed out"));
+        }
+        reader.get_ref().set_read_timeout(Some(timeout))?;
+        let buf = match reader.fill_buf() {
+            Ok([]) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(buf) => buf,
+            Err(err) if is_timeout(&err) => continue,
+            Err(err) => return Err(err),
+        };
+
+        let (len, is_complete) = match buf.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (buf.len(), false),
+        };
+        line.extend_from_slice(&buf[..len]);
+        reader.consume(len);
+        if is_complete {
+            return Ok(line);
+        }
+        if line.len() > MAX_HELLO_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "`hello` is too long",
+            ));
+        }
+    }
+}
+
+/// Confirms the handshake. Only features the server supports are confirmed,
+/// reconnects are only confirmed along with a session token.
+pub fn write_welcome(
+    writer: &mut impl Write,
+    hello: &Hello,
+    player_id: Option<PlayerId>,
+    session_token: Option<&str>,
+) -> io::Result<()> {
+    let welcome = Welcome {
+        player_id: player_id.map(|player_id| player_id.to_string()),
+        protocol_version: PROTOCOL_VERSION,
+        features: hello
+            .features
+            .iter()
+            .filter(|feature| features::SUPPORTED.contains(&feature.as_str()))
+            .filter(|feature| *feature != features::RECONNECT || session_token.is_some())
+            .cloned()
+            .collect(),
+        session_token: session_token.map(str::to_string),
+    };
+    writer.write_handshake(&Handshake::Welcome(welcome))?;
+    writer.flush()
+}
+
+/// Name of the client trimmed to a sane length, `None` if it is blank.
+pub fn display_name(hello: &Hello) -> Option<String> {
+    let name = hello
+        .name
+        .trim()
+        .chars()
+        .take(MAX_NAME_LEN)
+        .collect::<String>();
+    Some(name).filter(|name| !name.is_empty())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Server side of a connection with the given client bytes already sent.
+    fn connect(sent: &[u8]) -> (BufReader<TcpStream>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(sent).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (BufReader::new(stream), client)
+    }
+
+    #[test]
+    fn legacy_client_has_no_hello() {
+        let (mut reader, _client) = connect(b"");
+        assert!(read_hello(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn stalled_hello_times_out() {
+        let (mut reader, _client) = connect(br#"{"hello":{"#);
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let err = read_line_until(&mut reader, deadline).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn hello_in_parts() {
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            name: "bot".to_string(),
+            features: vec![],
+            session_token: None,
+        };
+        let mut line = vec![];
+        line.write_handshake(&Handshake::Hello(hello.clone()))
+            .unwrap();
+        let rest = line.split_off(5);
+
+        let (mut reader, mut client) = connect(&line);
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            client.write_all(&rest).unwrap();
+            client
+        });
+        assert_eq!(read_hello(&mut reader).unwrap(), Some(hello));
+        sender.join().unwrap();
+    }
+}
@@ -1,5 +1,11 @@
+pub mod bot;
 pub mod endpoint;
-pub mod game;
-mod game_field;
-pub mod player_vec;
+pub mod handshake;
+pub mod lobby;
 pub mod server;
+pub mod session;
+pub mod validate;
+pub mod web;
+pub mod worker;
+
+pub use paperio_rules::{game, player_vec};
@@ -0,0 +1,351 @@
+//! Long running mode: players connect to a single port and wait in a queue,
+//! full games are started as soon as there are enough of them. If the queue
+//! doesn't fill up in time, built-in bots take the empty seats.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, ErrorKind},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::*;
+use paperio_proto::{Hello, PlayerInfo, Rules};
+
+use crate::{
+    bot::SimpleBot,
+    endpoint::{BotEndpoint, Endpoint, StreamEndpoint},
+    game::PlayerId,
+    handshake,
+    player_vec::PlayerIndexedVector,
+    server::{Server, TimeBudget},
+};
+
+/// How often the queue is checked when nobody connects.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy)]
+pub struct LobbyConfig {
+    pub players_per_game: usize,
+    /// How long the first player in the queue waits for others before bots are added.
+    pub fill_timeout: Duration,
+    pub max_concurrent_games: usize,
+    pub tick_count: usize,
+    pub rules: Rules,
+    pub time_budget: TimeBudget,
+}
+
+pub struct GameReport {
+    pub game_num: usize,
+    /// Players in order of their ids.
+    pub players: Vec<PlayerReport>,
+    pub winner: Option<String>,
+}
+
+pub struct PlayerReport {
+    pub name: String,
+    pub score: u32,
+    pub is_bot: bool,
+}
+
+struct Client {
+    hello: Option<Hello>,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    queued_at: Instant,
+}
+
+impl Client {
+    fn accept(stream: TcpStream) -> io::Result<Self> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let hello = handshake::read_hello(&mut reader)?;
+        Ok(Self {
+            hello,
+            reader,
+            writer: BufWriter::new(stream),
+            queued_at: Instant::now(),
+        })
+    }
+
+    /// Detects clients that left while waiting in the queue.
+    fn is_connected(&self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return true;
+        }
+        let stream = self.reader.get_ref();
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let is_connected = match stream.peek(&mut [0]) {
+            Ok(len) => len > 0,
+            Err(err) => err.kind() == ErrorKind::WouldBlock,
+        };
+        is_connected && stream.set_nonblocking(false).is_ok()
+    }
+
+    fn name(&self) -> Option<String> {
+        self.hello.as_ref().and_then(handshake::display_name)
+    }
+}
+
+pub struct Lobby {
+    listener: TcpListener,
+    config: LobbyConfig,
+}
+
+impl Lobby {
+    pub fn bind(address: SocketAddr, config: LobbyConfig) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            config,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves players until the listener fails, `on_report` is called after every game.
+    pub fn run(self, mut on_report: impl FnMut(GameReport)) -> io::Result<()> {
+        let config = self.config;
+        let clients = spawn_acceptor(self.listener);
+        let (report_sender, reports) = mpsc::channel();
+
+        let mut queue = VecDeque::new();
+        let running_games = Arc::new(AtomicUsize::new(0));
+        let mut games_started = 0;
+        loop {
+            match clients.recv_timeout(POLL_INTERVAL) {
+                Ok(client) => queue.push_back(client),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::other("lobby listener stopped"));
+                }
+            }
+            while let Ok(report) = reports.try_recv() {
+                on_report(report);
+            }
+
+            queue.retain(Client::is_connected);
+            while running_games.load(Ordering::Relaxed) < config.max_concurrent_games
+                && is_game_ready(&queue, &config)
+            {
+                let seated = queue
+                    .drain(..queue.len().min(config.players_per_game))
+                    .collect::<Vec<_>>();
+                games_started += 1;
+
+                let game_num = games_started;
+                let running_game = RunningGame::start(&running_games);
+                let report_sender = report_sender.clone();
+                info!("starting game #{game_num} with {} player(s)", seated.len());
+                thread::spawn(move || {
+                    let _running_game = running_game;
+                    let report = play_game(game_num, seated, &config);
+                    if report_sender.send(report).is_err() {
+                        debug!("lobby has stopped, report of game #{game_num} is dropped");
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Counts a game as running until it is dropped, even if the game panics.
+struct RunningGame(Arc<AtomicUsize>);
+
+impl RunningGame {
+    fn start(running_games: &Arc<AtomicUsize>) -> Self {
+        running_games.fetch_add(1, Ordering::Relaxed);
+        Self(running_games.clone())
+    }
+}
+
+impl Drop for RunningGame {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn is_game_ready(queue: &VecDeque<Client>, config: &LobbyConfig) -> bool {
+    queue.len() >= config.players_per_game
+        || queue
+            .front()
+            .is_some_and(|client| client.queued_at.elapsed() >= config.fill_timeout)
+}
+
+/// Accepts connections and greets them in background, so that
+/// a slow client doesn't hold up the others.
+fn spawn_acceptor(listener: TcpListener) -> Receiver<Client> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("failed to accept a player: {err}");
+                    continue;
+                }
+            };
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let peer_addr = stream.peer_addr();
+                match Client::accept(stream) {
+                    Ok(client) => {
+                        info!("player {peer_addr:?} joined the queue");
+                        let _ = sender.send(client);
+                    }
+                    Err(err) => warn!("handshake with {peer_addr:?} failed: {err}"),
+                }
+            });
+        }
+    });
+    receiver
+}
+
+fn play_game(game_num: usize, clients: Vec<Client>, config: &LobbyConfig) -> GameReport {
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+
+    let mut endpoints = Vec::<Box<dyn Endpoint + Send>>::new();
+    let mut players = vec![];
+    for (index, client) in clients.into_iter().enumerate() {
+        let player_id = PlayerId::new(index + 1).unwrap();
+        let name = client
+            .name()
+            .unwrap_or_else(|| format!("Player #{player_id}"));
+        let Client {
+            hello,
+            reader,
+            mut writer,
+            ..
+        } = client;
+        if let Some(hello) = &hello {
+            if let Err(err) = handshake::write_welcome(&mut writer, hello, Some(player_id), None) {
+                warn!("failed to welcome {name:?}: {err}");
+            }
+        }
+        endpoints.push(Box::new(StreamEndpoint::new(reader, writer)));
+        players.push((name, false));
+    }
+    for index in endpoints.len()..config.players_per_game {
+        endpoints.push(Box::new(BotEndpoint::new(SimpleBot::new(
+            seed.wrapping_add(index as u64),
+        ))));
+        players.push((format!("Bot #{}", index + 1), true));
+    }
+
+    let player_infos = players
+        .iter()
+        .map(|(name, _)| PlayerInfo {
+            user_name: name.clone(),
+        })
+        .collect::<PlayerIndexedVector<_>>();
+    let result = Server::new(
+        PlayerIndexedVector::from(endpoints),
+        Vec::<Box<dyn Endpoint + Send>>::new(),
+    )
+    .time_budget(config.time_budget)
+    .rules(config.rules)
+    .seed(seed)
+    .player_infos(player_infos)
+    .run(config.tick_count);
+
+    GameReport {
+        game_num,
+        winner: result
+            .winner
+            .map(|player_id| players[player_id.get() - 1].0.clone()),
+        players: players
+            .into_iter()
+            .zip(result.players.into_vec())
+            .map(|((name, is_bot), result)| PlayerReport {
+                name,
+                score: result.score,
+                is_bot,
+            })
+            .collect(),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::{
+        traits::{JsonRead, JsonWrite},
+        Command, Handshake, Message, PROTOCOL_VERSION,
+    };
+    use std::io::Write;
+
+    #[test]
+    fn bots_fill_empty_seats() {
+        let config = LobbyConfig {
+            players_per_game: 3,
+            fill_timeout: Duration::from_millis(200),
+            max_concurrent_games: 2,
+            tick_count: 20,
+            rules: Rules::default(),
+            time_budget: TimeBudget::default(),
+        };
+        let lobby = Lobby::bind("127.0.0.1:0".parse().unwrap(), config).unwrap();
+        let address = lobby.local_addr().unwrap();
+        let (report_sender, reports) = mpsc::channel();
+        thread::spawn(move || lobby.run(|report| report_sender.send(report).unwrap()));
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = BufWriter::new(stream);
+        let hello = Hello {
+            name: "tester".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            features: vec![],
+            session_token: None,
+        };
+        writer.write_handshake(&Handshake::Hello(hello)).unwrap();
+        writer.flush().unwrap();
+
+        let Handshake::Welcome(welcome) = reader.read_handshake().unwrap() else {
+            panic!("expected welcome");
+        };
+        assert_eq!(welcome.player_id.as_deref(), Some("1"));
+        let Message::StartGame(params) = reader.read_message().unwrap() else {
+            panic!("expected start_game");
+        };
+        assert_eq!(params.players.len(), 3);
+        assert_eq!(params.players["1"].user_name, "tester");
+
+        while let Message::Tick(_) = reader.read_message().unwrap() {
+            writer.write_command(&Command::NoOp).unwrap();
+            writer.flush().unwrap();
+        }
+
+        let report = reports.recv().unwrap();
+        assert_eq!(report.game_num, 1);
+        let bots = report.players.iter().filter(|p| p.is_bot).count();
+        assert_eq!(bots, 2);
+        assert_eq!(report.players[0].name, "tester");
+    }
+
+    #[test]
+    fn panicked_game_frees_its_slot() {
+        let running_games = Arc::new(AtomicUsize::new(0));
+        let running_game = RunningGame::start(&running_games);
+        assert_eq!(running_games.load(Ordering::Relaxed), 1);
+
+        let game = thread::spawn(move || {
+            let _running_game = running_game;
+            panic!("the game has failed");
+        });
+        assert!(game.join().is_err());
+        assert_eq!(running_games.load(Ordering::Relaxed), 0);
+    }
+}
@@ -1,19 +1,28 @@
 use anyhow::{ensure, Context, Result};
-use clap::Parser;
-use log::info;
+use clap::{builder::RangedU64ValueParser, Parser};
+use log::{info, warn};
+use paperio_proto::{replay::ReplayWriter, GameReplay, PlayerInfo, Rules, SpeedBoost};
 use paperio_server::{
-    endpoint::{Endpoint, JsonEndpoint},
-    game::PlayerId,
+    endpoint::{Endpoint, StreamEndpoint},
+    game::{PlayerId, MAX_PLAYER_COUNT},
+    handshake,
+    lobby::{GameReport, Lobby, LobbyConfig},
     player_vec::PlayerIndexedVector,
-    server::Server,
+    server::{Server, TimeBudget},
+    session::{DisconnectPolicy, Sessions},
+    validate::validate_replay,
+    web::WebSpectators,
 };
 
 use std::{
     collections::HashMap,
-    io::{BufReader, BufWriter},
+    io::{BufReader, BufWriter, Write},
     iter,
-    net::{SocketAddr, TcpListener},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::Arc,
     thread,
+    time::Duration,
 };
 
 #[derive(Parser)]
@@ -25,19 +34,31 @@ struct Arguments {
     #[arg(short = 'p', long, default_value_t = 8000)]
     default_player_port: u16,
 
-    #[arg(long = "p1")]
+    /// Comma separated ports of players in order of their ids.
+    /// Players without a port in this list use the default player port.
+    #[arg(long = "ports", value_delimiter = ',')]
+    player_ports: Vec<u16>,
+
+    /// Old per-seat ports of the first four players, `--ports` replaces them.
+    #[arg(long = "p1", hide = true, conflicts_with = "player_ports")]
     player_one_port: Option<u16>,
 
-    #[arg(long = "p2")]
+    #[arg(long = "p2", hide = true, conflicts_with = "player_ports")]
     player_two_port: Option<u16>,
 
-    #[arg(long = "p3")]
+    #[arg(long = "p3", hide = true, conflicts_with = "player_ports")]
     player_three_port: Option<u16>,
 
-    #[arg(long = "p4")]
+    #[arg(long = "p4", hide = true, conflicts_with = "player_ports")]
     player_four_port: Option<u16>,
 
-    #[arg(short = 'n', long, default_value_t = 4)]
+    /// Number of players, from 1 to 12.
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 4,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..=MAX_PLAYER_COUNT as u64)
+    )]
     player_count: usize,
 
     #[arg(short, long, default_value_t = 300)]
@@ -49,8 +70,123 @@ struct Arguments {
     #[arg(short, long, default_value_t = 0)]
     spectator_count: usize,
 
+    /// Keep running and play games one after another with players connecting
+    /// to the default player port. Bots take seats left empty.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "player_ports",
+            "player_one_port",
+            "player_two_port",
+            "player_three_port",
+            "player_four_port",
+            "reconnect_port",
+            "web_port",
+            "replay",
+        ]
+    )]
+    lobby: bool,
+
+    /// In lobby mode, how long a player waits for opponents before bots join.
+    #[arg(long, default_value_t = 5000)]
+    lobby_fill_ms: u64,
+
+    /// In lobby mode, maximal number of games played at the same time.
+    #[arg(long, default_value_t = 4)]
+    max_games: usize,
+
+    /// Accept reconnecting players on this port. Players get a session token
+    /// in the handshake and may use it to take their seat back.
+    #[arg(long)]
+    reconnect_port: Option<u16>,
+
+    /// Time for a disconnected player to reconnect.
+    #[arg(long, default_value_t = 10000)]
+    reconnect_grace_ms: u64,
+
+    /// What the snake of a disconnected player does.
+    #[arg(long, value_enum, default_value_t = DisconnectPolicy::Straight)]
+    disconnect_policy: DisconnectPolicy,
+
+    /// Serve a web viewer on this port. Web spectators may come and go
+    /// at any time and never slow the game down.
+    #[arg(long)]
+    web_port: Option<u16>,
+
     #[arg(short, long, default_value_t = 2)]
     log_level: usize,
+
+    /// Time for a player to answer a tick, a late answer is ignored.
+    #[arg(long)]
+    tick_timeout_ms: Option<u64>,
+
+    /// Total time for a player to answer all ticks, the player is disqualified when it runs out.
+    #[arg(long)]
+    time_budget_ms: Option<u64>,
+
+    #[command(flatten)]
+    rules: RulesArguments,
+
+    /// Seed for spawn positions, the game is reproducible given the seed and commands.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Re-simulate the given replay, check that it is consistent and exit.
+    #[arg(long, value_name = "REPLAY")]
+    validate: Option<PathBuf>,
+
+    /// Record the game into a replay file (JSON lines, gzipped if path ends with `.gz`).
+    #[arg(short, long)]
+    replay: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Rules")]
+struct RulesArguments {
+    #[arg(long, default_value_t = Rules::default().free_cell_score)]
+    free_cell_score: u32,
+
+    #[arg(long, default_value_t = Rules::default().enemy_cell_score)]
+    enemy_cell_score: u32,
+
+    /// Players who lost respawn after this many ticks instead of being eliminated.
+    #[arg(long)]
+    respawn_delay: Option<u32>,
+
+    /// A player who captures this percentage of the field wins immediately.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=100))]
+    territory_win_percent: Option<u32>,
+
+    /// Capturing at least CELLS cells at once doubles the speed for TICKS ticks.
+    #[arg(long, value_name = "CELLS:TICKS", value_parser = parse_speed_boost)]
+    speed_boost: Option<SpeedBoost>,
+
+    /// Play up to this many extra ticks while the lead is shared.
+    #[arg(long)]
+    sudden_death_ticks: Option<u32>,
+}
+
+impl From<RulesArguments> for Rules {
+    fn from(args: RulesArguments) -> Self {
+        Self {
+            free_cell_score: args.free_cell_score,
+            enemy_cell_score: args.enemy_cell_score,
+            respawn_delay: args.respawn_delay,
+            territory_win_percent: args.territory_win_percent,
+            speed_boost: args.speed_boost,
+            sudden_death_ticks: args.sudden_death_ticks,
+        }
+    }
+}
+
+fn parse_speed_boost(arg: &str) -> Result<SpeedBoost> {
+    let (cells, ticks) = arg
+        .split_once(':')
+        .context("expected speed boost in form CELLS:TICKS")?;
+    Ok(SpeedBoost {
+        min_captured_cells: cells.parse()?,
+        duration: ticks.parse()?,
+    })
 }
 
 #[derive(Clone, Copy)]
@@ -59,23 +195,29 @@ enum EndpointTag {
     Spectator,
 }
 
-fn get_port_to_endpoint_tags(args: &Arguments) -> HashMap<u16, Vec<EndpointTag>> {
-    let player_ports = [
-        args.player_one_port,
-        args.player_two_port,
-        args.player_three_port,
-        args.player_four_port,
-    ];
+impl Arguments {
+    fn legacy_player_ports(&self) -> [Option<u16>; 4] {
+        [
+            self.player_one_port,
+            self.player_two_port,
+            self.player_three_port,
+            self.player_four_port,
+        ]
+    }
+}
 
+fn get_port_to_endpoint_tags(args: &Arguments) -> HashMap<u16, Vec<EndpointTag>> {
+    let legacy_player_ports = args.legacy_player_ports();
     let mut port_to_endpoint_tags = HashMap::<u16, Vec<EndpointTag>>::new();
 
     for i in 0..args.player_count {
         let tag = EndpointTag::Player(PlayerId::new(i + 1).unwrap());
 
-        let port = player_ports
+        let port = args
+            .player_ports
             .get(i)
+            .or(legacy_player_ports.get(i).and_then(Option::as_ref))
             .copied()
-            .flatten()
             .unwrap_or(args.default_player_port);
 
         port_to_endpoint_tags
@@ -87,17 +229,101 @@ fn get_port_to_endpoint_tags(args: &Arguments) -> HashMap<u16, Vec<EndpointTag>>
     port_to_endpoint_tags
         .entry(args.spectator_port)
         .and_modify(|tags| {
-            tags.extend(iter::repeat(EndpointTag::Spectator).take(args.spectator_count))
+            tags.extend(iter::repeat_n(EndpointTag::Spectator, args.spectator_count))
         })
         .or_insert(vec![EndpointTag::Spectator; args.spectator_count]);
 
     port_to_endpoint_tags
 }
 
+/// Serves the optional handshake. Returns the name from `Hello`,
+/// or `None` if the client didn't send one in time.
+fn accept_handshake(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut impl Write,
+    tag: EndpointTag,
+    sessions: Option<&Sessions>,
+) -> Result<Option<String>> {
+    let Some(hello) = handshake::read_hello(reader).context("invalid handshake")? else {
+        return Ok(None);
+    };
+    info!(
+        "hello from {:?}, protocol version {}",
+        hello.name, hello.protocol_version
+    );
+
+    let player_id = match tag {
+        EndpointTag::Player(player_id) => Some(player_id),
+        EndpointTag::Spectator => None,
+    };
+    let session_token = sessions.zip(player_id).map(|(s, id)| s.token(id));
+    handshake::write_welcome(writer, &hello, player_id, session_token)?;
+    Ok(handshake::display_name(&hello))
+}
+
+/// Takes the seat of the player with the session token from `Hello`.
+fn accept_reconnect(stream: TcpStream, sessions: &Sessions) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone().context("failed to clone fd")?);
+    let mut writer = BufWriter::new(stream);
+    let hello = handshake::read_hello(&mut reader)
+        .context("invalid handshake")?
+        .context("no `hello` received")?;
+    let player_id = hello
+        .session_token
+        .as_deref()
+        .and_then(|token| sessions.find_player(token))
+        .context("unknown session token")?;
+
+    handshake::write_welcome(
+        &mut writer,
+        &hello,
+        Some(player_id),
+        Some(sessions.token(player_id)),
+    )?;
+    let endpoint = Box::new(StreamEndpoint::new(reader, writer));
+    ensure!(sessions.reconnect(player_id, endpoint), "the game is over");
+    info!("Player #{player_id} is reconnecting");
+    Ok(())
+}
+
+fn spawn_reconnect_listener(socket_address: SocketAddr, sessions: Arc<Sessions>) -> Result<()> {
+    let listener = TcpListener::bind(socket_address)
+        .with_context(|| format!("failed to bind reconnect listener to {socket_address}"))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .context("failed to accept connection")
+                .and_then(|stream| accept_reconnect(stream, &sessions));
+            if let Err(err) = result {
+                warn!("failed to reconnect a player: {err:#}");
+            }
+        }
+    });
+    Ok(())
+}
+
+type AcceptedEndpoint = (
+    EndpointTag,
+    Option<String>,
+    StreamEndpoint<BufReader<TcpStream>, BufWriter<TcpStream>>,
+);
+
+fn accept_endpoint(
+    stream: TcpStream,
+    tag: EndpointTag,
+    sessions: Option<&Sessions>,
+) -> Result<AcceptedEndpoint> {
+    let mut reader = BufReader::new(stream.try_clone().context("failed to clone fd")?);
+    let mut writer = BufWriter::new(stream);
+    let name = accept_handshake(&mut reader, &mut writer, tag, sessions)?;
+    Ok((tag, name, StreamEndpoint::new(reader, writer)))
+}
+
 fn spawn_listener(
     socket_address: SocketAddr,
     tags: Vec<EndpointTag>,
-) -> thread::JoinHandle<Result<Vec<(EndpointTag, impl Endpoint)>>> {
+    sessions: Option<Arc<Sessions>>,
+) -> thread::JoinHandle<Result<Vec<AcceptedEndpoint>>> {
     thread::spawn(move || {
         if tags.is_empty() {
             return Ok(vec![]);
@@ -108,26 +334,37 @@ fn spawn_listener(
             tags.len()
         );
 
-        tags.into_iter()
-            .zip(TcpListener::bind(socket_address)?.incoming())
-            .map(|(tag, mb_stream)| {
-                let stream = mb_stream?;
-                let peer_addr = stream.peer_addr()?;
+        let listener = TcpListener::bind(socket_address)?;
+        let mut endpoints = vec![];
+        for tag in tags {
+            // A client that fails the handshake is dropped, and the seat
+            // waits for the next connection.
+            loop {
+                let (stream, peer_addr) = listener.accept()?;
                 info!("incomming connection: {peer_addr} -> {socket_address}");
-
-                let reader = BufReader::new(stream.try_clone().context("failed to clone fd")?);
-                let writer = BufWriter::new(stream);
-                let endpoint = JsonEndpoint::new(reader, writer);
-
-                Ok((tag, endpoint))
-            })
-            .collect()
+                match accept_endpoint(stream, tag, sessions.as_deref()) {
+                    Ok(endpoint) => {
+                        endpoints.push(endpoint);
+                        break;
+                    }
+                    Err(err) => warn!("handshake with {peer_addr} failed: {err:#}"),
+                }
+            }
+        }
+        Ok(endpoints)
     })
 }
 
+type Endpoints<P, S> = (
+    PlayerIndexedVector<P>,
+    PlayerIndexedVector<PlayerInfo>,
+    Vec<S>,
+);
+
 fn get_endpoints(
     args: &Arguments,
-) -> Result<(PlayerIndexedVector<impl Endpoint>, Vec<impl Endpoint>)> {
+    sessions: Option<&Arc<Sessions>>,
+) -> Result<Endpoints<impl Endpoint + Send + 'static, impl Endpoint + Send + 'static>> {
     let port_to_endpoint_tags = get_port_to_endpoint_tags(args);
 
     let mut handles = vec![];
@@ -135,31 +372,59 @@ fn get_endpoints(
         let socket_addr = format!("{}:{}", args.address, port)
             .parse()
             .with_context(|| format!("invalid socket address: {}:{}", args.address, port))?;
-        let handle = spawn_listener(socket_addr, endpoint_tags);
+        let handle = spawn_listener(socket_addr, endpoint_tags, sessions.cloned());
         handles.push(handle);
     }
 
     let mut players = PlayerIndexedVector::new(args.player_count);
+    let mut names = PlayerIndexedVector::<Option<String>>::new(args.player_count);
     let mut spectators = vec![];
     for handle in handles {
-        for (tag, endpoint) in handle.join().unwrap()? {
+        for (tag, name, endpoint) in handle.join().unwrap()? {
             match tag {
-                EndpointTag::Player(player_id) => players[player_id] = Some(endpoint),
+                EndpointTag::Player(player_id) => {
+                    players[player_id] = Some(endpoint);
+                    names[player_id] = name;
+                }
                 EndpointTag::Spectator => spectators.push(endpoint),
             }
         }
     }
 
     let players = players.mapped(|e| e.unwrap());
+    let player_infos = names
+        .iter()
+        .map(|(player_id, name)| PlayerInfo {
+            user_name: name
+                .clone()
+                .unwrap_or_else(|| format!("Player #{player_id}")),
+        })
+        .collect();
 
-    Ok((players, spectators))
+    Ok((players, player_infos, spectators))
+}
+
+fn print_report(report: GameReport) {
+    let scores = report
+        .players
+        .iter()
+        .map(|player| {
+            let bot_mark = if player.is_bot { " (bot)" } else { "" };
+            format!("{}{bot_mark}: {}", player.name, player.score)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let winner = report.winner.as_deref().unwrap_or("nobody (tie)");
+    println!("Game #{}: {scores}; winner is {winner}", report.game_num);
 }
 
 fn main() -> Result<()> {
     let args = Arguments::parse();
     ensure!(
-        (1..=4).contains(&args.player_count),
-        "player count should be from 1 to 4"
+        args.player_ports.len() <= args.player_count,
+        "got {} player ports for {} players",
+        args.player_ports.len(),
+        args.player_count
     );
 
     stderrlog::new()
@@ -168,8 +433,111 @@ fn main() -> Result<()> {
         .init()
         .unwrap();
 
-    let (player_endpoints, spectator_endpoints) = get_endpoints(&args)?;
-    Server::new(player_endpoints, spectator_endpoints).run(args.tick_count);
+    if args.legacy_player_ports().iter().any(Option::is_some) {
+        warn!("--p1..--p4 are deprecated, use --ports with a port per player instead");
+    }
+
+    if let Some(path) = &args.validate {
+        let replay =
+            GameReplay::load(path).with_context(|| format!("failed to load replay {path:?}"))?;
+        let tick_count = validate_replay(&replay).context("replay is inconsistent")?;
+        println!("Replay is consistent, {tick_count} tick(s) validated");
+        return Ok(());
+    }
+
+    let time_budget = TimeBudget {
+        per_tick: args.tick_timeout_ms.map(Duration::from_millis),
+        total: args.time_budget_ms.map(Duration::from_millis),
+    };
+
+    if args.lobby {
+        let address = format!("{}:{}", args.address, args.default_player_port);
+        let address = address
+            .parse()
+            .with_context(|| format!("invalid socket address: {address}"))?;
+        let config = LobbyConfig {
+            players_per_game: args.player_count,
+            fill_timeout: Duration::from_millis(args.lobby_fill_ms),
+            max_concurrent_games: args.max_games,
+            tick_count: args.tick_count,
+            rules: args.rules.into(),
+            time_budget,
+        };
+        let lobby = Lobby::bind(address, config)
+            .with_context(|| format!("failed to bind lobby to {address}"))?;
+        println!("Lobby is waiting for players on {address}");
+        lobby.run(print_report)?;
+        return Ok(());
+    }
+
+    let web_spectators = match args.web_port {
+        Some(port) => {
+            let address = format!("{}:{port}", args.address)
+                .parse()
+                .with_context(|| format!("invalid socket address: {}:{port}", args.address))?;
+            let web_spectators = WebSpectators::bind(address)
+                .with_context(|| format!("failed to bind web viewer to {address}"))?;
+            println!("Web viewer is at http://{}", web_spectators.local_addr());
+            Some(web_spectators)
+        }
+        None => None,
+    };
+
+    let (sessions, reconnects) = match args.reconnect_port {
+        Some(port) => {
+            let (sessions, reconnects) = Sessions::new(args.player_count);
+            let sessions = Arc::new(sessions);
+            let address = format!("{}:{port}", args.address)
+                .parse()
+                .with_context(|| format!("invalid socket address: {}:{port}", args.address))?;
+            spawn_reconnect_listener(address, sessions.clone())?;
+            (Some(sessions), Some(reconnects))
+        }
+        None => (None, None),
+    };
+
+    let (player_endpoints, player_infos, spectator_endpoints) =
+        get_endpoints(&args, sessions.as_ref())?;
+    let spectator_endpoints = spectator_endpoints
+        .into_iter()
+        .map(|endpoint| Box::new(endpoint) as Box<dyn Endpoint + Send>)
+        .chain(web_spectators.map(|endpoint| Box::new(endpoint) as Box<dyn Endpoint + Send>));
+    let mut server = Server::new(player_endpoints, spectator_endpoints)
+        .time_budget(time_budget)
+        .rules(args.rules.into())
+        .player_infos(player_infos.map(PlayerInfo::clone))
+        .disconnect_policy(args.disconnect_policy);
+    if let Some(reconnects) = reconnects {
+        server = server.reconnects(reconnects, Duration::from_millis(args.reconnect_grace_ms));
+    }
+    if let Some(seed) = args.seed {
+        server = server.seed(seed);
+    }
+    if let Some(path) = &args.replay {
+        let player_infos = player_infos
+            .iter()
+            .map(|(player_id, info)| (player_id.to_string(), info.clone()))
+            .collect();
+        let replay = ReplayWriter::create(path, &player_infos)
+            .with_context(|| format!("failed to create replay file {path:?}"))?;
+        server = server.record_replay(replay);
+    }
+    let result = server.run(args.tick_count);
+    for (player_id, player) in result.players.iter() {
+        if player.disqualified {
+            println!("Player #{player_id} was disqualified for exceeding the time budget");
+        }
+        info!(
+            "Player #{player_id}: mean answer time {:?}, max {:?}, {} late tick(s)",
+            player.timing.mean(),
+            player.timing.max,
+            player.timing.late_ticks
+        );
+    }
+    match result.winner {
+        Some(player_id) => println!("Winner is Player #{player_id}!"),
+        None => println!("There is no winner (tie)"),
+    }
 
     Ok(())
 }
@@ -1,108 +1,370 @@
-use std::io;
+use std::{
+    io,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
 
 use log::*;
-use paperio_proto::{Command, Message};
+use paperio_proto::{
+    replay::ReplayWriter,
+    traits::{Bot, SUPPORTED_ENCODINGS},
+    Command, GameParams, Message, PlayerInfo, Rules, TickLog,
+};
 
 use crate::{
+    bot::SimpleBot,
     endpoint::Endpoint,
     game::{Game, PlayerId},
     player_vec::PlayerIndexedVector,
+    session::{DisconnectPolicy, Reconnect},
+    worker::{CommandResult, EndpointWorker},
 };
 
+/// Limits on how long players may think, in the style of AiCups.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeBudget {
+    /// Time to answer a single tick. A late answer counts as `NoOp`.
+    pub per_tick: Option<Duration>,
+    /// Time to answer all ticks of the game. A player who runs out of it is disqualified.
+    pub total: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TimingStats {
+    /// Time spent answering ticks; late answers are counted up to the tick limit.
+    pub total: Duration,
+    pub max: Duration,
+    pub answered_ticks: u32,
+    pub late_ticks: u32,
+}
+
+impl TimingStats {
+    pub fn mean(&self) -> Duration {
+        let ticks = self.answered_ticks + self.late_ticks;
+        if ticks == 0 {
+            Duration::ZERO
+        } else {
+            self.total / ticks
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration, is_late: bool) {
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        if is_late {
+            self.late_ticks += 1;
+        } else {
+            self.answered_ticks += 1;
+        }
+    }
+}
+
 pub struct PlayerResult {
     pub score: u32,
     pub io_error: Option<io::Error>,
+    pub disqualified: bool,
+    pub timing: TimingStats,
+}
+
+pub struct GameResult {
+    pub winner: Option<PlayerId>,
+    pub players: PlayerIndexedVector<PlayerResult>,
 }
 
-pub struct Server<'a> {
-    player_endpoints: PlayerIndexedVector<Box<dyn Endpoint + 'a>>,
-    spectator_endpoints: Vec<Box<dyn Endpoint + 'a>>,
+/// How long delivery of the final messages is awaited after the game ends.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct Server {
+    player_workers: PlayerIndexedVector<EndpointWorker>,
+    spectator_workers: Vec<EndpointWorker>,
     player_io_errors: PlayerIndexedVector<Option<io::Error>>,
+    disqualified: PlayerIndexedVector<bool>,
+    timings: PlayerIndexedVector<TimingStats>,
+    time_budget: TimeBudget,
+    rules: Rules,
+    seed: Option<u64>,
+    player_infos: Option<PlayerIndexedVector<PlayerInfo>>,
+    replay: Option<ReplayWriter>,
+    reconnects: Option<Receiver<Reconnect>>,
+    reconnect_grace: Duration,
+    disconnected_at: PlayerIndexedVector<Option<Instant>>,
+    disconnect_policy: DisconnectPolicy,
+    fallback_bots: PlayerIndexedVector<Option<SimpleBot>>,
 }
 
-impl<'a> Server<'a> {
+impl Server {
     pub fn new(
-        player_endpoints: PlayerIndexedVector<impl Endpoint + 'a>,
-        spectator_endpoints: impl IntoIterator<Item = impl Endpoint + 'a>,
+        player_endpoints: PlayerIndexedVector<impl Endpoint + Send + 'static>,
+        spectator_endpoints: impl IntoIterator<Item = impl Endpoint + Send + 'static>,
     ) -> Self {
         let player_count = player_endpoints.len();
         Self {
-            player_endpoints: player_endpoints.mapped(|e| Box::new(e) as Box<dyn Endpoint>),
-            spectator_endpoints: spectator_endpoints
+            player_workers: player_endpoints.mapped(EndpointWorker::spawn),
+            spectator_workers: spectator_endpoints
                 .into_iter()
-                .map(|e| Box::new(e) as Box<dyn Endpoint>)
+                .map(EndpointWorker::spawn)
                 .collect(),
             player_io_errors: PlayerIndexedVector::new(player_count),
+            disqualified: PlayerIndexedVector::new(player_count),
+            timings: PlayerIndexedVector::new(player_count),
+            time_budget: TimeBudget::default(),
+            rules: Rules::default(),
+            seed: None,
+            player_infos: None,
+            replay: None,
+            reconnects: None,
+            reconnect_grace: Duration::ZERO,
+            disconnected_at: PlayerIndexedVector::new(player_count),
+            disconnect_policy: DisconnectPolicy::default(),
+            fallback_bots: PlayerIndexedVector::new(player_count),
         }
     }
 
-    pub fn run(mut self, ticks_amount: usize) -> PlayerIndexedVector<PlayerResult> {
-        let mut game = Game::new(self.player_endpoints.len());
-        let params = game.get_game_params();
+    /// Record every message sent to spectators into the replay.
+    pub fn record_replay(mut self, replay: ReplayWriter) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn time_budget(mut self, time_budget: TimeBudget) -> Self {
+        self.time_budget = time_budget;
+        self
+    }
+
+    pub fn rules(mut self, rules: Rules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Shuffles spawn positions with the given seed. Together with the commands
+    /// recorded in the replay it makes the game reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Player names sent to everyone in `GameParams::players`.
+    pub fn player_infos(mut self, player_infos: PlayerIndexedVector<PlayerInfo>) -> Self {
+        self.player_infos = Some(player_infos);
+        self
+    }
+
+    /// Lets players who lost connection take their seats back within
+    /// the grace period, see [`Sessions`](crate::session::Sessions).
+    pub fn reconnects(mut self, reconnects: Receiver<Reconnect>, grace_period: Duration) -> Self {
+        self.reconnects = Some(reconnects);
+        self.reconnect_grace = grace_period;
+        self
+    }
+
+    pub fn disconnect_policy(mut self, policy: DisconnectPolicy) -> Self {
+        self.disconnect_policy = policy;
+        self
+    }
 
-        self.send_to_all(&Message::StartGame(params));
+    /// Plays `ticks_amount` ticks, or a different number if the rules end the game
+    /// early or add sudden death ticks.
+    pub fn run(mut self, ticks_amount: usize) -> GameResult {
+        let player_count = self.player_workers.len();
+        let mut game = match self.seed {
+            Some(seed) => Game::with_seed(player_count, self.rules, seed),
+            None => Game::new(player_count, self.rules),
+        };
+        let players = self
+            .player_infos
+            .iter()
+            .flat_map(|infos| infos.iter())
+            .map(|(player_id, info)| (player_id.to_string(), info.clone()))
+            .collect();
+        let params = GameParams {
+            encodings: SUPPORTED_ENCODINGS.to_vec(),
+            players,
+            ..game.get_game_params()
+        };
 
-        for tick in 0..ticks_amount {
+        if let Some(seed) = self.seed {
+            self.write_replay(|replay| replay.write_seed(seed));
+        }
+
+        self.send_to_all(&Message::StartGame(params.clone()));
+
+        for tick in 0.. {
+            if game.is_finished(ticks_amount) {
+                break;
+            }
             debug!("tick #{tick}");
+            self.accept_reconnects(&params);
 
-            for player_id in self.player_endpoints.iter_player_ids() {
+            // Workers deliver messages in background, so every endpoint
+            // gets its tick at the same time and thinks in parallel.
+            let mut requests = vec![];
+            for player_id in self.player_workers.iter_player_ids() {
+                if !self.is_connected(player_id) {
+                    continue;
+                }
                 let world = game.get_player_world(player_id);
-                self.send_to_player(player_id, &Message::Tick(world));
+                let worker = &mut self.player_workers[player_id];
+                worker.send(Message::Tick(world));
+                requests.push((player_id, worker.request_command()));
             }
 
             let spectator_world = game.get_spectator_world();
             self.send_to_spectators(&Message::Tick(spectator_world));
+            let spectator_requests = self
+                .spectator_workers
+                .iter_mut()
+                .map(EndpointWorker::request_command)
+                .collect::<Vec<_>>();
+
+            let mut tick_log = TickLog {
+                tick_num: game.tick_num(),
+                commands: Default::default(),
+                disqualified: vec![],
+                stopped: vec![],
+                state_hash: 0,
+            };
 
-            for player_id in self.player_endpoints.iter_player_ids() {
-                let mb_command = self.try_get_player_command(player_id);
+            // Deadlines are counted from the moment of request, so waiting for
+            // one player doesn't eat into the time of the others.
+            for (player_id, request_id) in requests {
+                let mb_command = self.try_get_player_command(player_id, request_id);
+                if let Some(command) = mb_command {
+                    tick_log.commands.insert(player_id.to_string(), command);
+                }
                 if let Some(Command::ChangeDirection(dir)) = mb_command {
                     game.try_change_direction(player_id, dir);
                 }
+                if self.disqualified[player_id] {
+                    tick_log.disqualified.push(player_id.to_string());
+                    game.disqualify(player_id);
+                }
             }
+            self.apply_disconnect_policy(&mut game, &mut tick_log);
 
-            self.sync_with_spectators();
+            self.sync_with_spectators(spectator_requests);
 
             game.tick();
+
+            tick_log.state_hash = game.state_hash();
+            trace!("state hash {:#018x}", tick_log.state_hash);
+            self.write_replay(|replay| replay.write_tick_log(tick_log));
         }
 
         self.send_to_all(&Message::EndGame {});
+        if let Some(Err(err)) = self.replay.take().map(ReplayWriter::finish) {
+            error!("failed to finish replay: {err}");
+        }
 
-        let mb_leader_id = game.leader_id();
-        match mb_leader_id {
-            Some(player_id) => println!("Winner is Player #{player_id}!"),
-            None => println!("There is no winner (tie)"),
+        let close_deadline = Instant::now() + CLOSE_TIMEOUT;
+        let workers = self
+            .player_workers
+            .into_iter()
+            .chain(self.spectator_workers);
+        for worker in workers {
+            worker.close(close_deadline);
         }
 
-        game.get_player_scores()
+        let players = game
+            .get_player_scores()
             .into_iter()
             .zip(self.player_io_errors)
-            .map(|(score, io_error)| PlayerResult { score, io_error })
-            .collect::<Vec<_>>()
-            .into()
+            .zip(self.disqualified)
+            .zip(self.timings)
+            .map(|(((score, io_error), disqualified), timing)| PlayerResult {
+                score,
+                io_error,
+                disqualified,
+                timing,
+            })
+            .collect();
+
+        GameResult {
+            winner: game.winner(),
+            players,
+        }
     }
 
-    fn send_to_spectators(&mut self, message: &Message) {
-        for endpoint in self.spectator_endpoints.iter_mut() {
-            if let Err(err) = endpoint.send_message(message) {
-                error!("failed to send message to spectator: {err}");
+    /// Reconnected players get `StartGame` again and continue from the current tick.
+    fn accept_reconnects(&mut self, params: &GameParams) {
+        let Some(reconnects) = &self.reconnects else {
+            return;
+        };
+        for Reconnect {
+            player_id,
+            endpoint,
+        } in reconnects.try_iter().collect::<Vec<_>>()
+        {
+            if self.disqualified[player_id] {
+                continue;
+            }
+            let is_expired = self.disconnected_at[player_id]
+                .is_some_and(|disconnected_at| disconnected_at.elapsed() > self.reconnect_grace);
+            if is_expired {
+                warn!("Player #{player_id} came back after the grace period and is not accepted");
+                continue;
             }
+
+            info!("Player #{player_id} reconnected");
+            let worker = EndpointWorker::spawn(endpoint);
+            worker.send(Message::StartGame(params.clone()));
+            self.player_workers[player_id] = worker;
+            self.player_io_errors[player_id] = None;
+            self.disconnected_at[player_id] = None;
+            self.fallback_bots[player_id] = None;
         }
     }
 
-    fn send_to_player(&mut self, player_id: PlayerId, message: &Message) {
-        if self.player_io_errors[player_id].is_some() {
-            return;
+    fn apply_disconnect_policy(&mut self, game: &mut Game, tick_log: &mut TickLog) {
+        for player_id in self.player_workers.iter_player_ids() {
+            let is_disconnected =
+                self.player_io_errors[player_id].is_some() && !self.disqualified[player_id];
+
+            let stopped = is_disconnected && self.disconnect_policy == DisconnectPolicy::Stop;
+            game.set_stopped(player_id, stopped);
+            if stopped {
+                tick_log.stopped.push(player_id.to_string());
+            }
+
+            if is_disconnected
+                && self.disconnect_policy == DisconnectPolicy::Bot
+                && !game.has_lost(player_id)
+            {
+                let bot = self.fallback_bots[player_id]
+                    .get_or_insert_with(|| SimpleBot::new(player_id.get() as u64));
+                let direction = bot.on_tick(game.get_player_world(player_id));
+                let command = Command::ChangeDirection(direction);
+                tick_log.commands.insert(player_id.to_string(), command);
+                game.try_change_direction(player_id, direction);
+            }
+        }
+    }
+
+    fn is_connected(&self, player_id: PlayerId) -> bool {
+        self.player_io_errors[player_id].is_none() && !self.disqualified[player_id]
+    }
+
+    fn write_replay(&mut self, write: impl FnOnce(&mut ReplayWriter) -> io::Result<()>) {
+        if let Some(replay) = self.replay.as_mut() {
+            if let Err(err) = write(replay) {
+                error!("failed to write to replay, recording stopped: {err}");
+                self.replay = None;
+            }
         }
-        let endpoint = &mut self.player_endpoints[player_id];
-        if let Err(err) = endpoint.send_message(message) {
-            error!("failed to send message to Player #{player_id}: {err}");
-            self.player_io_errors[player_id] = Some(err);
+    }
+
+    fn send_to_spectators(&mut self, message: &Message) {
+        self.write_replay(|replay| replay.write_message(message));
+
+        for worker in self.spectator_workers.iter() {
+            worker.send(message.clone());
         }
     }
 
     fn send_to_players(&mut self, message: &Message) {
-        for player_id in self.player_endpoints.iter_player_ids() {
-            self.send_to_player(player_id, message);
+        for player_id in self.player_workers.iter_player_ids() {
+            if self.player_io_errors[player_id].is_none() {
+                self.player_workers[player_id].send(message.clone());
+            }
         }
     }
 
@@ -111,26 +373,247 @@ impl<'a> Server<'a> {
         self.send_to_spectators(message);
     }
 
-    fn try_get_player_command(&mut self, player_id: PlayerId) -> Option<Command> {
-        if self.player_io_errors[player_id].is_some() {
-            return None;
-        }
-        let endpoint = &mut self.player_endpoints[player_id];
-        match endpoint.get_command() {
-            Ok(cmd) => Some(cmd),
-            Err(err) => {
+    /// The player can't take longer than the tick limit or the rest of its total budget.
+    fn get_player_deadline(&self, player_id: PlayerId) -> Option<Instant> {
+        let remaining_total = self
+            .time_budget
+            .total
+            .map(|total| total.saturating_sub(self.timings[player_id].total));
+        let limit = match (self.time_budget.per_tick, remaining_total) {
+            (Some(per_tick), Some(remaining)) => Some(per_tick.min(remaining)),
+            (limit, None) | (None, limit) => limit,
+        };
+        let requested_at = self.player_workers[player_id].requested_at();
+        limit.map(|limit| requested_at + limit)
+    }
+
+    fn try_get_player_command(&mut self, player_id: PlayerId, request_id: u64) -> Option<Command> {
+        let deadline = self.get_player_deadline(player_id);
+        let worker = &mut self.player_workers[player_id];
+        let requested_at = worker.requested_at();
+
+        let mb_command = match worker.wait_command(request_id, deadline) {
+            CommandResult::Received { command, elapsed } => {
+                self.timings[player_id].record(elapsed, false);
+                Some(command)
+            }
+            CommandResult::TimedOut => {
+                warn!("Player #{player_id} is late, its command is ignored");
+                let elapsed = deadline.map_or(Duration::ZERO, |d| d - requested_at);
+                self.timings[player_id].record(elapsed, true);
+                None
+            }
+            CommandResult::Failed(err) => {
                 error!("failed to get command from Player #{player_id}: {err}");
                 self.player_io_errors[player_id] = Some(err);
+                self.disconnected_at[player_id] = Some(Instant::now());
                 None
             }
+        };
+
+        if let Some(total) = self.time_budget.total {
+            if self.timings[player_id].total >= total {
+                warn!("Player #{player_id} exceeded its time budget and is disqualified");
+                self.disqualified[player_id] = true;
+            }
         }
+
+        mb_command
     }
 
-    fn sync_with_spectators(&mut self) {
-        for endpoint in self.spectator_endpoints.iter_mut() {
-            if let Err(err) = endpoint.get_command() {
-                error!("failed to sync with spectator: {err}");
+    /// Spectators acknowledge every tick, so they never fall behind the game.
+    /// They get the same time per tick as players: a late spectator isn't
+    /// waited for, and a spectator that fails is dropped.
+    fn sync_with_spectators(&mut self, request_ids: Vec<u64>) {
+        let workers = std::mem::take(&mut self.spectator_workers);
+        for (mut worker, request_id) in workers.into_iter().zip(request_ids) {
+            let deadline = self
+                .time_budget
+                .per_tick
+                .map(|per_tick| worker.requested_at() + per_tick);
+            match worker.wait_command(request_id, deadline) {
+                CommandResult::Failed(err) => error!("failed to sync with spectator: {err}"),
+                CommandResult::TimedOut => {
+                    warn!("spectator is late, the game goes on without it");
+                    self.spectator_workers.push(worker);
+                }
+                CommandResult::Received { .. } => self.spectator_workers.push(worker),
             }
         }
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::endpoint::BotEndpoint;
+
+    use paperio_proto::{Direction, World};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Player that thinks for a fixed time and then always turns the same way.
+    struct SlowPlayer {
+        delay: Duration,
+        direction: Direction,
+    }
+
+    impl Endpoint for SlowPlayer {
+        fn send_message(&mut self, _message: &Message) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn get_command(&mut self) -> io::Result<Command> {
+            thread::sleep(self.delay);
+            Ok(Command::ChangeDirection(self.direction))
+        }
+    }
+
+    /// Spectator that keeps the worlds of all ticks.
+    struct RecordingSpectator {
+        worlds: Arc<Mutex<Vec<World>>>,
+    }
+
+    impl Endpoint for RecordingSpectator {
+        fn send_message(&mut self, message: &Message) -> io::Result<()> {
+            if let Message::Tick(world) = message {
+                self.worlds.lock().unwrap().push(world.clone());
+            }
+            Ok(())
+        }
+
+        fn get_command(&mut self) -> io::Result<Command> {
+            Ok(Command::NoOp)
+        }
+    }
+
+    /// Plays the slow player against a bot and returns the result with the
+    /// worlds spectators saw.
+    fn play_slow_player(
+        delay: Duration,
+        time_budget: TimeBudget,
+        tick_count: usize,
+    ) -> (GameResult, Vec<World>) {
+        let endpoints = vec![
+            Box::new(SlowPlayer {
+                delay,
+                direction: Direction::Up,
+            }) as Box<dyn Endpoint + Send>,
+            Box::new(BotEndpoint::new(SimpleBot::new(1))),
+        ]
+        .into();
+        let worlds = Arc::new(Mutex::new(vec![]));
+        let spectator = RecordingSpectator {
+            worlds: worlds.clone(),
+        };
+        let result = Server::new(endpoints, [spectator])
+            .time_budget(time_budget)
+            .seed(1)
+            .run(tick_count);
+        let worlds = worlds.lock().unwrap().clone();
+        (result, worlds)
+    }
+
+    #[test]
+    fn late_answer_counts_as_noop() {
+        let per_tick = Duration::from_millis(10);
+        let time_budget = TimeBudget {
+            per_tick: Some(per_tick),
+            total: None,
+        };
+        let (result, worlds) = play_slow_player(Duration::from_millis(50), time_budget, 5);
+
+        let slow = &result.players[PlayerId::new(1).unwrap()];
+        assert!(!slow.disqualified);
+        assert_eq!(slow.timing.late_ticks, 5);
+        assert_eq!(slow.timing.answered_ticks, 0);
+        // Late ticks are counted up to the tick limit.
+        assert_eq!(slow.timing.total, 5 * per_tick);
+        assert_eq!(slow.timing.max, per_tick);
+        assert_eq!(slow.timing.mean(), per_tick);
+        // The turn up never comes in time.
+        assert_eq!(worlds.len(), 5);
+        for world in &worlds {
+            assert_eq!(world.players["1"].direction, Some(Direction::Left));
+        }
+
+        let bot = &result.players[PlayerId::new(2).unwrap()];
+        assert_eq!(bot.timing.late_ticks, 0);
+        assert_eq!(bot.timing.answered_ticks, 5);
+    }
+
+    #[test]
+    fn total_budget_disqualifies() {
+        let total = Duration::from_millis(100);
+        let time_budget = TimeBudget {
+            per_tick: None,
+            total: Some(total),
+        };
+        let (result, worlds) = play_slow_player(Duration::from_millis(40), time_budget, 10);
+
+        // Two answers fit into the budget, the third one runs out of it.
+        let slow = &result.players[PlayerId::new(1).unwrap()];
+        assert!(slow.disqualified);
+        assert_eq!(slow.timing.answered_ticks, 2);
+        assert_eq!(slow.timing.late_ticks, 1);
+        assert_eq!(slow.timing.total, total);
+        assert!(slow.timing.max >= Duration::from_millis(40));
+
+        // The snake is removed from the field right away.
+        let snake = &worlds.last().unwrap().players["1"];
+        assert!(snake.has_lost);
+        assert!(snake.territory.is_empty() && snake.lines.is_empty());
+        assert!(!result.players[PlayerId::new(2).unwrap()].disqualified);
+    }
+
+    #[test]
+    fn timing_stats() {
+        let mut timing = TimingStats::default();
+        assert_eq!(timing.mean(), Duration::ZERO);
+        timing.record(Duration::from_millis(10), false);
+        timing.record(Duration::from_millis(30), false);
+        timing.record(Duration::from_millis(20), true);
+        assert_eq!(timing.total, Duration::from_millis(60));
+        assert_eq!(timing.max, Duration::from_millis(30));
+        assert_eq!(timing.mean(), Duration::from_millis(20));
+        assert_eq!(timing.answered_ticks, 2);
+        assert_eq!(timing.late_ticks, 1);
+    }
+
+    /// Spectator that takes every message but never acknowledges a tick.
+    struct HungSpectator;
+
+    impl Endpoint for HungSpectator {
+        fn send_message(&mut self, _message: &Message) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn get_command(&mut self) -> io::Result<Command> {
+            loop {
+                thread::park();
+            }
+        }
+    }
+
+    #[test]
+    fn hung_spectator_does_not_stop_the_game() {
+        let endpoints = (1..=2)
+            .map(|seed| {
+                Box::new(BotEndpoint::new(SimpleBot::new(seed))) as Box<dyn Endpoint + Send>
+            })
+            .collect();
+        let spectators: Vec<Box<dyn Endpoint + Send>> = vec![Box::new(HungSpectator)];
+        let started_at = Instant::now();
+        Server::new(endpoints, spectators)
+            .time_budget(TimeBudget {
+                per_tick: Some(Duration::from_millis(20)),
+                total: None,
+            })
+            .run(10);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,158 @@
+//! Session tokens let a player whose connection dropped take its seat back.
+//! Tokens are handed out in `Welcome`, a reconnecting client sends its token
+//! in `Hello` and its new endpoint is passed to the running [`Server`].
+//!
+//! [`Server`]: crate::server::Server
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use clap::ValueEnum;
+use rand::{rngs::OsRng, Rng};
+
+use crate::{endpoint::Endpoint, game::PlayerId, player_vec::PlayerIndexedVector};
+
+/// What happens to the snake of a disconnected player.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DisconnectPolicy {
+    /// Keep moving in the last direction.
+    #[default]
+    Straight,
+    /// Stand still until the player reconnects.
+    Stop,
+    /// A built-in bot plays for the player until it reconnects.
+    Bot,
+}
+
+pub struct Reconnect {
+    pub player_id: PlayerId,
+    pub endpoint: Box<dyn Endpoint + Send>,
+}
+
+pub struct Sessions {
+    tokens: PlayerIndexedVector<String>,
+    reconnects: Sender<Reconnect>,
+}
+
+impl Sessions {
+    /// Creates random tokens for all players. Reconnects are received
+    /// from the returned receiver, see `Server::reconnects`.
+    pub fn new(player_count: usize) -> (Self, Receiver<Reconnect>) {
+        // Tokens guard seats from other players, so they must be unguessable.
+        let tokens = (0..player_count)
+            .map(|_| format!("{:032x}", OsRng.gen::<u128>()))
+            .collect();
+
+        let (sender, receiver) = mpsc::channel();
+        let sessions = Self {
+            tokens,
+            reconnects: sender,
+        };
+        (sessions, receiver)
+    }
+
+    pub fn token(&self, player_id: PlayerId) -> &str {
+        &self.tokens[player_id]
+    }
+
+    pub fn find_player(&self, token: &str) -> Option<PlayerId> {
+        self.tokens
+            .iter()
+            .find(|(_, player_token)| player_token.as_str() == token)
+            .map(|(player_id, _)| player_id)
+    }
+
+    /// Hands the new endpoint of the player over to the server.
+    /// Returns `false` if the game is already over.
+    pub fn reconnect(&self, player_id: PlayerId, endpoint: Box<dyn Endpoint + Send>) -> bool {
+        let reconnect = Reconnect {
+            player_id,
+            endpoint,
+        };
+        self.reconnects.send(reconnect).is_ok()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::Server;
+    use paperio_proto::{Command, Message};
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    type OnFail = Box<dyn FnOnce() + Send>;
+
+    /// Records received messages and breaks down on the given tick.
+    struct TestEndpoint {
+        messages: Arc<Mutex<Vec<Message>>>,
+        fail: Option<(u32, OnFail)>,
+    }
+
+    impl Endpoint for TestEndpoint {
+        fn send_message(&mut self, message: &Message) -> io::Result<()> {
+            self.messages.lock().unwrap().push(message.clone());
+            let tick_num = match message {
+                Message::Tick(world) => world.tick_num,
+                _ => return Ok(()),
+            };
+            if self.fail.as_ref().is_some_and(|(at, _)| *at == tick_num) {
+                let (_, on_fail) = self.fail.take().unwrap();
+                on_fail();
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            Ok(())
+        }
+
+        fn get_command(&mut self) -> io::Result<Command> {
+            Ok(Command::NoOp)
+        }
+    }
+
+    #[test]
+    fn player_reconnects() {
+        let (sessions, reconnects) = Sessions::new(2);
+        let sessions = Arc::new(sessions);
+        let one = PlayerId::new(1).unwrap();
+        assert_eq!(sessions.find_player(sessions.token(one)), Some(one));
+        assert_eq!(sessions.find_player("unknown"), None);
+        let two = PlayerId::new(2).unwrap();
+        assert_ne!(sessions.token(one), sessions.token(two));
+        assert_eq!(sessions.token(one).len(), 32);
+
+        let messages = Arc::new(Mutex::new(vec![]));
+        let reconnecting = TestEndpoint {
+            messages: messages.clone(),
+            fail: None,
+        };
+        let reconnect: OnFail = {
+            let sessions = sessions.clone();
+            Box::new(move || assert!(sessions.reconnect(one, Box::new(reconnecting))))
+        };
+        let endpoints: Vec<Box<dyn Endpoint + Send>> = vec![
+            Box::new(TestEndpoint {
+                messages: Default::default(),
+                fail: Some((3, reconnect)),
+            }),
+            Box::new(TestEndpoint {
+                messages: Default::default(),
+                fail: None,
+            }),
+        ];
+
+        let result = Server::new(endpoints.into(), Vec::<Box<dyn Endpoint + Send>>::new())
+            .reconnects(reconnects, Duration::from_secs(10))
+            .disconnect_policy(DisconnectPolicy::Stop)
+            .run(10);
+        assert!(result.players[one].io_error.is_none());
+
+        let messages = messages.lock().unwrap();
+        assert!(matches!(messages[0], Message::StartGame(_)));
+        assert!(matches!(&messages[1], Message::Tick(world) if world.tick_num == 4));
+        assert_eq!(messages.last(), Some(&Message::EndGame {}));
+    }
+}
@@ -0,0 +1,219 @@
+use std::{error::Error, fmt};
+
+use paperio_proto::{Command, GameReplay, Message};
+
+use crate::game::{Game, PlayerId, MAX_PLAYER_COUNT};
+
+#[derive(Debug)]
+pub enum ValidationError {
+    /// The replay has no `StartGame` or `Tick` messages to take the game setup from.
+    MissingSetup,
+    /// The replay was recorded without tick logs.
+    MissingTickLogs,
+    /// The replay was recorded without a seed, so spawn positions can't be reproduced.
+    MissingSeed,
+    /// The replay has more players than a game can have, or none at all.
+    InvalidPlayerCount(usize),
+    UnknownPlayer(String),
+    TickMismatch {
+        expected_tick_num: u32,
+        actual_tick_num: u32,
+    },
+    StateMismatch {
+        tick_num: u32,
+        expected_hash: u64,
+        actual_hash: u64,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSetup => write!(f, "replay has no game setup"),
+            Self::MissingTickLogs => write!(f, "replay has no tick logs"),
+            Self::MissingSeed => write!(f, "replay has no seed"),
+            Self::InvalidPlayerCount(count) => write!(
+                f,
+                "replay has {count} player(s), expected from 1 to {MAX_PLAYER_COUNT}"
+            ),
+            Self::UnknownPlayer(id) => write!(f, "unknown player {id:?} in tick log"),
+            Self::TickMismatch {
+                expected_tick_num,
+                actual_tick_num,
+            } => write!(
+                f,
+                "tick log #{actual_tick_num} found where #{expected_tick_num} was expected"
+            ),
+            Self::StateMismatch {
+                tick_num,
+                expected_hash,
+                actual_hash,
+            } => write!(
+                f,
+                "state diverged at tick #{tick_num}: \
+                 recorded hash {expected_hash:#018x}, simulated {actual_hash:#018x}"
+            ),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Re-simulates the game from the recorded seed and commands, and checks
+/// the state hash after every tick. Returns the number of validated ticks.
+pub fn validate_replay(replay: &GameReplay) -> Result<usize, ValidationError> {
+    let mut params = None;
+    let mut player_count = None;
+    for message in &replay.messages {
+        match message {
+            Message::StartGame(game_params) => params = Some(game_params.clone()),
+            Message::Tick(world) => {
+                player_count = Some(world.players.len());
+                break;
+            }
+            Message::EndGame {} => break,
+        }
+    }
+    let (Some(params), Some(player_count)) = (params, player_count) else {
+        return Err(ValidationError::MissingSetup);
+    };
+    if !(1..=MAX_PLAYER_COUNT).contains(&player_count) {
+        return Err(ValidationError::InvalidPlayerCount(player_count));
+    }
+    if replay.tick_logs.is_empty() {
+        return Err(ValidationError::MissingTickLogs);
+    }
+    let Some(seed) = replay.seed else {
+        return Err(ValidationError::MissingSeed);
+    };
+
+    let mut game = Game::with_seed(player_count, params.rules, seed);
+    let parse_player_id = |id: &String| {
+        id.parse::<PlayerId>()
+            .ok()
+            .filter(|player_id| player_id.get() <= player_count)
+            .ok_or_else(|| ValidationError::UnknownPlayer(id.clone()))
+    };
+
+    for tick_log in &replay.tick_logs {
+        if tick_log.tick_num != game.tick_num() {
+            return Err(ValidationError::TickMismatch {
+                expected_tick_num: game.tick_num(),
+                actual_tick_num: tick_log.tick_num,
+            });
+        }
+
+        for (id, command) in &tick_log.commands {
+            if let Command::ChangeDirection(direction) = command {
+                game.try_change_direction(parse_player_id(id)?, *direction);
+            }
+        }
+        for id in &tick_log.disqualified {
+            game.disqualify(parse_player_id(id)?);
+        }
+        let stopped = tick_log
+            .stopped
+            .iter()
+            .map(parse_player_id)
+            .collect::<Result<Vec<_>, _>>()?;
+        for player_id in (1..=player_count).filter_map(PlayerId::new) {
+            game.set_stopped(player_id, stopped.contains(&player_id));
+        }
+        game.tick();
+
+        let actual_hash = game.state_hash();
+        if actual_hash != tick_log.state_hash {
+            return Err(ValidationError::StateMismatch {
+                tick_num: tick_log.tick_num,
+                expected_hash: tick_log.state_hash,
+                actual_hash,
+            });
+        }
+    }
+
+    Ok(replay.tick_logs.len())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        bot::SimpleBot,
+        endpoint::{BotEndpoint, Endpoint},
+        server::Server,
+    };
+    use paperio_proto::replay::ReplayWriter;
+
+    use std::{collections::HashMap, fs};
+
+    fn record_game(seed: u64) -> GameReplay {
+        let path = std::env::temp_dir().join(format!("validate-{}.jsonl", std::process::id()));
+        let replay = ReplayWriter::create(&path, &HashMap::new()).unwrap();
+        let endpoints = (1..=2)
+            .map(|seed| {
+                Box::new(BotEndpoint::new(SimpleBot::new(seed))) as Box<dyn Endpoint + Send>
+            })
+            .collect();
+        Server::new(endpoints, Vec::<Box<dyn Endpoint + Send>>::new())
+            .seed(seed)
+            .record_replay(replay)
+            .run(100);
+
+        let replay = GameReplay::load(&path);
+        fs::remove_file(&path).unwrap();
+        replay.unwrap()
+    }
+
+    #[test]
+    fn recorded_game_validates() {
+        let replay = record_game(7);
+        assert_eq!(replay.seed, Some(7));
+        assert_eq!(validate_replay(&replay).unwrap(), replay.tick_logs.len());
+
+        let mut corrupted = replay.clone();
+        corrupted.tick_logs[10].state_hash ^= 1;
+        assert!(matches!(
+            validate_replay(&corrupted),
+            Err(ValidationError::StateMismatch { tick_num, .. })
+                if tick_num == replay.tick_logs[10].tick_num
+        ));
+
+        let mut corrupted = replay.clone();
+        corrupted.tick_logs.remove(10);
+        assert!(matches!(
+            validate_replay(&corrupted),
+            Err(ValidationError::TickMismatch { .. })
+        ));
+
+        let mut corrupted = replay.clone();
+        corrupted.seed = Some(8);
+        assert!(validate_replay(&corrupted).is_err());
+
+        let mut seedless = replay.clone();
+        seedless.seed = None;
+        assert!(matches!(
+            validate_replay(&seedless),
+            Err(ValidationError::MissingSeed)
+        ));
+
+        // Too many players must be an error rather than a panic of the game.
+        let mut crowded = replay;
+        let Some(Message::Tick(world)) = crowded
+            .messages
+            .iter_mut()
+            .find(|message| matches!(message, Message::Tick(_)))
+        else {
+            panic!("replay has no ticks");
+        };
+        let player = world.players["1"].clone();
+        for id in 3..=MAX_PLAYER_COUNT + 1 {
+            world.players.insert(id.to_string(), player.clone());
+        }
+        assert!(matches!(
+            validate_replay(&crowded),
+            Err(ValidationError::InvalidPlayerCount(count)) if count == MAX_PLAYER_COUNT + 1
+        ));
+    }
+}
@@ -0,0 +1,411 @@
+//! Spectators over WebSocket. Any number of browsers can watch the game:
+//! `GET /` serves a built-in viewer, which connects back with a WebSocket
+//! and receives the spectator stream as JSON messages, one per frame.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::*;
+use paperio_proto::{Command, Message};
+use tungstenite::WebSocket;
+
+use crate::endpoint::Endpoint;
+
+const VIEWER_HTML: &str = include_str!("../web/viewer.html");
+
+/// Messages queued for a single viewer. Every tick holds the full state,
+/// so a viewer that falls behind just skips ticks instead of slowing the game.
+const VIEWER_QUEUE_LEN: usize = 16;
+
+/// Time to receive an HTTP request or to write to a viewer.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the accept thread checks whether the spectators are gone.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+const MAX_REQUEST_LEN: usize = 8 << 10;
+
+/// Messages waiting to be sent to a single viewer.
+#[derive(Default)]
+struct ViewerQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<QueuedMessage>,
+    closed: bool,
+}
+
+struct QueuedMessage {
+    json: Arc<str>,
+    droppable: bool,
+}
+
+impl ViewerQueue {
+    /// Queues a message. When the viewer falls behind, its oldest tick is
+    /// dropped to make room, while the game start and end are always delivered.
+    fn push(&self, json: Arc<str>, droppable: bool) {
+        let mut state = self.state.lock().unwrap();
+        if droppable && state.messages.len() >= VIEWER_QUEUE_LEN {
+            if let Some(oldest) = state.messages.iter().position(|m| m.droppable) {
+                state.messages.remove(oldest);
+            }
+        }
+        state.messages.push_back(QueuedMessage { json, droppable });
+        self.ready.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_one();
+    }
+
+    /// Waits for the next message. Returns `None` once the queue is closed
+    /// and everything in it has been taken.
+    fn pop(&self) -> Option<Arc<str>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                return Some(message.json);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    /// Messages a viewer needs to catch up: the game start and the latest tick.
+    start_game: Option<Arc<str>>,
+    last_tick: Option<Arc<str>>,
+    end_game: Option<Arc<str>>,
+    viewers: Vec<Arc<ViewerQueue>>,
+}
+
+impl Shared {
+    fn subscribe(&mut self) -> Arc<ViewerQueue> {
+        let queue = Arc::new(ViewerQueue::default());
+        for message in [&self.start_game, &self.last_tick, &self.end_game]
+            .into_iter()
+            .flatten()
+        {
+            queue.push(message.clone(), false);
+        }
+        self.viewers.push(queue.clone());
+        queue
+    }
+
+    fn broadcast(&mut self, message: &Message) -> io::Result<()> {
+        let json: Arc<str> = serde_json::to_string(message)?.into();
+        match message {
+            Message::StartGame(_) => {
+                self.start_game = Some(json.clone());
+                self.last_tick = None;
+                self.end_game = None;
+            }
+            Message::Tick(_) => self.last_tick = Some(json.clone()),
+            Message::EndGame {} => self.end_game = Some(json.clone()),
+        }
+
+        let droppable = matches!(message, Message::Tick(_));
+        // A viewer that went away no longer holds its queue.
+        self.viewers.retain(|viewer| Arc::strong_count(viewer) > 1);
+        for viewer in &self.viewers {
+            viewer.push(json.clone(), droppable);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        for viewer in &self.viewers {
+            viewer.close();
+        }
+    }
+}
+
+/// Spectator endpoint that fans the game out to web viewers. It never waits
+/// for viewers, and they may join or leave at any moment.
+pub struct WebSpectators {
+    local_addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+    stop: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl WebSpectators {
+    /// Starts accepting viewers in background.
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let local_addr = listener.local_addr()?;
+        // The accept thread polls, so that it can notice the spectators are dropped.
+        listener.set_nonblocking(true)?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let accept_shared = Arc::downgrade(&shared);
+        let accept_stop = stop.clone();
+        let accept_thread = thread::spawn(move || {
+            while !accept_stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let shared = accept_shared.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = serve_connection(stream, &shared) {
+                                debug!("web viewer disconnected: {err}");
+                            }
+                        });
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL)
+                    }
+                    Err(err) => warn!("failed to accept web viewer: {err}"),
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            shared,
+            stop,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for WebSpectators {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+    }
+}
+
+impl Endpoint for WebSpectators {
+    fn send_message(&mut self, message: &Message) -> io::Result<()> {
+        self.shared.lock().unwrap().broadcast(message)
+    }
+
+    fn get_command(&mut self) -> io::Result<Command> {
+        Ok(Command::NoOp)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Peeks at the request head without consuming it, so that a WebSocket
+/// handshake can be handed over to tungstenite as is. Returns the head
+/// and its length in bytes, including the empty line after it.
+fn peek_request_head(stream: &TcpStream) -> io::Result<(String, usize)> {
+    let mut buf = vec![0; MAX_REQUEST_LEN];
+    loop {
+        let len = stream.peek(&mut buf)?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(end) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+            return Ok((head, end + 4));
+        }
+        if len == buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request is too long",
+            ));
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, shared: &Weak<Mutex<Shared>>) -> io::Result<()> {
+    // Accepted sockets may inherit the non-blocking mode of the listener.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let (head, head_len) = peek_request_head(&stream)?;
+    let head = head.to_ascii_lowercase();
+    let is_upgrade = head
+        .lines()
+        .any(|line| line.starts_with("upgrade:") && line.contains("websocket"));
+    if is_upgrade {
+        // Only the queue is kept, so that the game going away closes it.
+        let Some(shared) = shared.upgrade() else {
+            return Ok(());
+        };
+        let messages = shared.lock().unwrap().subscribe();
+        drop(shared);
+
+        let socket = tungstenite::accept(stream).map_err(io::Error::other)?;
+        info!("web viewer connected");
+        return stream_to_viewer(socket, messages);
+    }
+
+    let mut request = vec![0; head_len];
+    stream.read_exact(&mut request)?;
+    let response = if head.starts_with("get / ") || head.starts_with("get /index.html ") {
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{VIEWER_HTML}",
+            VIEWER_HTML.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
+
+fn stream_to_viewer(
+    mut socket: WebSocket<TcpStream>,
+    messages: Arc<ViewerQueue>,
+) -> io::Result<()> {
+    // The queue is closed when the game is over and the server is gone.
+    while let Some(message) = messages.pop() {
+        socket
+            .send(tungstenite::Message::Text(message.to_string()))
+            .map_err(io::Error::other)?;
+    }
+    socket.close(None).map_err(io::Error::other)?;
+    // Wait for the viewer to acknowledge the close.
+    while socket.read().is_ok() {}
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::{GameParams, World};
+
+    fn read_message(socket: &mut WebSocket<impl Read + Write>) -> Message {
+        let frame = socket.read().unwrap();
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn late_viewer_gets_current_state() {
+        let mut spectators = WebSpectators::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let url = format!("ws://{}/ws", spectators.local_addr());
+
+        let start_game = Message::StartGame(GameParams {
+            x_cells_count: 31,
+            y_cells_count: 31,
+            rules: Default::default(),
+            encodings: vec![],
+            players: Default::default(),
+        });
+        let tick = |tick_num| {
+            Message::Tick(World {
+                players: Default::default(),
+                tick_num,
+            })
+        };
+        spectators.send_message(&start_game).unwrap();
+        for tick_num in 1..=3 {
+            spectators.send_message(&tick(tick_num)).unwrap();
+        }
+
+        let (mut socket, _) = tungstenite::connect(url).unwrap();
+        assert_eq!(read_message(&mut socket), start_game);
+        assert_eq!(read_message(&mut socket), tick(3));
+
+        spectators.send_message(&tick(4)).unwrap();
+        assert_eq!(read_message(&mut socket), tick(4));
+        assert_eq!(spectators.get_command().unwrap(), Command::NoOp);
+
+        spectators.send_message(&Message::EndGame {}).unwrap();
+        drop(spectators);
+        assert_eq!(read_message(&mut socket), Message::EndGame {});
+        assert!(socket.read().unwrap().is_close());
+    }
+
+    #[test]
+    fn stops_accepting_when_dropped() {
+        let spectators = WebSpectators::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = spectators.local_addr();
+        drop(spectators);
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn slow_viewer_keeps_latest_ticks() {
+        let mut shared = Shared::default();
+        let queue = shared.subscribe();
+        for tick_num in 1..=2 * VIEWER_QUEUE_LEN as u32 {
+            let tick = Message::Tick(World {
+                players: Default::default(),
+                tick_num,
+            });
+            shared.broadcast(&tick).unwrap();
+        }
+        shared.broadcast(&Message::EndGame {}).unwrap();
+        drop(shared);
+
+        let messages: Vec<Message> = std::iter::from_fn(|| queue.pop())
+            .map(|json| serde_json::from_str(&json).unwrap())
+            .collect();
+        assert_eq!(messages.len(), VIEWER_QUEUE_LEN + 1);
+        assert_eq!(messages.last(), Some(&Message::EndGame {}));
+        // The oldest ticks make room for the newer ones.
+        let tick_nums: Vec<_> = messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Tick(world) => Some(world.tick_num),
+                _ => None,
+            })
+            .collect();
+        let latest = VIEWER_QUEUE_LEN as u32 + 1..=2 * VIEWER_QUEUE_LEN as u32;
+        assert_eq!(tick_nums, latest.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn serves_viewer_page() {
+        let spectators = WebSpectators::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut stream = TcpStream::connect(spectators.local_addr()).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(VIEWER_HTML));
+    }
+
+    #[test]
+    fn serves_request_with_binary_header() {
+        let spectators = WebSpectators::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut stream = TcpStream::connect(spectators.local_addr()).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nX-Name: \xff\xfe\r\n\r\n")
+            .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+}
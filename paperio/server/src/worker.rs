@@ -0,0 +1,160 @@
+use std::{
+    io,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use paperio_proto::{Command, Message};
+
+use crate::endpoint::Endpoint;
+
+enum Job {
+    Send(Message),
+    Receive { request_id: u64 },
+}
+
+enum Event {
+    Command {
+        request_id: u64,
+        command: Command,
+        received_at: Instant,
+    },
+    Failed(io::Error),
+}
+
+pub enum CommandResult {
+    /// `elapsed` is measured on the worker thread, so it doesn't depend on
+    /// how long the server was busy waiting for other endpoints.
+    Received {
+        command: Command,
+        elapsed: Duration,
+    },
+    TimedOut,
+    Failed(io::Error),
+}
+
+/// Owns an endpoint and talks to it from a dedicated thread,
+/// so that a hung endpoint can't block the server and
+/// all endpoints are served concurrently.
+///
+/// The thread is detached: it exits once the worker is dropped
+/// and the endpoint finishes its current blocking call.
+pub struct EndpointWorker {
+    jobs: Sender<Job>,
+    events: Receiver<Event>,
+    next_request_id: u64,
+    requested_at: Instant,
+}
+
+impl EndpointWorker {
+    pub fn spawn(mut endpoint: impl Endpoint + Send + 'static) -> Self {
+        let (jobs_sender, jobs) = mpsc::channel();
+        let (events, events_receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for job in jobs {
+                let event = match job {
+                    Job::Send(message) => match endpoint.send_message(&message) {
+                        Ok(()) => continue,
+                        Err(err) => Event::Failed(err),
+                    },
+                    Job::Receive { request_id } => match endpoint.get_command() {
+                        Ok(command) => Event::Command {
+                            request_id,
+                            command,
+                            received_at: Instant::now(),
+                        },
+                        Err(err) => Event::Failed(err),
+                    },
+                };
+
+                let failed = matches!(event, Event::Failed(_));
+                if events.send(event).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            jobs: jobs_sender,
+            events: events_receiver,
+            next_request_id: 0,
+            requested_at: Instant::now(),
+        }
+    }
+
+    pub fn send(&self, message: Message) {
+        // If the thread has exited, the error is reported by `wait_command`.
+        let _ = self.jobs.send(Job::Send(message));
+    }
+
+    /// Asks the endpoint for a command, the answer is obtained with `wait_command`.
+    pub fn request_command(&mut self) -> u64 {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.requested_at = Instant::now();
+        let _ = self.jobs.send(Job::Receive { request_id });
+        request_id
+    }
+
+    /// Moment of the last `request_command` call.
+    pub fn requested_at(&self) -> Instant {
+        self.requested_at
+    }
+
+    /// Waits for the answer to the given request until the deadline.
+    /// Answers to earlier requests that came too late are dropped.
+    pub fn wait_command(&mut self, request_id: u64, deadline: Option<Instant>) -> CommandResult {
+        loop {
+            let event = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match self.events.recv_timeout(timeout) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => return CommandResult::TimedOut,
+                        Err(RecvTimeoutError::Disconnected) => return worker_exited(),
+                    }
+                }
+                None => match self.events.recv() {
+                    Ok(event) => event,
+                    Err(_) => return worker_exited(),
+                },
+            };
+
+            match event {
+                Event::Command {
+                    request_id: id,
+                    command,
+                    received_at,
+                } if id == request_id => {
+                    return CommandResult::Received {
+                        command,
+                        elapsed: received_at.saturating_duration_since(self.requested_at),
+                    }
+                }
+                Event::Command { .. } => continue,
+                Event::Failed(err) => return CommandResult::Failed(err),
+            }
+        }
+    }
+
+    /// Lets the thread deliver already queued messages, waiting for it at most until the deadline.
+    pub fn close(self, deadline: Instant) {
+        drop(self.jobs);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(timeout) {
+                Ok(_) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+fn worker_exited() -> CommandResult {
+    CommandResult::Failed(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "endpoint worker has exited",
+    ))
+}
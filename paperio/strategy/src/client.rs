@@ -0,0 +1,86 @@
+//! Playing over the server protocol: an optional handshake, then messages
+//! in JSON or bincode over TCP or stdin/stdout.
+
+use paperio_proto::{
+    features,
+    traits::{Bot, Codec, Encoding, JsonRead, JsonWrite},
+    Command, Handshake, Hello, Message, PROTOCOL_VERSION,
+};
+
+use crate::{strategy::StrategyParams, StrategyKind};
+
+use std::{
+    io::{stdin, stdout, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+};
+
+/// Introduces the strategy to the server. Only done over TCP,
+/// pipes of the tournament and the wasm launcher have no handshake.
+fn handshake(reader: &mut impl BufRead, writer: &mut impl Write, name: &str) {
+    let hello = Hello {
+        name: name.to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        features: vec![features::ENCODINGS.to_string()],
+        session_token: None,
+    };
+    writer.write_handshake(&Handshake::Hello(hello)).unwrap();
+    writer.flush().unwrap();
+
+    let Ok(Handshake::Welcome(_)) = reader.read_handshake() else {
+        panic!("expected 'welcome' in reply to 'hello'");
+    };
+}
+
+fn run(reader: impl Read, mut writer: impl Write, name: Option<&str>, mut strategy: impl Bot) {
+    let mut reader = BufReader::new(reader);
+    let mut codec = Codec::new();
+
+    if let Some(name) = name {
+        handshake(&mut reader, &mut writer, name);
+    }
+
+    let Ok(Message::StartGame(params)) = codec.read_message(&mut reader) else {
+        panic!("expected the first message to be 'start_game'");
+    };
+
+    // Ticks are much smaller with delta encoding, use it if the server supports it.
+    let mut new_encoding = Some(Encoding::BincodeDelta).filter(|e| params.encodings.contains(e));
+
+    strategy.on_start(params);
+    while let Ok(Message::Tick(tick_params)) = codec.read_message(&mut reader) {
+        let direction = strategy.on_tick(tick_params);
+        if let Some(encoding) = new_encoding.take() {
+            codec
+                .write_command(&mut writer, &Command::SetEncoding(encoding))
+                .unwrap();
+            codec.set_encoding(encoding);
+        }
+        let msg = Command::ChangeDirection(direction);
+        codec.write_command(&mut writer, &msg).unwrap();
+        writer.flush().unwrap();
+    }
+}
+
+/// Strategy selected by the environment, see [`StrategyKind::from_env`].
+pub fn create_strategy() -> Box<dyn Bot + Send> {
+    let kind = StrategyKind::from_env().unwrap();
+    let params = StrategyParams::from_env().unwrap();
+    kind.create_with(params)
+}
+
+/// Connects to the server on the port from the first argument,
+/// or plays over stdin/stdout if there is none.
+pub fn main() {
+    let strategy = create_strategy();
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(port_str) = args.get(1) {
+        let port = port_str.parse::<u16>().expect("args[1] should be a u16");
+        let stream = TcpStream::connect(format!("localhost:{}", port))
+            .expect("failed to connect to tcp socket");
+        let cloned_stream = stream.try_clone().unwrap();
+        let name = args.get(2).map_or(env!("CARGO_PKG_NAME"), String::as_str);
+        run(stream, cloned_stream, Some(name), strategy);
+    } else {
+        run(stdin(), stdout(), None, strategy);
+    }
+}
@@ -1,3 +1,71 @@
 #![forbid(unsafe_code)]
 
+pub mod client;
+pub mod mcts;
+pub mod planner;
 pub mod strategy;
+
+use std::{fmt, str::FromStr};
+
+use paperio_proto::traits::Bot;
+use strategy::StrategyParams;
+
+/// Environment variable that selects the strategy at startup.
+pub const STRATEGY_ENV: &str = "PAPERIO_STRATEGY";
+/// Environment variable with a path to a JSON file with [`StrategyParams`].
+pub const PARAMS_ENV: &str = "PAPERIO_STRATEGY_PARAMS";
+
+/// Strategies this crate can play.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StrategyKind {
+    /// Captures one rectangle at a time, see [`strategy::Strategy`].
+    #[default]
+    Rectangles,
+    /// Tree search, see [`mcts::Mcts`].
+    Mcts,
+}
+
+impl StrategyKind {
+    /// Reads the strategy from [`STRATEGY_ENV`], the default one if it isn't set.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(STRATEGY_ENV) {
+            Ok(name) => name.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn create(self) -> Box<dyn Bot + Send> {
+        self.create_with(StrategyParams::default())
+    }
+
+    /// Parameters only affect [`StrategyKind::Rectangles`].
+    pub fn create_with(self, params: StrategyParams) -> Box<dyn Bot + Send> {
+        match self {
+            Self::Rectangles => Box::new(strategy::Strategy::with_params(params)),
+            Self::Mcts => Box::new(mcts::Mcts::new()),
+        }
+    }
+}
+
+impl FromStr for StrategyKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "rectangles" => Ok(Self::Rectangles),
+            "mcts" => Ok(Self::Mcts),
+            _ => Err(format!(
+                "unknown strategy {name:?}, expected 'rectangles' or 'mcts'"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StrategyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rectangles => "rectangles",
+            Self::Mcts => "mcts",
+        })
+    }
+}
@@ -0,0 +1,631 @@
+//! Monte Carlo tree search over my moves. Enemies are played by simple
+//! opponent models, and every simulation follows the server's rules.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use paperio_proto::{
+    traits::Bot,
+    view::{DistanceMap, Grid},
+    Cell, Direction, GameParams, Rules, World, MAP_SIZE_CELLS,
+};
+use paperio_rules::game::{world_player_ids, Game, PlayerId};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::planner::Planner;
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+/// Thinking time per tick, well below usual tick timeouts.
+pub const DEFAULT_TIME_BUDGET: Duration = Duration::from_millis(40);
+/// Searched at least this many times even on a slow machine.
+const MIN_ITERATIONS: u32 = 32;
+/// Ticks simulated ahead of the current one.
+const HORIZON: u32 = 30;
+const EXPLORATION: f64 = 8.;
+
+const LOSS_PENALTY: f64 = 100.;
+const KILL_BONUS: f64 = 60.;
+/// For every cell of my trace left open at the end of a simulation.
+const OPEN_TRACE_PENALTY: f64 = 0.5;
+
+/// For every simulated tick an enemy could cut my trace, see [`is_exposed`].
+const EXPOSURE_PENALTY: f64 = 1.;
+/// Share of the points for closing an open trace counted at the end of a simulation.
+const POTENTIAL_WEIGHT: f64 = 0.5;
+/// The share above shrinks by this factor for every step left to get home.
+const POTENTIAL_DECAY: f64 = 0.9;
+/// Per cell of distance to the border of my territory.
+const FRONTIER_WEIGHT: f64 = 0.5;
+
+/// Players hunt for traces this close to them.
+const HUNT_DISTANCE: i32 = 6;
+/// Chance that a player hunts during a simulation. Enemies are expected
+/// to be busy with their own plans more often than not.
+const MY_HUNT_PROBABILITY: f64 = 0.8;
+const ENEMY_HUNT_PROBABILITY: f64 = 0.3;
+/// Length of a side of a loop walkers make.
+const MIN_LEG: usize = 2;
+const MAX_LEG: usize = 12;
+/// Chance that a walker keeps its direction on its territory.
+const STRAIGHT_PROBABILITY: f64 = 0.7;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct Node {
+    visits: u32,
+    total_value: f64,
+    /// Indexed by `Direction`.
+    children: [Option<usize>; 4],
+}
+
+impl Node {
+    fn mean_value(&self) -> f64 {
+        self.total_value / self.visits.max(1) as f64
+    }
+}
+
+pub struct Mcts {
+    rules: Rules,
+    time_budget: Duration,
+    /// Fixed number of simulations per tick, overrides `time_budget`.
+    iterations: Option<u32>,
+    rng: StdRng,
+}
+
+/// What stays the same during one search.
+struct Context {
+    me: PlayerId,
+    enemies: Vec<PlayerId>,
+    /// Distances to the territory of each player at the start of the search, by player index.
+    home: Vec<DistanceMap>,
+    /// Distances to the cells outside the territory of each player, by player index.
+    exits: Vec<DistanceMap>,
+}
+
+impl Context {
+    fn new(game: &Game, me: PlayerId) -> Self {
+        let home = game
+            .player_ids()
+            .map(|id| distances_to(game.player_cells(id).0.iter().copied()))
+            .collect();
+        let exits = game
+            .player_ids()
+            .map(|id| distances_to(all_cells().filter(|&cell| game.cell_owner(cell) != Some(id))))
+            .collect();
+        Self {
+            me,
+            enemies: game
+                .player_ids()
+                .filter(|&id| id != me && !game.has_lost(id))
+                .collect(),
+            home,
+            exits,
+        }
+    }
+
+    fn home_distance(&self, player_id: PlayerId, cell: Cell) -> u32 {
+        self.home[player_id.get() - 1][cell].unwrap_or(u32::MAX)
+    }
+
+    fn exit_distance(&self, player_id: PlayerId, cell: Cell) -> u32 {
+        self.exits[player_id.get() - 1][cell].unwrap_or(0)
+    }
+}
+
+impl Mcts {
+    /// Search with a random seed.
+    pub fn new() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    /// With the same seed and [`Mcts::iterations`] the bot plays the same moves.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rules: Rules::default(),
+            time_budget: DEFAULT_TIME_BUDGET,
+            iterations: None,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn time_budget(mut self, time_budget: Duration) -> Self {
+        self.time_budget = time_budget;
+        self
+    }
+
+    /// Runs exactly this many simulations per tick, however long they take.
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    fn keeps_searching(&self, iterations: u32, deadline: Instant) -> bool {
+        match self.iterations {
+            Some(limit) => iterations < limit,
+            None => iterations < MIN_ITERATIONS || Instant::now() < deadline,
+        }
+    }
+
+    fn search(&mut self, world: &World) -> Direction {
+        let deadline = Instant::now() + self.time_budget;
+        let me = world_player_ids(world)["i"];
+        let root = Game::from_world(world, self.rules);
+        if root.has_lost(me) {
+            return root.player_direction(me);
+        }
+
+        let context = Context::new(&root, me);
+        let mut nodes = vec![Node::default()];
+        let mut iterations = 0;
+        while self.keeps_searching(iterations, deadline) {
+            iterations += 1;
+            let mut game = root.clone();
+            let mut plans = game
+                .player_ids()
+                .map(|id| {
+                    let hunt_probability = if id == me {
+                        MY_HUNT_PROBABILITY
+                    } else {
+                        ENEMY_HUNT_PROBABILITY
+                    };
+                    Plan::random(hunt_probability, &mut self.rng)
+                })
+                .collect::<Vec<_>>();
+            let mut path = vec![0];
+            let mut depth = 0;
+            let mut exposure = 0;
+
+            // Selection and expansion.
+            while depth < HORIZON && !game.has_lost(me) {
+                let node = *path.last().unwrap();
+                let (direction, is_new) = self.select(&nodes, node, &game, me);
+                let child = match nodes[node].children[direction as usize] {
+                    Some(child) => child,
+                    None => {
+                        nodes.push(Node::default());
+                        nodes[node].children[direction as usize] = Some(nodes.len() - 1);
+                        nodes.len() - 1
+                    }
+                };
+                self.step(&mut game, &context, direction, &mut plans);
+                exposure += is_exposed(&game, &context) as u32;
+                path.push(child);
+                depth += 1;
+                if is_new {
+                    break;
+                }
+            }
+
+            // Rollout.
+            while depth < HORIZON && !game.has_lost(me) {
+                let plan = &mut plans[me.get() - 1];
+                let direction =
+                    player_move(&game, &context, me, &context.enemies, plan, &mut self.rng);
+                self.step(&mut game, &context, direction, &mut plans);
+                exposure += is_exposed(&game, &context) as u32;
+                depth += 1;
+            }
+
+            let value =
+                evaluate(&root, &game, &context, depth) - exposure as f64 * EXPOSURE_PENALTY;
+            for &node in &path {
+                nodes[node].visits += 1;
+                nodes[node].total_value += value;
+            }
+        }
+
+        let best = DIRECTIONS
+            .into_iter()
+            .filter_map(|direction| {
+                let child = nodes[0].children[direction as usize]?;
+                Some((direction, &nodes[child]))
+            })
+            .max_by(|(_, a), (_, b)| {
+                (a.visits, a.mean_value())
+                    .partial_cmp(&(b.visits, b.mean_value()))
+                    .unwrap()
+            });
+        best.map_or(root.player_direction(me), |(direction, _)| direction)
+    }
+
+    /// Picks an untried move first, then the one with the best UCT score.
+    fn select(
+        &mut self,
+        nodes: &[Node],
+        node: usize,
+        game: &Game,
+        me: PlayerId,
+    ) -> (Direction, bool) {
+        let mut moves = safe_moves(game, me);
+        if moves.is_empty() {
+            moves = legal_moves(game, me);
+        }
+        if moves.is_empty() {
+            moves = vec![game.player_direction(me)];
+        }
+
+        let untried = moves
+            .iter()
+            .copied()
+            .filter(|&direction| {
+                nodes[node].children[direction as usize]
+                    .is_none_or(|child| nodes[child].visits == 0)
+            })
+            .collect::<Vec<_>>();
+        if !untried.is_empty() {
+            return (untried[self.rng.gen_range(0..untried.len())], true);
+        }
+
+        let log_visits = (nodes[node].visits.max(1) as f64).ln();
+        let uct = |direction: Direction| {
+            let child = &nodes[nodes[node].children[direction as usize].unwrap()];
+            child.mean_value() + EXPLORATION * (log_visits / child.visits as f64).sqrt()
+        };
+        let best = moves
+            .into_iter()
+            .max_by(|&a, &b| uct(a).partial_cmp(&uct(b)).unwrap())
+            .unwrap();
+        (best, false)
+    }
+
+    fn step(
+        &mut self,
+        game: &mut Game,
+        context: &Context,
+        direction: Direction,
+        plans: &mut [Plan],
+    ) {
+        let me = context.me;
+        let mut directions = vec![(me, direction)];
+        for &enemy in &context.enemies {
+            if !game.has_lost(enemy) {
+                let plan = &mut plans[enemy.get() - 1];
+                let direction = player_move(game, context, enemy, &[me], plan, &mut self.rng);
+                directions.push((enemy, direction));
+            }
+        }
+        game.tick_with(directions);
+    }
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bot for Mcts {
+    fn on_start(&mut self, params: GameParams) {
+        self.rules = params.rules;
+    }
+
+    fn on_tick(&mut self, world: World) -> Direction {
+        let direction = self.search(&world);
+        // Simulations see only a few ticks ahead, the planner makes sure
+        // there is still a safe way home after the move.
+        let planner = Planner::new(&world, self.rules);
+        if planner.is_safe_move(direction) {
+            direction
+        } else {
+            planner.next_move()
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Points gained, with a penalty for losing and for an open trace.
+/// Losing later is a bit better than losing sooner. An open trace is worth
+/// part of what closing it would give, and standing deep in my territory
+/// is a bit worse than standing near its border.
+fn evaluate(root: &Game, game: &Game, context: &Context, depth: u32) -> f64 {
+    let me = context.me;
+    if game.has_lost(me) {
+        return -LOSS_PENALTY + depth as f64;
+    }
+    let gained = game.player_score(me) as f64 - root.player_score(me) as f64;
+    let kills = context
+        .enemies
+        .iter()
+        .filter(|&&id| game.has_lost(id))
+        .count();
+    let open_trace = game.player_cells(me).1.len();
+    let potential = if open_trace > 0 {
+        let time_home = context.home_distance(me, game.player_position(me));
+        game.pending_capture_score(me) as f64
+            * POTENTIAL_WEIGHT
+            * POTENTIAL_DECAY.powi(time_home.min(HORIZON) as i32)
+    } else {
+        let frontier = context.exit_distance(me, game.player_position(me));
+        -(frontier as f64) * FRONTIER_WEIGHT
+    };
+    gained + kills as f64 * KILL_BONUS - open_trace as f64 * OPEN_TRACE_PENALTY + potential
+}
+
+/// Whether an enemy can reach my trace before I get back home.
+fn is_exposed(game: &Game, context: &Context) -> bool {
+    let me = context.me;
+    let trace = game.player_cells(me).1;
+    if trace.is_empty() || game.has_lost(me) {
+        return false;
+    }
+    let time_home = context.home_distance(me, game.player_position(me));
+    context
+        .enemies
+        .iter()
+        .filter(|&&enemy| !game.has_lost(enemy))
+        .any(|&enemy| {
+            let enemy_position = game.player_position(enemy);
+            trace
+                .iter()
+                .any(|cell| (cell.distance_to(enemy_position) as u32) < time_home)
+        })
+}
+
+/// Moves that don't turn back or leave the field.
+fn legal_moves(game: &Game, player_id: PlayerId) -> Vec<Direction> {
+    let position = game.player_position(player_id);
+    let back = game.player_direction(player_id).opposite();
+    DIRECTIONS
+        .into_iter()
+        .filter(|&direction| direction != back && position.adjacent(direction).is_some())
+        .collect()
+}
+
+/// Legal moves that don't step on the player's own trace.
+fn safe_moves(game: &Game, player_id: PlayerId) -> Vec<Direction> {
+    let position = game.player_position(player_id);
+    legal_moves(game, player_id)
+        .into_iter()
+        .filter(|&direction| game.cell_trace(position + direction) != Some(player_id))
+        .collect()
+}
+
+/// The move that gets closest to the target.
+fn move_towards(game: &Game, player_id: PlayerId, target: Cell, moves: &[Direction]) -> Direction {
+    let position = game.player_position(player_id);
+    moves
+        .iter()
+        .copied()
+        .min_by_key(|&direction| (position + direction).distance_to(target))
+        .unwrap_or(game.player_direction(player_id))
+}
+
+fn all_cells() -> impl Iterator<Item = Cell> {
+    (0..MAP_SIZE_CELLS).flat_map(|x| (0..MAP_SIZE_CELLS).map(move |y| Cell(x, y)))
+}
+
+fn nearest(cells: impl Iterator<Item = Cell>, from: Cell) -> Option<Cell> {
+    cells.min_by_key(|cell| cell.distance_to(from))
+}
+
+/// Distances from every cell to the closest of the given ones.
+fn distances_to(cells: impl Iterator<Item = Cell>) -> DistanceMap {
+    let mut distances = Grid::new(None);
+    let mut queue = VecDeque::new();
+    for cell in cells {
+        distances[cell] = Some(0);
+        queue.push_back(cell);
+    }
+    while let Some(cell) = queue.pop_front() {
+        let distance = distances[cell].unwrap();
+        for next in cell.iter_neighbors() {
+            if distances[next].is_none() {
+                distances[next] = Some(distance + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+    distances
+}
+
+/// How a player behaves during one simulation. Outside of its territory
+/// it makes a loop: `first` cells forward, a turn, `second` cells,
+/// a turn to the same side and back home.
+struct Plan {
+    first: usize,
+    second: usize,
+    clockwise: bool,
+    /// Whether it goes after traces of its prey nearby.
+    hunts: bool,
+    /// Way back home, the next cell is the last one.
+    route: Vec<Cell>,
+}
+
+impl Plan {
+    fn random(hunt_probability: f64, rng: &mut StdRng) -> Self {
+        Self {
+            first: rng.gen_range(MIN_LEG..=MAX_LEG),
+            second: rng.gen_range(MIN_LEG..=MAX_LEG),
+            clockwise: rng.gen(),
+            hunts: rng.gen_bool(hunt_probability),
+            route: vec![],
+        }
+    }
+}
+
+/// Wanders around its territory, leaves it to make a loop and comes back.
+fn walker_move(
+    game: &Game,
+    context: &Context,
+    player_id: PlayerId,
+    plan: &mut Plan,
+    rng: &mut StdRng,
+) -> Direction {
+    let moves = safe_moves(game, player_id);
+    if moves.is_empty() {
+        return game.player_direction(player_id);
+    }
+
+    let direction = game.player_direction(player_id);
+    let trace = game.player_cells(player_id).1;
+    if trace.is_empty() {
+        plan.route.clear();
+        let position = game.player_position(player_id);
+        if context.exit_distance(player_id, position) > 1 {
+            // Deep inside, heading out.
+            return *moves
+                .iter()
+                .min_by_key(|&&next| context.exit_distance(player_id, position + next))
+                .unwrap();
+        }
+        return if moves.contains(&direction) && rng.gen_bool(STRAIGHT_PROBABILITY) {
+            direction
+        } else {
+            moves[rng.gen_range(0..moves.len())]
+        };
+    }
+
+    let next = if trace.len() == plan.first || trace.len() == plan.first + plan.second {
+        direction.next(plan.clockwise)
+    } else {
+        direction
+    };
+    if trace.len() <= plan.first + plan.second && moves.contains(&next) {
+        return next;
+    }
+
+    // The route stays valid while it is followed, since the trace only grows behind.
+    let position = game.player_position(player_id);
+    if plan
+        .route
+        .last()
+        .is_none_or(|&cell| cell.distance_to(position) != 1)
+    {
+        plan.route = route_home(game, player_id);
+    }
+    match plan.route.pop() {
+        Some(cell) if moves.contains(&position.direction_to(cell)) => position.direction_to(cell),
+        _ => moves[rng.gen_range(0..moves.len())],
+    }
+}
+
+/// Shortest way to the player's territory that doesn't cross its trace,
+/// with the first cell last. Empty if there is no way home.
+fn route_home(game: &Game, player_id: PlayerId) -> Vec<Cell> {
+    let start = game.player_position(player_id);
+    let back = start + game.player_direction(player_id).opposite();
+    let mut previous = Grid::<Option<Cell>>::new(None);
+    previous[start] = Some(start);
+    let mut queue = VecDeque::from([start]);
+    while let Some(cell) = queue.pop_front() {
+        if game.cell_owner(cell) == Some(player_id) {
+            let mut route = vec![];
+            let mut cell = cell;
+            while cell != start {
+                route.push(cell);
+                cell = previous[cell].unwrap();
+            }
+            return route;
+        }
+        for next in cell.iter_neighbors() {
+            if previous[next].is_none()
+                && !(cell == start && next == back)
+                && game.cell_trace(next) != Some(player_id)
+            {
+                previous[next] = Some(cell);
+                queue.push_back(next);
+            }
+        }
+    }
+    vec![]
+}
+
+/// Cuts a trace of its prey when it can and hunts for it when it is close,
+/// otherwise it walks around. Enemies hunt for me, and I hunt for all of them.
+fn player_move(
+    game: &Game,
+    context: &Context,
+    player_id: PlayerId,
+    prey: &[PlayerId],
+    plan: &mut Plan,
+    rng: &mut StdRng,
+) -> Direction {
+    let moves = safe_moves(game, player_id);
+    let position = game.player_position(player_id);
+    let is_prey = |cell: Cell| game.cell_trace(cell).is_some_and(|id| prey.contains(&id));
+    if let Some(&cut) = moves
+        .iter()
+        .find(|&&direction| is_prey(position + direction))
+    {
+        return cut;
+    }
+
+    if plan.hunts {
+        let traces = prey
+            .iter()
+            .filter(|&&id| !game.has_lost(id))
+            .flat_map(|&id| game.player_cells(id).1.iter().copied());
+        if let Some(target) = nearest(traces, position) {
+            if target.distance_to(position) <= HUNT_DISTANCE {
+                return move_towards(game, player_id, target, &moves);
+            }
+        }
+    }
+
+    walker_move(game, context, player_id, plan, rng)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::Player;
+
+    fn player(
+        position: Cell,
+        direction: Direction,
+        territory: Vec<Cell>,
+        lines: Vec<Cell>,
+    ) -> Player {
+        Player {
+            score: 0,
+            territory,
+            position,
+            lines,
+            direction: Some(direction),
+            has_lost: false,
+            speed_boost_ticks: 0,
+        }
+    }
+
+    fn square(Cell(x, y): Cell) -> Vec<Cell> {
+        (x - 1..=x + 1)
+            .flat_map(|x| (y - 1..=y + 1).map(move |y| Cell(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn goes_home_when_hunted() {
+        // I've just left my territory, an enemy is coming for my trace.
+        let me = player(
+            Cell(11, 9),
+            Direction::Right,
+            square(Cell(9, 9)),
+            vec![Cell(11, 9)],
+        );
+        let enemy = player(
+            Cell(11, 12),
+            Direction::Down,
+            square(Cell(21, 21)),
+            vec![Cell(11, 12)],
+        );
+        let world = World {
+            players: [("i".to_string(), me), ("2".to_string(), enemy)].into(),
+            tick_num: 50,
+        };
+
+        let mut mcts = Mcts::with_seed(0).iterations(200);
+        assert_ne!(mcts.on_tick(world), Direction::Right);
+    }
+}
@@ -0,0 +1,385 @@
+//! Planning of loops that are safe from enemies. A plan is safe if I get
+//! home along it sooner than any enemy can reach my trace, including
+//! the part of the trace the plan itself lays.
+
+use paperio_proto::{
+    view::{DistanceMap, Grid, WorldView},
+    Cell, Direction, PlayerId, Rules, World,
+};
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+/// Longest side of a loop, in cells.
+const MAX_LEG: usize = 12;
+
+fn me() -> PlayerId {
+    "i".to_string()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Cells to go through one per tick, the last one is on my territory.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Route {
+    pub path: Vec<Cell>,
+    /// Points for closing the loop along the path.
+    pub score: u32,
+}
+
+impl Route {
+    /// Number of ticks to get home along the route.
+    pub fn len(&self) -> usize {
+        self.path.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.path.is_empty()
+    }
+}
+
+pub struct Planner<'a> {
+    view: WorldView<'a>,
+    rules: Rules,
+    reach_times: DistanceMap,
+    /// My current trace.
+    trace: Grid<bool>,
+}
+
+impl<'a> Planner<'a> {
+    pub fn new(world: &'a World, rules: Rules) -> Self {
+        let view = WorldView::new(world);
+        let reach_times = view.enemy_reach_times();
+        let mut trace = Grid::new(false);
+        for &cell in &world.me().lines {
+            trace[cell] = true;
+        }
+        Self {
+            view,
+            rules,
+            reach_times,
+            trace,
+        }
+    }
+
+    pub fn view(&self) -> &WorldView<'a> {
+        &self.view
+    }
+
+    fn position(&self) -> Cell {
+        self.view.world().me().position
+    }
+
+    fn direction(&self) -> Option<Direction> {
+        self.view.world().me().direction
+    }
+
+    /// Whether I get home along `path` sooner than any enemy reaches my trace.
+    /// The path must not cross my trace or itself, and it has to end on my territory.
+    pub fn is_safe(&self, path: &[Cell]) -> bool {
+        let Some(&last) = path.last() else {
+            return self.trace.iter().all(|(_, &traced)| !traced);
+        };
+        if !self.view.is_mine(last) {
+            return false;
+        }
+
+        let time_home = path.len() as u32;
+        let mut visited = self.trace.clone();
+        let mut trace_cells = self.view.world().me().lines.clone();
+        for &cell in &path[..path.len() - 1] {
+            if !cell.in_bounds() || visited[cell] {
+                return false;
+            }
+            visited[cell] = true;
+            if !self.view.is_mine(cell) {
+                trace_cells.push(cell);
+            }
+        }
+        trace_cells
+            .into_iter()
+            .all(|cell| self.reach_times[cell].is_none_or(|reach_time| time_home < reach_time))
+    }
+
+    /// Whether after the move there is still a safe way home.
+    pub fn is_safe_move(&self, direction: Direction) -> bool {
+        if self.direction() == Some(direction.opposite()) {
+            return false;
+        }
+        let Some(next) = self.position().adjacent(direction) else {
+            return false;
+        };
+        if self.trace[next] {
+            return false;
+        }
+        let mut path = vec![next];
+        if !self.view.is_mine(next) {
+            let mut blocked = self.trace.clone();
+            blocked[next] = true;
+            match self
+                .view
+                .path_home_from(&me(), next, Some(direction), &blocked)
+            {
+                Some(rest) => path.extend(rest),
+                None => return false,
+            }
+        }
+        self.is_safe(&path)
+    }
+
+    /// Shortest way home, safe or not. On my territory it is a single step
+    /// to a neighbouring cell of my territory.
+    pub fn route_home(&self) -> Option<Route> {
+        let path = self.view.path_home(&me(), &self.trace)?;
+        Some(self.route(path))
+    }
+
+    /// Shortest safe way home, see [`Self::is_safe`]. A way of `n` steps
+    /// can't go through cells enemies reach in `n` ticks, so such cells are
+    /// avoided for growing `n` until the shortest way left fits.
+    pub fn safe_route_home(&self) -> Option<Route> {
+        for max_len in 1.. {
+            let mut blocked = self.trace.clone();
+            for (cell, reach_time) in self.reach_times.iter() {
+                if reach_time.is_some_and(|time| time <= max_len) && !self.view.is_mine(cell) {
+                    blocked[cell] = true;
+                }
+            }
+            let path = self.view.path_home(&me(), &blocked)?;
+            if path.len() as u32 <= max_len {
+                // Only my current trace can make it unsafe, and it would
+                // make any longer way unsafe too.
+                return self.is_safe(&path).then(|| self.route(path));
+            }
+        }
+        None
+    }
+
+    /// The safe loop with the most points per tick. It goes straight, turns,
+    /// goes straight again and then takes the shortest way home.
+    /// `None` if there is no safe loop.
+    pub fn best_loop(&self) -> Option<Route> {
+        let start = self.position();
+        let mut best: Option<(f64, Route)> = None;
+        for first in DIRECTIONS {
+            if self.direction() == Some(first.opposite()) {
+                continue;
+            }
+            for clockwise in [true, false] {
+                let second = first.next(clockwise);
+                for first_len in 1..=MAX_LEG {
+                    for second_len in 0..=MAX_LEG {
+                        if clockwise && second_len == 0 {
+                            // Same path as the counterclockwise one.
+                            continue;
+                        }
+                        let Some(path) =
+                            self.make_loop(start, [(first, first_len), (second, second_len)])
+                        else {
+                            continue;
+                        };
+                        if !self.is_safe(&path) {
+                            continue;
+                        }
+                        let route = self.route(path);
+                        let rate = route.score as f64 / route.len() as f64;
+                        if best.as_ref().is_none_or(|(best_rate, _)| rate > *best_rate) {
+                            best = Some((rate, route));
+                        }
+                    }
+                }
+            }
+        }
+        best.map(|(_, route)| route)
+    }
+
+    /// Follows the best safe loop, goes home safely if there is none, or along
+    /// the shortest way if that is impossible too. Avoids stepping on my trace
+    /// if there is no way home at all.
+    pub fn next_move(&self) -> Direction {
+        let position = self.position();
+        let route = self
+            .best_loop()
+            .or_else(|| self.safe_route_home())
+            .or_else(|| self.route_home());
+        if let Some(route) = route {
+            return position.direction_to(route.path[0]);
+        }
+        let back = self.direction().map(Direction::opposite);
+        DIRECTIONS
+            .into_iter()
+            .filter(|&direction| Some(direction) != back)
+            .find(|&direction| {
+                position
+                    .adjacent(direction)
+                    .is_some_and(|next| !self.trace[next])
+            })
+            .or(self.direction())
+            .unwrap_or(Direction::Up)
+    }
+
+    fn route(&self, path: Vec<Cell>) -> Route {
+        let score = self
+            .view
+            .capture_score(&self.view.capture_area(&path), &self.rules);
+        Route { path, score }
+    }
+
+    /// Goes along the legs and then home. The loop closes as soon as
+    /// it gets back to my territory after leaving it.
+    fn make_loop(&self, start: Cell, legs: [(Direction, usize); 2]) -> Option<Vec<Cell>> {
+        let mut blocked = self.trace.clone();
+        let mut path = vec![];
+        let mut outside = !self.view.is_mine(start);
+        let mut cell = start;
+        for (direction, len) in legs {
+            for _ in 0..len {
+                cell = cell.adjacent(direction)?;
+                if blocked[cell] {
+                    return None;
+                }
+                blocked[cell] = true;
+                path.push(cell);
+                if self.view.is_mine(cell) {
+                    if outside {
+                        return Some(path);
+                    }
+                } else {
+                    outside = true;
+                }
+            }
+        }
+        if !outside {
+            return None;
+        }
+
+        let last = legs.iter().rev().find(|(_, len)| *len > 0).map(|(d, _)| *d);
+        path.extend(self.view.path_home_from(&me(), cell, last, &blocked)?);
+        Some(path)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::Player;
+
+    fn player(
+        position: Cell,
+        direction: Direction,
+        territory: Vec<Cell>,
+        lines: Vec<Cell>,
+    ) -> Player {
+        Player {
+            score: 0,
+            territory,
+            position,
+            lines,
+            direction: Some(direction),
+            has_lost: false,
+            speed_boost_ticks: 0,
+        }
+    }
+
+    fn square(Cell(x, y): Cell) -> Vec<Cell> {
+        (x - 1..=x + 1)
+            .flat_map(|x| (y - 1..=y + 1).map(move |y| Cell(x, y)))
+            .collect()
+    }
+
+    fn world(me: Player, enemy: Player) -> World {
+        World {
+            players: [("i".to_string(), me), ("2".to_string(), enemy)].into(),
+            tick_num: 50,
+        }
+    }
+
+    #[test]
+    fn loops_are_safe() {
+        let me = player(Cell(9, 9), Direction::Right, square(Cell(9, 9)), vec![]);
+        let enemy = player(
+            Cell(15, 9),
+            Direction::Left,
+            square(Cell(21, 21)),
+            vec![Cell(15, 9)],
+        );
+        let world = world(me, enemy);
+        let planner = Planner::new(&world, Rules::default());
+
+        let route = planner.best_loop().unwrap();
+        assert!(route.score > 0);
+        assert!(planner.is_safe(&route.path));
+        assert!(planner.is_safe_move(Cell(9, 9).direction_to(route.path[0])));
+
+        // I'd be back in 4 ticks, but the enemy gets to (11, 9) in 4 ticks too.
+        assert!(!planner.is_safe(&[Cell(10, 9), Cell(11, 9), Cell(11, 10), Cell(10, 10)]));
+    }
+
+    #[test]
+    fn goes_home_when_hunted() {
+        let me = player(
+            Cell(12, 9),
+            Direction::Right,
+            square(Cell(9, 9)),
+            vec![Cell(11, 9), Cell(12, 9)],
+        );
+        let enemy = player(
+            Cell(12, 14),
+            Direction::Down,
+            square(Cell(21, 21)),
+            vec![Cell(12, 14)],
+        );
+        let world = world(me, enemy);
+        let planner = Planner::new(&world, Rules::default());
+
+        assert!(!planner.is_safe_move(Direction::Right));
+        let home = planner.route_home().unwrap();
+        assert_eq!(home.len(), 3);
+        assert!(planner.is_safe(&home.path));
+        // Never turns back into the trace.
+        assert_ne!(planner.next_move(), Direction::Left);
+        assert!(planner.is_safe_move(planner.next_move()));
+    }
+
+    #[test]
+    fn goes_around_to_get_home_safely() {
+        // My trace goes right from one part of my territory, the other one is just ahead.
+        let territory = [square(Cell(5, 15)), square(Cell(19, 15))].concat();
+        let lines = (7..=15).map(|x| Cell(x, 15)).collect();
+        let me = player(Cell(15, 15), Direction::Right, territory, lines);
+        // The enemy is walled in by its own trace and can only get out
+        // through (17, 15), which is on my shortest way home.
+        let enemy = player(
+            Cell(17, 13),
+            Direction::Up,
+            square(Cell(25, 25)),
+            vec![
+                Cell(16, 12),
+                Cell(16, 13),
+                Cell(16, 14),
+                Cell(17, 12),
+                Cell(18, 12),
+                Cell(18, 13),
+                Cell(16, 15),
+                Cell(17, 16),
+            ],
+        );
+        let world = world(me, enemy);
+        let planner = Planner::new(&world, Rules::default());
+
+        let shortest = planner.route_home().unwrap();
+        assert_eq!(shortest.path, [Cell(16, 15), Cell(17, 15), Cell(18, 15)]);
+        assert!(!planner.is_safe(&shortest.path));
+
+        let safe = planner.safe_route_home().unwrap();
+        assert_eq!(safe.len(), 4);
+        assert!(planner.is_safe(&safe.path));
+        assert!(planner.is_safe_move(planner.next_move()));
+    }
+}
@@ -1,33 +1,105 @@
-use paperio_proto::{Cell, Direction, World, MAP_SIZE_CELLS};
-use std::cmp::{max, min};
+use crate::{planner::Planner, PARAMS_ENV};
+use paperio_proto::{
+    traits::Bot, view::WorldView, Cell, Direction, GameParams, Rules, World, MAP_SIZE_CELLS,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{max, min},
+    fs,
+    path::Path,
+};
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Weights of the rectangle score. A rectangle scores
+/// `territory_weight * new_territory - danger_weight * danger^2`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct StrategyParams {
+    pub territory_weight: f64,
+    pub danger_weight: f64,
+    /// Value of a cell of the rectangle that has an enemy trace in it.
+    pub enemy_trace_value: f64,
+    /// Value of a cell of the rectangle that belongs to an enemy.
+    pub enemy_cell_value: f64,
+    pub free_cell_value: f64,
+    /// Ticks added to the way around the rectangle when its danger is estimated.
+    pub route_margin: f64,
+}
+
+impl Default for StrategyParams {
+    fn default() -> Self {
+        Self {
+            territory_weight: 3.,
+            danger_weight: 1.,
+            enemy_trace_value: 5.,
+            enemy_cell_value: 3.,
+            free_cell_value: 1.,
+            route_margin: 2.,
+        }
+    }
+}
+
+impl StrategyParams {
+    /// Reads the parameters from a JSON file, missing ones keep their default values.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+        serde_json::from_str(&json)
+            .map_err(|err| format!("failed to parse {}: {err}", path.display()))
+    }
+
+    /// Reads the parameters from the file named by [`PARAMS_ENV`], the default ones if it isn't set.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(PARAMS_ENV) {
+            Ok(path) => Self::load(path),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, json + "\n")
+            .map_err(|err| format!("failed to write {}: {err}", path.display()))
+    }
+}
+
 pub struct Strategy {
     cur_goal_index: usize,
     plan: Cell,
     goals: Vec<Cell>,
     previous_direction: Direction,
     got_new_territory: bool,
+    rules: Rules,
+    params: StrategyParams,
 }
 
 impl Strategy {
-    const MIN_SCORE: i32 = -10000;
+    const MIN_SCORE: f64 = -10000.;
     pub fn new() -> Self {
+        Self::with_params(StrategyParams::default())
+    }
+
+    pub fn with_params(params: StrategyParams) -> Self {
         Self {
             plan: Cell(-1, -1),
             cur_goal_index: 0,
             previous_direction: Direction::Left, //not specified
             goals: Vec::new(),
             got_new_territory: true,
+            rules: Rules::default(),
+            params,
         }
     }
 
-    fn plan_route(world: &World) -> Cell {
+    fn plan_route(&self, world: &World) -> Cell {
+        let view = WorldView::new(world);
         let x0 = world.me().position.0;
         let y0 = world.me().position.1;
 
         let mut best_route: Cell = Cell(-1, -1); //to which cell should we move by rectangle direction
-        let mut best_route_score: i32 = Self::MIN_SCORE;
+        let mut best_route_score = Self::MIN_SCORE;
 
         for x in 0..MAP_SIZE_CELLS {
             for y in 0..MAP_SIZE_CELLS {
@@ -36,12 +108,13 @@ impl Strategy {
                 if rectangle_square <= 1 || dist(x, x0) == 0 || dist(y, y0) == 0 {
                     continue;
                 }
-                let rect_score = count_new_territory(world, Cell(x, y));
-                let danger = calculate_danger(x0, y0, x, y, world);
+                let rect_score = count_new_territory(&view, Cell(x, y), &self.params);
+                let danger = calculate_danger(x0, y0, x, y, world, &self.params);
 
-                let mut route_score = 3 * rect_score - danger * danger;
-                if rect_score <= 0 {
-                    route_score = Self::MIN_SCORE + 1;
+                let mut route_score = self.params.territory_weight * rect_score
+                    - self.params.danger_weight * danger * danger;
+                if rect_score <= 0. {
+                    route_score = Self::MIN_SCORE + 1.;
                 }
                 if route_score > best_route_score {
                     best_route = Cell(x, y);
@@ -71,10 +144,10 @@ impl Strategy {
         let my_pos = world.me().position;
 
         if (self.got_new_territory && own_territory(&world))
-            || my_pos == *self.goals.last().unwrap()
+            || self.goals.last().is_none_or(|&goal| goal == my_pos)
         {
             //change route
-            self.plan = Self::plan_route(&world);
+            self.plan = self.plan_route(&world);
             self.goals = self.create_route(my_pos);
             self.cur_goal_index = 0;
         } else if my_pos == self.goals[self.cur_goal_index] {
@@ -83,7 +156,18 @@ impl Strategy {
         }
 
         self.got_new_territory = !own_territory(&world);
-        self.make_move(my_pos)
+        let direction = self.make_move(my_pos);
+
+        // The rectangle may run into enemies or back into the trace, leave it then.
+        let planner = Planner::new(&world, self.rules);
+        if !planner.is_safe_move(direction) {
+            self.previous_direction = planner.next_move();
+            // The rectangle is left, so a new one is planned next tick.
+            self.plan = Cell(-1, -1);
+            self.goals.clear();
+            self.cur_goal_index = 0;
+        }
+        self.previous_direction
     }
 }
 fn own_territory(world: &World) -> bool {
@@ -99,8 +183,15 @@ fn dist(x1: i32, x2: i32) -> i32 {
     (x1 - x2).abs()
 }
 
-fn calculate_danger(x0: i32, y0: i32, x1: i32, y1: i32, world: &World) -> i32 {
-    let number_moves = (dist(x1, x0) + 2 + dist(y1, y0)) * 2;
+fn calculate_danger(
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32,
+    world: &World,
+    params: &StrategyParams,
+) -> f64 {
+    let number_moves = ((dist(x1, x0) + dist(y1, y0)) as f64 + params.route_margin) * 2.;
 
     let edges = [Cell(x0, y0), Cell(x1, y1), Cell(x0, y1), Cell(x1, y0)];
     let mut moves_to_kill = 31 * 31;
@@ -125,12 +216,12 @@ fn calculate_danger(x0: i32, y0: i32, x1: i32, y1: i32, world: &World) -> i32 {
             moves_to_kill = min(min_dist_to_edge, moves_to_kill);
         }
     }
-    number_moves - moves_to_kill
+    number_moves - moves_to_kill as f64
 }
 
-fn count_new_territory(world: &World, dst: Cell) -> i32 {
-    let mut count = 0;
-    let player = world.me();
+fn count_new_territory(view: &WorldView, dst: Cell, params: &StrategyParams) -> f64 {
+    let mut count = 0.;
+    let player = view.world().me();
 
     let x_from = min(player.position.0, dst.0);
     let y_from = min(player.position.1, dst.1);
@@ -139,26 +230,100 @@ fn count_new_territory(world: &World, dst: Cell) -> i32 {
 
     for x in x_from..=x_to {
         for y in y_from..=y_to {
-            if world
-                .iter_enemies()
-                .any(|(_id, enemy)| enemy.lines.contains(&Cell(x, y)))
-            {
-                count += 5;
-            } else if world
-                .iter_enemies()
-                .any(|(_id, enemy)| enemy.territory.contains(&Cell(x, y)))
-            {
-                count += 3;
-            } else if !player.territory.contains(&Cell(x, y)) {
-                count += 1;
+            let cell = Cell(x, y);
+            if view.trace_owner(cell).is_some_and(|id| id != "i") {
+                count += params.enemy_trace_value;
+            } else if view.owner(cell).is_some_and(|id| id != "i") {
+                count += params.enemy_cell_value;
+            } else if !view.is_mine(cell) {
+                count += params.free_cell_value;
             }
         }
     }
     count
 }
 
+impl Bot for Strategy {
+    fn on_start(&mut self, params: GameParams) {
+        self.rules = params.rules;
+    }
+
+    fn on_tick(&mut self, world: World) -> Direction {
+        Strategy::on_tick(self, world)
+    }
+}
+
 impl Default for Strategy {
     fn default() -> Self {
         Self::new()
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use paperio_proto::Player;
+
+    fn player(
+        position: Cell,
+        direction: Direction,
+        territory: Vec<Cell>,
+        lines: Vec<Cell>,
+    ) -> Player {
+        Player {
+            score: 0,
+            territory,
+            position,
+            lines,
+            direction: Some(direction),
+            has_lost: false,
+            speed_boost_ticks: 0,
+        }
+    }
+
+    #[test]
+    fn guard_drops_the_plan() {
+        let territory = (8..=10)
+            .flat_map(|x| (8..=10).map(move |y| Cell(x, y)))
+            .collect::<Vec<_>>();
+        let world = |position, direction, lines, enemy| World {
+            players: [
+                (
+                    "i".to_string(),
+                    player(position, direction, territory.clone(), lines),
+                ),
+                ("2".to_string(), enemy),
+            ]
+            .into(),
+            tick_num: 50,
+        };
+
+        // Going on to the right lets the enemy cut the trace.
+        let mut strategy = Strategy::new();
+        strategy.plan = Cell(20, 3);
+        strategy.goals = vec![Cell(20, 9), Cell(20, 3), Cell(10, 3)];
+        strategy.got_new_territory = false;
+        strategy.previous_direction = Direction::Right;
+
+        let lines = vec![Cell(11, 9), Cell(12, 9)];
+        let enemy = player(
+            Cell(12, 14),
+            Direction::Down,
+            vec![Cell(21, 21)],
+            vec![Cell(12, 14)],
+        );
+        let direction =
+            strategy.on_tick(world(Cell(12, 9), Direction::Right, lines.clone(), enemy));
+        assert_ne!(direction, Direction::Right);
+        assert!(strategy.goals.is_empty());
+
+        // Once the enemy is gone, a new rectangle is planned from where I am.
+        let position = Cell(12, 9) + direction;
+        let lines = [lines, vec![position]].concat();
+        let enemy = player(Cell(25, 25), Direction::Left, vec![Cell(27, 27)], vec![]);
+        strategy.on_tick(world(position, direction, lines, enemy));
+        assert_eq!(strategy.goals.last(), Some(&position));
+    }
+}
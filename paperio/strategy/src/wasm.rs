@@ -0,0 +1,53 @@
+//! The strategy as it is built for wasm. Besides `main` with the usual protocol,
+//! the module exports the typed interface, and the launcher picks the mode itself.
+//! Exports need `#[no_mangle]`, so this is the only part of the strategy that
+//! may not forbid unsafe code. Everything else lives in the library.
+#![cfg_attr(not(target_family = "wasm"), forbid(unsafe_code))]
+
+/// Typed interface of [`paperio_proto::abi`], the host calls these instead of `main`.
+#[cfg(target_family = "wasm")]
+mod exports {
+    use std::cell::RefCell;
+
+    use paperio_proto::{abi, traits::Bot, GameParams, World};
+    use paperio_strategy::client;
+
+    thread_local! {
+        static INPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+        static STRATEGY: RefCell<Option<Box<dyn Bot + Send>>> = const { RefCell::new(None) };
+    }
+
+    #[no_mangle]
+    pub extern "C" fn paperio_input_buffer(len: u32) -> u32 {
+        INPUT.with_borrow_mut(|input| {
+            input.clear();
+            input.resize(len as usize, 0);
+            input.as_mut_ptr() as u32
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn paperio_on_start(len: u32) {
+        let params = INPUT
+            .with_borrow(|input| abi::decode::<GameParams>(&input[..len as usize]))
+            .expect("invalid game params");
+        let mut strategy = client::create_strategy();
+        strategy.on_start(params);
+        STRATEGY.set(Some(strategy));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn paperio_on_tick(len: u32) -> i32 {
+        let world = INPUT
+            .with_borrow(|input| abi::decode::<World>(&input[..len as usize]))
+            .expect("invalid world");
+        STRATEGY.with_borrow_mut(|strategy| {
+            let strategy = strategy.as_mut().expect("the game hasn't started");
+            abi::direction_code(strategy.on_tick(world))
+        })
+    }
+}
+
+fn main() {
+    paperio_strategy::client::main();
+}
@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::*;
+use paperio_server::server::TimeBudget;
+use paperio_strategy::strategy::StrategyParams;
+use paperio_tournament::{
+    bot::BotSpec,
+    tune::{TuneConfig, Tuner},
+};
+
+use std::{path::PathBuf, thread, time::Duration};
+
+/// Tunes the parameters of the strategy from `paperio-strategy`
+/// by playing it against the given bots.
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Arguments {
+    /// Opponents: native executables or `.wasm` bots, e.g. the ones from `bots`.
+    #[arg(required = true)]
+    opponents: Vec<String>,
+
+    /// Parameters to start from, the default ones if not set.
+    #[arg(short, long)]
+    params: Option<PathBuf>,
+
+    /// Where the best parameters are written every time they improve and at the end.
+    #[arg(short, long, default_value = "strategy_params.json")]
+    output: PathBuf,
+
+    #[arg(short, long, default_value_t = 50)]
+    iterations: usize,
+
+    /// Games played by every candidate.
+    #[arg(short, long, default_value_t = 8)]
+    games: usize,
+
+    #[arg(short, long, default_value_t = 300)]
+    tick_count: usize,
+
+    /// Number of games played in parallel, all cores by default.
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Initial relative change of the parameters.
+    #[arg(long, default_value_t = 0.3)]
+    step: f64,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Time for a bot to answer a tick, a late answer is ignored.
+    #[arg(long)]
+    tick_timeout_ms: Option<u64>,
+
+    #[arg(short, long, default_value_t = 2)]
+    log_level: usize,
+}
+
+fn main() -> Result<()> {
+    let args = Arguments::parse();
+
+    stderrlog::new()
+        .verbosity(args.log_level)
+        .init()
+        .context("failed to initialize stderr logger")?;
+
+    let opponents = args
+        .opponents
+        .iter()
+        .map(|arg| BotSpec::load(arg))
+        .collect::<Result<_>>()?;
+    let start = match &args.params {
+        Some(path) => StrategyParams::load(path).map_err(anyhow::Error::msg)?,
+        None => StrategyParams::default(),
+    };
+
+    let jobs = match args.jobs {
+        Some(jobs) => jobs,
+        None => thread::available_parallelism()?.get(),
+    };
+    let config = TuneConfig {
+        iterations: args.iterations,
+        games: args.games,
+        tick_count: args.tick_count,
+        jobs,
+        step: args.step,
+        seed: args.seed,
+        time_budget: TimeBudget {
+            per_tick: args.tick_timeout_ms.map(Duration::from_millis),
+            total: None,
+        },
+    };
+
+    let (best, fitness) = Tuner::new(opponents, config)?.run(start, |params, fitness| {
+        info!("new best fitness {fitness:.3}, saving to {:?}", args.output);
+        params.save(&args.output).map_err(anyhow::Error::msg)
+    })?;
+    best.save(&args.output).map_err(anyhow::Error::msg)?;
+    println!("best fitness {fitness:.3}");
+    println!("{}", serde_json::to_string_pretty(&best)?);
+
+    Ok(())
+}
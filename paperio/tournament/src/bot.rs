@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use log::*;
+use paperio_server::{
+    endpoint::{BotEndpoint, Endpoint, StreamEndpoint},
+    server::TimeBudget,
+};
+use paperio_strategy::{strategy::StrategyParams, StrategyKind};
+use paperio_wasm_launcher::{CompiledStrategy, Interrupter, RunError, WasmStrategyRunner};
+
+use std::{
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+/// Name of the bot from `paperio-strategy`, which is run in-process.
+pub const BUILTIN_BOT: &str = "builtin";
+
+/// How much of stderr of a wasm bot is kept, per game.
+const WASM_LOG_LIMIT: usize = 64 << 10;
+
+/// Memory a wasm bot may use, the same as `cargo xtask` gives strategies.
+const WASM_MEMORY_LIMIT: usize = 256 << 20;
+
+#[derive(Clone)]
+pub enum BotSource {
+    /// Native executable talking JSON over stdin and stdout.
+    Native(PathBuf),
+    /// Wasm module run by `WasmStrategyRunner` over in-memory pipes,
+    /// or called directly if it has the typed interface of `paperio_proto::abi`.
+    Wasm(CompiledStrategy),
+    /// Strategy from `paperio-strategy`, called directly from the server thread.
+    Builtin(StrategyKind, StrategyParams),
+}
+
+#[derive(Clone)]
+pub struct BotSpec {
+    pub name: String,
+    pub source: BotSource,
+}
+
+impl BotSpec {
+    /// Accepts a path to an executable or a `.wasm` file, or `builtin`
+    /// optionally followed by the kind of the strategy, e.g. `builtin:mcts`,
+    /// or by a JSON file with its parameters, e.g. `builtin:params.json`.
+    pub fn load(arg: &str) -> Result<Self> {
+        if arg == BUILTIN_BOT {
+            return Ok(Self {
+                name: BUILTIN_BOT.to_string(),
+                source: BotSource::Builtin(StrategyKind::default(), StrategyParams::default()),
+            });
+        }
+        if let Some(suffix) = arg
+            .strip_prefix(BUILTIN_BOT)
+            .and_then(|suffix| suffix.strip_prefix(':'))
+        {
+            let source = if suffix.ends_with(".json") {
+                let params = StrategyParams::load(suffix).map_err(anyhow::Error::msg)?;
+                BotSource::Builtin(StrategyKind::Rectangles, params)
+            } else {
+                let kind = suffix.parse::<StrategyKind>().map_err(anyhow::Error::msg)?;
+                BotSource::Builtin(kind, StrategyParams::default())
+            };
+            return Ok(Self {
+                name: arg.to_string(),
+                source,
+            });
+        }
+
+        let path = PathBuf::from(arg);
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| arg.to_string());
+        let source = if path.extension().is_some_and(|ext| ext == "wasm") {
+            let strategy = CompiledStrategy::load(&path)
+                .with_context(|| format!("failed to compile {path:?}"))?;
+            BotSource::Wasm(strategy)
+        } else {
+            BotSource::Native(path)
+        };
+        Ok(Self { name, source })
+    }
+
+    /// Wasm bots are stopped once a tick takes longer than `time_budget.per_tick`,
+    /// since the server can't preempt the ones called from its thread.
+    pub fn launch(&self, time_budget: TimeBudget) -> Result<BotInstance> {
+        match &self.source {
+            BotSource::Native(path) => launch_native(path),
+            BotSource::Wasm(strategy) => launch_wasm(strategy, time_budget),
+            BotSource::Builtin(kind, params) => Ok(BotInstance {
+                endpoint: Some(Box::new(BotEndpoint::new(kind.create_with(*params)))),
+                process: BotProcess::InProcess,
+            }),
+        }
+        .with_context(|| format!("failed to launch bot {:?}", self.name))
+    }
+}
+
+enum BotProcess {
+    InProcess,
+    Native(Child),
+    Wasm {
+        handle: JoinHandle<()>,
+        interrupter: Interrupter,
+    },
+}
+
+/// A running bot. The endpoint must be dropped before the instance is shut down,
+/// so that the bot sees the end of its input.
+pub struct BotInstance {
+    endpoint: Option<Box<dyn Endpoint + Send>>,
+    process: BotProcess,
+}
+
+impl BotInstance {
+    pub fn take_endpoint(&mut self) -> Box<dyn Endpoint + Send> {
+        self.endpoint.take().expect("endpoint is already taken")
+    }
+
+    pub fn shutdown(self) {
+        drop(self.endpoint);
+        match self.process {
+            BotProcess::InProcess => {}
+            BotProcess::Native(mut child) => {
+                let _ = child.kill();
+                if let Err(err) = child.wait() {
+                    warn!("failed to wait for bot process: {err}");
+                }
+            }
+            BotProcess::Wasm {
+                handle,
+                interrupter,
+            } => {
+                interrupter.interrupt();
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+fn launch_native(path: &Path) -> Result<BotInstance> {
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let reader = BufReader::new(child.stdout.take().unwrap());
+    let writer = BufWriter::new(child.stdin.take().unwrap());
+
+    Ok(BotInstance {
+        endpoint: Some(Box::new(StreamEndpoint::new(reader, writer))),
+        process: BotProcess::Native(child),
+    })
+}
+
+fn launch_wasm(strategy: &CompiledStrategy, time_budget: TimeBudget) -> Result<BotInstance> {
+    let mut runner =
+        WasmStrategyRunner::from_compiled(strategy).memory_size_limit(WASM_MEMORY_LIMIT);
+    if let Some(timeout) = time_budget.per_tick {
+        runner = runner.tick_timeout(timeout);
+    }
+
+    // Strategies with the typed interface are called directly from the server thread.
+    if strategy.has_abi() {
+        let bot = runner.into_bot()?;
+        return Ok(BotInstance {
+            endpoint: Some(Box::new(BotEndpoint::new(bot))),
+            process: BotProcess::InProcess,
+        });
+    }
+
+    let (runner, stdin, stdout) = runner.in_memory_io();
+    let (runner, log) = runner.stderr_log(WASM_LOG_LIMIT);
+    let interrupter = runner.make_iterrupter();
+
+    let handle = thread::spawn(move || {
+        match runner.run() {
+            Ok(status) => {
+                match status.result {
+                    Ok(()) => {}
+                    // Bots are interrupted when the game is over.
+                    Err(RunError::Interrupted) => debug!("wasm bot stopped: interrupted"),
+                    Err(err) => warn!("wasm bot stopped: {err}"),
+                }
+            }
+            Err(err) => error!("failed to run wasm bot: {err}"),
+        }
+        let contents = log.contents();
+        if !contents.is_empty() {
+            debug!("wasm bot stderr:\n{}", String::from_utf8_lossy(&contents));
+        }
+    });
+
+    Ok(BotInstance {
+        endpoint: Some(Box::new(StreamEndpoint::new(
+            BufReader::new(stdout),
+            BufWriter::new(stdin),
+        ))),
+        process: BotProcess::Wasm {
+            handle,
+            interrupter,
+        },
+    })
+}
@@ -0,0 +1,429 @@
+pub mod bot;
+pub mod rating;
+pub mod tune;
+
+use anyhow::{ensure, Result};
+use log::*;
+use paperio_proto::PlayerInfo;
+use paperio_server::{
+    endpoint::Endpoint,
+    game::MAX_PLAYER_COUNT,
+    player_vec::PlayerIndexedVector,
+    server::{Server, TimeBudget},
+};
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{bot::BotSpec, rating::EloRatings};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Format {
+    /// Every combination of bots plays `rounds` games, rotating seats.
+    RoundRobin,
+    /// Each round bots with close ratings play against each other.
+    Swiss,
+}
+
+#[derive(Clone, Debug)]
+pub struct TournamentConfig {
+    pub format: Format,
+    pub rounds: usize,
+    pub players_per_game: usize,
+    pub tick_count: usize,
+    pub jobs: usize,
+    pub k_factor: f64,
+    pub time_budget: TimeBudget,
+    /// Seed of the spawn positions, every game gets its own one derived from it.
+    /// Random if not set.
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BotStats {
+    pub name: String,
+    pub rating: f64,
+    pub games: usize,
+    pub wins: usize,
+    pub total_score: u64,
+}
+
+impl BotStats {
+    pub fn win_rate(&self) -> f64 {
+        if self.games == 0 {
+            0.
+        } else {
+            self.wins as f64 / self.games as f64
+        }
+    }
+
+    pub fn average_score(&self) -> f64 {
+        if self.games == 0 {
+            0.
+        } else {
+            self.total_score as f64 / self.games as f64
+        }
+    }
+}
+
+/// Final results, sorted by rating.
+pub struct Standings(pub Vec<BotStats>);
+
+struct GameOutcome {
+    scores: Vec<u32>,
+    winner: Option<usize>,
+}
+
+pub struct Tournament {
+    bots: Vec<BotSpec>,
+    config: TournamentConfig,
+}
+
+impl Tournament {
+    pub fn new(bots: Vec<BotSpec>, config: TournamentConfig) -> Result<Self> {
+        ensure!(
+            config.players_per_game >= 2,
+            "at least two players are required for a game"
+        );
+        ensure!(
+            config.players_per_game <= MAX_PLAYER_COUNT,
+            "a game can't have more than {MAX_PLAYER_COUNT} players"
+        );
+        ensure!(
+            bots.len() >= config.players_per_game,
+            "{} bots are not enough for {}-player games",
+            bots.len(),
+            config.players_per_game
+        );
+        Ok(Self { bots, config })
+    }
+
+    pub fn run(&self) -> Standings {
+        let mut ratings = EloRatings::new(self.bots.len(), self.config.k_factor);
+        let mut stats = self
+            .bots
+            .iter()
+            .map(|bot| BotStats {
+                name: bot.name.clone(),
+                rating: 0.,
+                games: 0,
+                wins: 0,
+                total_score: 0,
+            })
+            .collect::<Vec<_>>();
+
+        let rounds = match self.config.format {
+            Format::RoundRobin => 1,
+            Format::Swiss => self.config.rounds,
+        };
+        for round in 0..rounds {
+            let games = match self.config.format {
+                Format::RoundRobin => round_robin_schedule(
+                    self.bots.len(),
+                    self.config.players_per_game,
+                    self.config.rounds,
+                ),
+                Format::Swiss => swiss_schedule(&ratings, self.config.players_per_game, round),
+            };
+            info!("round #{}: {} game(s)", round + 1, games.len());
+
+            // Ratings are updated in schedule order, so results don't depend on
+            // which game happened to finish first.
+            for (seats, outcome) in games.iter().zip(self.play_games(round, &games)) {
+                let Some(outcome) = outcome else {
+                    continue;
+                };
+
+                let participants = seats
+                    .iter()
+                    .copied()
+                    .zip(outcome.scores.iter().copied())
+                    .collect::<Vec<_>>();
+                ratings.update(&participants);
+
+                for (seat, &(bot, score)) in participants.iter().enumerate() {
+                    stats[bot].games += 1;
+                    stats[bot].total_score += score as u64;
+                    if outcome.winner == Some(seat) {
+                        stats[bot].wins += 1;
+                    }
+                }
+            }
+        }
+
+        for (bot, bot_stats) in stats.iter_mut().enumerate() {
+            bot_stats.rating = ratings.get(bot);
+        }
+        stats.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        Standings(stats)
+    }
+
+    fn play_games(&self, round: usize, games: &[Vec<usize>]) -> Vec<Option<GameOutcome>> {
+        let next_game = AtomicUsize::new(0);
+        let outcomes = Mutex::new((0..games.len()).map(|_| None).collect::<Vec<_>>());
+
+        thread::scope(|scope| {
+            for _ in 0..self.config.jobs.max(1) {
+                scope.spawn(|| loop {
+                    let index = next_game.fetch_add(1, Ordering::Relaxed);
+                    let Some(seats) = games.get(index) else {
+                        break;
+                    };
+
+                    let seed = self.config.seed.map(|seed| game_seed(seed, round, index));
+                    match self.play_game(seats, seed) {
+                        Ok(outcome) => outcomes.lock().unwrap()[index] = Some(outcome),
+                        Err(err) => error!("game #{index} failed: {err:#}"),
+                    }
+                });
+            }
+        });
+
+        outcomes.into_inner().unwrap()
+    }
+
+    fn play_game(&self, seats: &[usize], seed: Option<u64>) -> Result<GameOutcome> {
+        let mut instances = seats
+            .iter()
+            .map(|&bot| self.bots[bot].launch(self.config.time_budget))
+            .collect::<Result<Vec<_>>>()?;
+
+        let endpoints = instances
+            .iter_mut()
+            .map(|instance| instance.take_endpoint())
+            .collect::<PlayerIndexedVector<_>>();
+        let player_infos = seats
+            .iter()
+            .map(|&bot| PlayerInfo {
+                user_name: self.bots[bot].name.clone(),
+            })
+            .collect();
+        let mut server = Server::new(endpoints, Vec::<Box<dyn Endpoint + Send>>::new())
+            .time_budget(self.config.time_budget)
+            .player_infos(player_infos);
+        if let Some(seed) = seed {
+            server = server.seed(seed);
+        }
+        let result = server.run(self.config.tick_count);
+
+        for instance in instances {
+            instance.shutdown();
+        }
+
+        let mut scores = vec![];
+        for (player_id, player) in result.players.iter() {
+            let name = &self.bots[seats[player_id.get() - 1]].name;
+            if let Some(err) = &player.io_error {
+                warn!("bot {name:?} had an io error: {err}");
+            }
+            if player.disqualified {
+                warn!("bot {name:?} was disqualified for exceeding the time budget");
+            } else if player.timing.late_ticks > 0 {
+                warn!(
+                    "bot {name:?} was late on {} tick(s)",
+                    player.timing.late_ticks
+                );
+            }
+            scores.push(player.score);
+        }
+
+        Ok(GameOutcome {
+            scores,
+            winner: result.winner.map(|player_id| player_id.get() - 1),
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Seed of a game, the same for the same place in the schedule.
+fn game_seed(seed: u64, round: usize, index: usize) -> u64 {
+    seed.wrapping_add(((round as u64) << 32) + index as u64)
+}
+
+/// All combinations of `players_per_game` bots, each played `repeats` times
+/// with seats rotated, since spawn positions are not symmetric.
+fn round_robin_schedule(
+    bot_count: usize,
+    players_per_game: usize,
+    repeats: usize,
+) -> Vec<Vec<usize>> {
+    fn combinations(
+        start: usize,
+        bot_count: usize,
+        size: usize,
+        current: &mut Vec<usize>,
+        result: &mut Vec<Vec<usize>>,
+    ) {
+        if current.len() == size {
+            result.push(current.clone());
+            return;
+        }
+        for bot in start..bot_count {
+            current.push(bot);
+            combinations(bot + 1, bot_count, size, current, result);
+            current.pop();
+        }
+    }
+
+    let mut groups = vec![];
+    combinations(0, bot_count, players_per_game, &mut vec![], &mut groups);
+
+    (0..repeats)
+        .flat_map(|repeat| {
+            groups.iter().map(move |group| {
+                let mut seats = group.clone();
+                seats.rotate_left(repeat % players_per_game);
+                seats
+            })
+        })
+        .collect()
+}
+
+/// Bots are sorted by rating and split into groups of neighbours.
+/// A single bot left without a group skips the round.
+fn swiss_schedule(ratings: &EloRatings, players_per_game: usize, round: usize) -> Vec<Vec<usize>> {
+    let mut bots = (0..ratings.len()).collect::<Vec<_>>();
+    bots.sort_by(|&a, &b| ratings.get(b).total_cmp(&ratings.get(a)).then(a.cmp(&b)));
+
+    bots.chunks(players_per_game)
+        .filter(|group| group.len() >= 2)
+        .map(|group| {
+            let mut seats = group.to_vec();
+            seats.rotate_left(round % group.len());
+            seats
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl fmt::Display for Standings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name_width = self
+            .0
+            .iter()
+            .map(|stats| stats.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+
+        writeln!(
+            f,
+            "{:>4}  {:<name_width$}  {:>7}  {:>5}  {:>5}  {:>8}  {:>9}",
+            "#", "Bot", "Rating", "Games", "Wins", "Win rate", "Avg score"
+        )?;
+        for (place, stats) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4}  {:<name_width$}  {:>7.1}  {:>5}  {:>5}  {:>7.1}%  {:>9.1}",
+                place + 1,
+                stats.name,
+                stats.rating,
+                stats.games,
+                stats.wins,
+                stats.win_rate() * 100.,
+                stats.average_score(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bot::BotSource;
+    use paperio_server::endpoint::BotEndpoint;
+    use paperio_strategy::{mcts::Mcts, strategy::StrategyParams, StrategyKind};
+
+    #[test]
+    fn round_robin_covers_all_groups() {
+        let games = round_robin_schedule(5, 4, 2);
+        assert_eq!(games.len(), 10);
+        for bot in 0..5 {
+            let count = games.iter().filter(|seats| seats.contains(&bot)).count();
+            assert_eq!(count, 8);
+        }
+        assert_eq!(games[0], vec![0, 1, 2, 3]);
+        assert_eq!(games[5], vec![1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn swiss_groups_neighbours() {
+        let mut ratings = EloRatings::new(5, 32.);
+        ratings.update(&[(4, 10), (3, 5)]);
+
+        let games = swiss_schedule(&ratings, 2, 0);
+        assert_eq!(games, vec![vec![4, 0], vec![1, 2]]);
+    }
+
+    #[test]
+    fn rejects_oversized_games() {
+        let bots = (0..=MAX_PLAYER_COUNT)
+            .map(|index| BotSpec {
+                name: format!("bot{index}"),
+                source: BotSource::Builtin(StrategyKind::Rectangles, StrategyParams::default()),
+            })
+            .collect::<Vec<_>>();
+        let config = TournamentConfig {
+            format: Format::RoundRobin,
+            rounds: 1,
+            players_per_game: MAX_PLAYER_COUNT + 1,
+            tick_count: 10,
+            jobs: 1,
+            k_factor: 32.,
+            time_budget: TimeBudget::default(),
+            seed: Some(0),
+        };
+        assert!(Tournament::new(bots.clone(), config.clone()).is_err());
+
+        let config = TournamentConfig {
+            players_per_game: MAX_PLAYER_COUNT,
+            ..config
+        };
+        assert!(Tournament::new(bots, config).is_ok());
+    }
+
+    #[test]
+    fn mcts_beats_aggressive() {
+        let aggressive = BotSpec::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../bots/aggressive.wasm"
+        ))
+        .unwrap();
+        let games = 4;
+        let mut wins = 0;
+        for seed in 0..games {
+            let mut instance = aggressive.launch(TimeBudget::default()).unwrap();
+            let mcts = BotEndpoint::new(Mcts::with_seed(seed).iterations(50));
+            let mut endpoints: Vec<Box<dyn Endpoint + Send>> =
+                vec![Box::new(mcts), instance.take_endpoint()];
+            // Spawn positions are not symmetric.
+            let mcts_seat = seed as usize % 2;
+            if mcts_seat == 1 {
+                endpoints.reverse();
+            }
+
+            let result = Server::new(
+                endpoints.into_iter().collect(),
+                Vec::<Box<dyn Endpoint + Send>>::new(),
+            )
+            .seed(seed)
+            .run(300);
+            instance.shutdown();
+            if result.winner.map(|player_id| player_id.get() - 1) == Some(mcts_seat) {
+                wins += 1;
+            }
+        }
+        assert!(wins >= 3, "mcts won {wins} of {games} games");
+    }
+}
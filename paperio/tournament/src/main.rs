@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use paperio_server::server::TimeBudget;
+use paperio_tournament::{bot::BotSpec, Format, Tournament, TournamentConfig};
+
+use std::{collections::HashMap, thread, time::Duration};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Arguments {
+    /// Strategies to play: native executables, `.wasm` bots
+    /// or `builtin` for the strategy from `paperio-strategy`, `builtin:mcts` for its tree search.
+    #[arg(required = true)]
+    bots: Vec<String>,
+
+    #[arg(short, long, value_enum, default_value_t = Format::RoundRobin)]
+    format: Format,
+
+    /// Number of swiss rounds, or how many times each round robin group plays.
+    #[arg(short, long, default_value_t = 4)]
+    rounds: usize,
+
+    #[arg(short = 'n', long, default_value_t = 4)]
+    players_per_game: usize,
+
+    #[arg(short, long, default_value_t = 300)]
+    tick_count: usize,
+
+    /// Number of games played in parallel, all cores by default.
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    #[arg(short, long, default_value_t = 32.)]
+    k_factor: f64,
+
+    /// Time for a bot to answer a tick, a late answer is ignored.
+    #[arg(long)]
+    tick_timeout_ms: Option<u64>,
+
+    /// Total time for a bot to answer all ticks of a game.
+    #[arg(long)]
+    time_budget_ms: Option<u64>,
+
+    /// Seed of the spawn positions, random if not set.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    #[arg(short, long, default_value_t = 1)]
+    log_level: usize,
+}
+
+fn main() -> Result<()> {
+    let args = Arguments::parse();
+
+    stderrlog::new()
+        .verbosity(args.log_level)
+        .init()
+        .context("failed to initialize stderr logger")?;
+
+    // The same bot may be passed several times, give each copy a unique name.
+    let mut name_counts = HashMap::<String, usize>::new();
+    let bots = args
+        .bots
+        .iter()
+        .map(|arg| {
+            let mut bot = BotSpec::load(arg)?;
+            let count = name_counts.entry(bot.name.clone()).or_default();
+            *count += 1;
+            if *count > 1 {
+                bot.name = format!("{}#{count}", bot.name);
+            }
+            Ok(bot)
+        })
+        .collect::<Result<_>>()?;
+
+    let jobs = match args.jobs {
+        Some(jobs) => jobs,
+        None => thread::available_parallelism()?.get(),
+    };
+    let config = TournamentConfig {
+        format: args.format,
+        rounds: args.rounds,
+        players_per_game: args.players_per_game,
+        tick_count: args.tick_count,
+        jobs,
+        k_factor: args.k_factor,
+        time_budget: TimeBudget {
+            per_tick: args.tick_timeout_ms.map(Duration::from_millis),
+            total: args.time_budget_ms.map(Duration::from_millis),
+        },
+        seed: args.seed,
+    };
+
+    let standings = Tournament::new(bots, config)?.run();
+    print!("{standings}");
+
+    Ok(())
+}
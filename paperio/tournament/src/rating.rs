@@ -0,0 +1,82 @@
+pub const INITIAL_RATING: f64 = 1500.;
+
+/// Multiplayer Elo: every game is treated as a set of pairwise matches
+/// between all its participants, ordered by their final scores.
+pub struct EloRatings {
+    ratings: Vec<f64>,
+    k_factor: f64,
+}
+
+impl EloRatings {
+    pub fn new(player_count: usize, k_factor: f64) -> Self {
+        Self {
+            ratings: vec![INITIAL_RATING; player_count],
+            k_factor,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ratings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ratings.is_empty()
+    }
+
+    pub fn get(&self, player: usize) -> f64 {
+        self.ratings[player]
+    }
+
+    /// Updates ratings after a game; `participants` are pairs of player index and score.
+    pub fn update(&mut self, participants: &[(usize, u32)]) {
+        if participants.len() < 2 {
+            return;
+        }
+
+        let k_factor = self.k_factor / (participants.len() - 1) as f64;
+        let deltas = participants
+            .iter()
+            .map(|&(player, score)| {
+                participants
+                    .iter()
+                    .filter(|&&(other, _)| other != player)
+                    .map(|&(other, other_score)| {
+                        let actual = match score.cmp(&other_score) {
+                            std::cmp::Ordering::Less => 0.,
+                            std::cmp::Ordering::Equal => 0.5,
+                            std::cmp::Ordering::Greater => 1.,
+                        };
+                        actual - self.expected_score(player, other)
+                    })
+                    .sum::<f64>()
+                    * k_factor
+            })
+            .collect::<Vec<_>>();
+
+        for (&(player, _), delta) in participants.iter().zip(deltas) {
+            self.ratings[player] += delta;
+        }
+    }
+
+    fn expected_score(&self, player: usize, other: usize) -> f64 {
+        1. / (1. + 10f64.powf((self.ratings[other] - self.ratings[player]) / 400.))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn winner_gains_rating() {
+        let mut ratings = EloRatings::new(3, 32.);
+        ratings.update(&[(0, 10), (1, 5), (2, 5)]);
+
+        assert!(ratings.get(0) > INITIAL_RATING);
+        assert!(ratings.get(1) < INITIAL_RATING);
+        assert_eq!(ratings.get(1), ratings.get(2));
+
+        let total = (0..3).map(|i| ratings.get(i)).sum::<f64>();
+        assert!((total - 3. * INITIAL_RATING).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,195 @@
+//! Random search over [`StrategyParams`]: the parameters are perturbed
+//! and kept if the strategy plays better with them against the opponents.
+
+use anyhow::{ensure, Result};
+use log::*;
+use paperio_server::{game::MAX_PLAYER_COUNT, server::TimeBudget};
+use paperio_strategy::{strategy::StrategyParams, StrategyKind};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::Value;
+
+use crate::{
+    bot::{BotSource, BotSpec},
+    Format, Tournament, TournamentConfig,
+};
+
+/// Name of the tuned strategy in the games.
+const CANDIDATE_BOT: &str = "candidate";
+
+const MIN_STEP: f64 = 0.01;
+const MAX_STEP: f64 = 1.;
+/// The step grows after a success and shrinks after a failure, so that
+/// roughly one candidate out of five is accepted.
+const SUCCESS_FACTOR: f64 = 1.5;
+const FAILURE_FACTOR: f64 = 0.9;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug)]
+pub struct TuneConfig {
+    pub iterations: usize,
+    /// Number of games each candidate plays against all opponents at once.
+    pub games: usize,
+    pub tick_count: usize,
+    pub jobs: usize,
+    /// Initial relative change of the parameters.
+    pub step: f64,
+    pub seed: u64,
+    pub time_budget: TimeBudget,
+}
+
+pub struct Tuner {
+    opponents: Vec<BotSpec>,
+    config: TuneConfig,
+    rng: StdRng,
+}
+
+impl Tuner {
+    pub fn new(opponents: Vec<BotSpec>, config: TuneConfig) -> Result<Self> {
+        ensure!(!opponents.is_empty(), "at least one opponent is required");
+        ensure!(
+            opponents.len() < MAX_PLAYER_COUNT,
+            "at most {} opponents fit in a game with the candidate",
+            MAX_PLAYER_COUNT - 1
+        );
+        ensure!(
+            config.games > 0,
+            "at least one game per candidate is required"
+        );
+        let rng = StdRng::seed_from_u64(config.seed);
+        Ok(Self {
+            opponents,
+            config,
+            rng,
+        })
+    }
+
+    /// Share of all points scored by the strategy with the given parameters
+    /// in games with spawn positions derived from `seed`.
+    pub fn evaluate(&self, params: StrategyParams, seed: u64) -> Result<f64> {
+        let mut bots = vec![BotSpec {
+            name: CANDIDATE_BOT.to_string(),
+            source: BotSource::Builtin(StrategyKind::Rectangles, params),
+        }];
+        bots.extend(self.opponents.iter().cloned());
+
+        let config = TournamentConfig {
+            format: Format::RoundRobin,
+            rounds: self.config.games,
+            players_per_game: bots.len(),
+            tick_count: self.config.tick_count,
+            jobs: self.config.jobs,
+            k_factor: 32.,
+            time_budget: self.config.time_budget,
+            seed: Some(seed),
+        };
+        let standings = Tournament::new(bots, config)?.run();
+
+        let total = standings
+            .0
+            .iter()
+            .map(|stats| stats.average_score())
+            .sum::<f64>();
+        let candidate = standings
+            .0
+            .iter()
+            .find(|stats| stats.name == CANDIDATE_BOT)
+            .unwrap();
+        Ok(if total > 0. {
+            candidate.average_score() / total
+        } else {
+            0.
+        })
+    }
+
+    /// Searches from `start` and returns the best parameters with their fitness.
+    /// `on_improvement` is called every time better parameters are found.
+    ///
+    /// Every iteration the best parameters play the same games as the candidate,
+    /// so that neither gets luckier spawns and a lucky result doesn't stick.
+    pub fn run(
+        &mut self,
+        start: StrategyParams,
+        mut on_improvement: impl FnMut(&StrategyParams, f64) -> Result<()>,
+    ) -> Result<(StrategyParams, f64)> {
+        let mut best = start;
+        let seed = self.rng.gen();
+        let mut best_fitness = self.evaluate(best, seed)?;
+        info!("initial fitness {best_fitness:.3}");
+
+        let mut step = self.config.step;
+        for iteration in 0..self.config.iterations {
+            let candidate = perturb(&best, step, &mut self.rng);
+            let seed = self.rng.gen();
+            best_fitness = self.evaluate(best, seed)?;
+            let fitness = self.evaluate(candidate, seed)?;
+            info!(
+                "iteration #{}: fitness {fitness:.3}, best {best_fitness:.3}, step {step:.3}",
+                iteration + 1
+            );
+
+            if fitness > best_fitness {
+                best = candidate;
+                best_fitness = fitness;
+                step = (step * SUCCESS_FACTOR).min(MAX_STEP);
+                on_improvement(&best, best_fitness)?;
+            } else {
+                step = (step * FAILURE_FACTOR).max(MIN_STEP);
+            }
+        }
+        Ok((best, best_fitness))
+    }
+}
+
+/// Changes every parameter by up to `step` of its value, parameters close
+/// to zero are changed by up to `step`. None of them gets negative.
+fn perturb(params: &StrategyParams, step: f64, rng: &mut impl Rng) -> StrategyParams {
+    let mut value = serde_json::to_value(params).unwrap();
+    if let Value::Object(fields) = &mut value {
+        for field in fields.values_mut() {
+            if let Some(x) = field.as_f64() {
+                let delta = step * x.abs().max(1.) * rng.gen_range(-1.0..=1.0);
+                *field = Value::from((x + delta).max(0.));
+            }
+        }
+    }
+    serde_json::from_value(value).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn perturb_stays_close() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let params = StrategyParams::default();
+        assert_eq!(perturb(&params, 0., &mut rng), params);
+
+        let changed = perturb(&params, 0.5, &mut rng);
+        assert_ne!(changed, params);
+        assert!(changed.territory_weight >= 1.5 && changed.territory_weight <= 4.5);
+        assert!(changed.free_cell_value >= 0. && changed.free_cell_value <= 1.5);
+    }
+
+    #[test]
+    fn rejects_too_many_opponents() {
+        let opponents = (0..MAX_PLAYER_COUNT)
+            .map(|index| BotSpec {
+                name: format!("opponent{index}"),
+                source: BotSource::Builtin(StrategyKind::Rectangles, StrategyParams::default()),
+            })
+            .collect::<Vec<_>>();
+        let config = TuneConfig {
+            iterations: 1,
+            games: 1,
+            tick_count: 10,
+            jobs: 1,
+            step: 0.1,
+            seed: 0,
+            time_budget: TimeBudget::default(),
+        };
+        assert!(Tuner::new(opponents.clone(), config.clone()).is_err());
+        assert!(Tuner::new(opponents[1..].to_vec(), config).is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Context, Result};
+use paperio_proto::{abi, traits::Bot, Direction, GameParams, World};
+use wasmtime::{Memory, Store, TypedFunc};
+
+use crate::{
+    ticks::{self, EpochTimer},
+    AppState, RunError, Sandbox,
+};
+
+/// Strategy with the typed interface of [`paperio_proto::abi`]. After an error
+/// it isn't called anymore and keeps going straight, see [`WasmBot::error`].
+pub struct WasmBot {
+    store: Store<AppState>,
+    memory: Memory,
+    input_buffer: TypedFunc<u32, u32>,
+    on_start: TypedFunc<u32, ()>,
+    on_tick: TypedFunc<u32, i32>,
+    error: Option<RunError>,
+    _timer: Option<EpochTimer>,
+}
+
+impl WasmBot {
+    pub(crate) fn new(sandbox: Sandbox) -> Result<Self> {
+        let Sandbox {
+            mut store,
+            linker,
+            module,
+            timer,
+        } = sandbox;
+        let instance = linker.instantiate(&mut store, &module)?;
+        // Reactors initialize themselves in `_initialize`, commands in every export.
+        if let Ok(initialize) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            initialize.call(&mut store, ())?;
+        }
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("strategy doesn't export its memory")?;
+        Ok(Self {
+            input_buffer: instance.get_typed_func(&mut store, abi::INPUT_BUFFER)?,
+            on_start: instance.get_typed_func(&mut store, abi::ON_START)?,
+            on_tick: instance.get_typed_func(&mut store, abi::ON_TICK)?,
+            store,
+            memory,
+            error: None,
+            _timer: timer,
+        })
+    }
+
+    /// Why the strategy was stopped, if it was.
+    pub fn error(&self) -> Option<&RunError> {
+        self.error.as_ref()
+    }
+
+    pub fn fuel_consumed(&self) -> u64 {
+        self.store.fuel_consumed().unwrap()
+    }
+
+    /// Fuel burned by each call.
+    pub fn fuel_per_tick(&self) -> &[u64] {
+        &self.store.data().ticks.fuel_per_tick
+    }
+
+    /// Size of the largest memory of the strategy so far, in bytes.
+    pub fn peak_memory(&self) -> usize {
+        self.store.data().limiter.peak_memory
+    }
+
+    /// Passes the input to the strategy and calls it as one tick.
+    fn call<R>(
+        &mut self,
+        input: &[u8],
+        call: impl FnOnce(&mut Store<AppState>, u32) -> Result<R>,
+    ) -> Result<R, RunError> {
+        let consumed = self.fuel_consumed();
+        let result = self.store.data_mut().ticks.start_tick(consumed);
+        let result = result
+            .map_or(Ok(()), |fuel| ticks::set_fuel(&mut self.store, fuel))
+            .and_then(|()| {
+                let len = input.len() as u32;
+                let ptr = self.input_buffer.call(&mut self.store, len)?;
+                self.memory.write(&mut self.store, ptr as usize, input)?;
+                call(&mut self.store, len)
+            });
+
+        let consumed = self.fuel_consumed();
+        let ticks = &mut self.store.data_mut().ticks;
+        ticks.finish_tick(consumed);
+        result.map_err(|err| ticks.classify(err))
+    }
+}
+
+impl Bot for WasmBot {
+    fn on_start(&mut self, params: GameParams) {
+        let on_start = self.on_start.clone();
+        let result = self.call(&abi::encode(&params), |store, len| {
+            on_start.call(store, len)
+        });
+        self.error = result.err();
+    }
+
+    fn on_tick(&mut self, world: World) -> Direction {
+        let straight = world.me().direction.unwrap_or(Direction::Up);
+        if self.error.is_some() {
+            return straight;
+        }
+
+        let on_tick = self.on_tick.clone();
+        let result = self.call(&abi::encode(&world), |store, len| on_tick.call(store, len));
+        match result.map(abi::decode_direction) {
+            Ok(Some(direction)) => direction,
+            Ok(None) => {
+                self.error = Some(RunError::Trap(anyhow!("strategy returned no direction")));
+                straight
+            }
+            Err(err) => {
+                self.error = Some(err);
+                straight
+            }
+        }
+    }
+}
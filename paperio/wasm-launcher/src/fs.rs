@@ -0,0 +1,66 @@
+use std::{any::Any, path::PathBuf};
+
+use wasi_common::{
+    dir::{OpenResult, ReaddirCursor, ReaddirEntity, WasiDir},
+    file::{FdFlags, Filestat, OFlags},
+    Error, ErrorExt,
+};
+
+/// Directory the strategy may only read: files are opened without write access,
+/// and nothing can be created, removed or renamed.
+pub(crate) struct ReadOnlyDir(pub Box<dyn WasiDir>);
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<OpenResult, Error> {
+        if write
+            || oflags.intersects(OFlags::CREATE | OFlags::TRUNCATE)
+            || fdflags.contains(FdFlags::APPEND)
+        {
+            return Err(Error::perm());
+        }
+        let result = self
+            .0
+            .open_file(symlink_follow, path, oflags, read, false, fdflags)
+            .await?;
+        Ok(match result {
+            OpenResult::Dir(dir) => OpenResult::Dir(Box::new(ReadOnlyDir(dir))),
+            file => file,
+        })
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+}
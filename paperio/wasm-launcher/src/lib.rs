@@ -1,21 +1,43 @@
-use anyhow::Result;
+mod bot;
+mod fs;
+mod limits;
+pub mod pipe;
+mod ticks;
+
+pub use bot::WasmBot;
+
+use anyhow::{Context, Result};
 
 use std::{
     any::Any,
+    fmt,
     io::{Read, Write},
     net::TcpStream,
     os::unix::net::UnixStream,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
+use paperio_proto::abi;
 use wasi_common::{
     file::WasiFile,
     pipe::{ReadPipe, WritePipe},
     WasiCtx,
 };
-use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimitsBuilder};
 use wasmtime_wasi::WasiCtxBuilder;
 
+use crate::{
+    fs::ReadOnlyDir,
+    limits::Limiter,
+    pipe::{pipe, BoundedLog, PipeReader, PipeWriter},
+    ticks::{EpochTimer, HasTicks, Ticks},
+};
+
 pub trait IntoWasiFile {
     fn into_wasi_file(self) -> impl WasiFile + 'static;
 }
@@ -48,33 +70,142 @@ impl<R: Read + Any + Send + Sync> IntoWasiFile for ReadPipe<R> {
 
 pub struct RunStatus {
     pub fuel_consumed: u64,
-    pub result: Result<()>,
+    /// Fuel burned answering each message, see [`WasmStrategyRunner::tick_fuel_limit`].
+    pub fuel_per_tick: Vec<u64>,
+    /// Size of the largest memory of the strategy, in bytes.
+    pub peak_memory: usize,
+    pub result: Result<(), RunError>,
+}
+
+/// Why the strategy was stopped. Ticks are counted from zero,
+/// one per message the strategy answered.
+#[derive(Debug)]
+pub enum RunError {
+    /// The strategy burned more fuel than [`WasmStrategyRunner::tick_fuel_limit`] allows.
+    TickFuelExceeded { tick: usize, limit: u64 },
+    /// The strategy burned all of [`WasmStrategyRunner::cpu_fuel_limit`].
+    FuelExhausted,
+    /// The strategy didn't answer within [`WasmStrategyRunner::tick_timeout`].
+    TickTimeout { tick: usize, timeout: Duration },
+    /// The strategy was stopped by an [`Interrupter`].
+    Interrupted,
+    /// The strategy exited with a non-zero code.
+    Exit(i32),
+    /// The strategy crashed.
+    Trap(anyhow::Error),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TickFuelExceeded { tick, limit } => {
+                write!(f, "tick #{tick} took more than {limit} fuel")
+            }
+            Self::FuelExhausted => write!(f, "all fuel is consumed"),
+            Self::TickTimeout { tick, timeout } => {
+                write!(f, "tick #{tick} took more than {timeout:?}")
+            }
+            Self::Interrupted => write!(f, "interrupted"),
+            Self::Exit(code) => write!(f, "exited with code {code}"),
+            Self::Trap(err) => write!(f, "{err:#}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+fn make_engine() -> Engine {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+    Engine::new(&config).expect("engine config is invalid")
+}
+
+/// A strategy compiled once and shared by many runners, so that every game
+/// doesn't pay for compilation. Each runner still gets its own engine,
+/// since interrupters work per engine.
+#[derive(Clone)]
+pub struct CompiledStrategy {
+    serialized_module: Arc<[u8]>,
+    has_abi: bool,
+}
+
+impl CompiledStrategy {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let module = Module::from_file(&make_engine(), path)
+            .map_err(|e| e.context("failed to load wasm file"))?;
+        Ok(Self {
+            serialized_module: module.serialize()?.into(),
+            has_abi: module.get_export(abi::ON_TICK).is_some(),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_wat(wat: &str) -> Self {
+        let module = Module::new(&make_engine(), wat).unwrap();
+        Self {
+            serialized_module: module.serialize().unwrap().into(),
+            has_abi: module.get_export(abi::ON_TICK).is_some(),
+        }
+    }
+
+    /// Whether the strategy exports the typed interface of [`paperio_proto::abi`]
+    /// and can be run with [`WasmStrategyRunner::into_bot`].
+    pub fn has_abi(&self) -> bool {
+        self.has_abi
+    }
+}
+
+/// Where [`WasmStrategyRunner::data_dir`] is seen by the strategy.
+pub const DATA_DIR: &str = "/data";
+
+enum ModuleSource {
+    File(PathBuf),
+    Compiled(CompiledStrategy),
 }
 
 pub struct WasmStrategyRunner {
     engine: Engine,
-    path: PathBuf,
+    module_source: ModuleSource,
     stdin: Option<Box<dyn WasiFile>>,
     stdout: Option<Box<dyn WasiFile>>,
     stderr: Option<Box<dyn WasiFile>>,
     cpu_fuel_limit: u64,
+    tick_fuel_limit: Option<u64>,
+    tick_timeout: Option<Duration>,
     memory_size_limit: usize,
+    table_limit: Option<usize>,
+    instance_limit: Option<usize>,
+    env: Vec<(String, String)>,
+    data_dir: Option<PathBuf>,
+    interrupted: Arc<AtomicBool>,
 }
 
 impl WasmStrategyRunner {
     pub fn new(path: impl Into<PathBuf>) -> Self {
-        let mut config = Config::new();
-        config.consume_fuel(true);
-        config.epoch_interruption(true);
+        Self::with_module_source(ModuleSource::File(path.into()))
+    }
+
+    pub fn from_compiled(strategy: &CompiledStrategy) -> Self {
+        Self::with_module_source(ModuleSource::Compiled(strategy.clone()))
+    }
 
+    fn with_module_source(module_source: ModuleSource) -> Self {
         Self {
-            engine: Engine::new(&config).expect("engine config is invalid"),
-            path: path.into(),
+            engine: make_engine(),
+            module_source,
             stdin: None,
             stdout: None,
             stderr: None,
             cpu_fuel_limit: u64::MAX,
+            tick_fuel_limit: None,
+            tick_timeout: None,
             memory_size_limit: usize::MAX,
+            table_limit: None,
+            instance_limit: None,
+            env: vec![],
+            data_dir: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -93,28 +224,142 @@ impl WasmStrategyRunner {
         self
     }
 
+    /// Connects stdin and stdout of the strategy to in-memory pipes.
+    /// Returns the host ends: a writer to strategy's stdin and a reader of its stdout.
+    pub fn in_memory_io(self) -> (Self, PipeWriter, PipeReader) {
+        let (stdin_writer, stdin_reader) = pipe();
+        let (stdout_writer, stdout_reader) = pipe();
+        let runner = self
+            .stdin(ReadPipe::new(stdin_reader))
+            .stdout(WritePipe::new(stdout_writer));
+        (runner, stdin_writer, stdout_reader)
+    }
+
+    /// Captures stderr of the strategy into a log of at most `limit` bytes.
+    pub fn stderr_log(self, limit: usize) -> (Self, BoundedLog) {
+        let log = BoundedLog::new(limit);
+        (self.stderr(WritePipe::new(log.clone())), log)
+    }
+
+    /// Sets an environment variable of the strategy. It sees no others.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// Passes the variable from the host environment, if it is set there.
+    pub fn allow_env(self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        match std::env::var(&name) {
+            Ok(value) => self.env(name, value),
+            Err(_) => self,
+        }
+    }
+
+    /// Directory the strategy may read, e.g. for weights. It is mounted at [`DATA_DIR`],
+    /// nothing else of the host file system is visible.
+    pub fn data_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(path.into());
+        self
+    }
+
     pub fn cpu_fuel_limit(mut self, limit: u64) -> Self {
         self.cpu_fuel_limit = limit;
         self
     }
 
+    /// Fuel for answering a single message. The strategy is refueled
+    /// every time it gets a new one, within what is left of `cpu_fuel_limit`.
+    pub fn tick_fuel_limit(mut self, limit: u64) -> Self {
+        self.tick_fuel_limit = Some(limit);
+        self
+    }
+
+    /// Wall-clock time for answering a single message.
+    pub fn tick_timeout(mut self, timeout: Duration) -> Self {
+        self.tick_timeout = Some(timeout);
+        self
+    }
+
     pub fn memory_size_limit(mut self, limit: usize) -> Self {
         self.memory_size_limit = limit;
         self
     }
 
+    /// Maximum number of tables across all instances.
+    pub fn table_limit(mut self, limit: usize) -> Self {
+        self.table_limit = Some(limit);
+        self
+    }
+
+    /// Maximum number of instances, including the strategy itself.
+    pub fn instance_limit(mut self, limit: usize) -> Self {
+        self.instance_limit = Some(limit);
+        self
+    }
+
+    /// What the strategy will be allowed to do.
+    pub fn isolation_report(&self) -> IsolationReport {
+        IsolationReport {
+            env: self.env.iter().map(|(name, _)| name.clone()).collect(),
+            data_dir: self.data_dir.clone(),
+            stdin: self.stdin.is_some(),
+            stdout: self.stdout.is_some(),
+            stderr: self.stderr.is_some(),
+            cpu_fuel_limit: self.cpu_fuel_limit,
+            tick_fuel_limit: self.tick_fuel_limit,
+            tick_timeout: self.tick_timeout,
+            memory_size_limit: self.memory_size_limit,
+            table_limit: self.table_limit,
+            instance_limit: self.instance_limit,
+        }
+    }
+
     pub fn make_iterrupter(&self) -> Interrupter {
         Interrupter {
             engine: self.engine.clone(),
+            interrupted: self.interrupted.clone(),
         }
     }
 
+    /// Runs the strategy until it exits, it talks over stdio.
     pub fn run(self) -> Result<RunStatus> {
-        struct AppState {
-            wasi_ctx: WasiCtx,
-            store_limits: StoreLimits,
-        }
+        let Sandbox {
+            mut store,
+            mut linker,
+            module,
+            timer,
+        } = self.into_sandbox()?;
+        linker.module(&mut store, "strategy", &module)?;
+
+        let result = linker
+            .get_default(&mut store, "strategy")?
+            .typed::<(), ()>(&store)?
+            .call(&mut store, ());
+        drop(timer);
 
+        let fuel_consumed = store.fuel_consumed().unwrap();
+        let ticks = &mut store.data_mut().ticks;
+        ticks.finish_tick(fuel_consumed);
+        let result = match result.map_err(|err| ticks.classify(err)) {
+            Err(RunError::Exit(0)) => Ok(()),
+            result => result,
+        };
+        Ok(RunStatus {
+            fuel_consumed,
+            fuel_per_tick: std::mem::take(&mut ticks.fuel_per_tick),
+            peak_memory: store.data().limiter.peak_memory,
+            result,
+        })
+    }
+
+    /// Instantiates a strategy with the typed interface of [`paperio_proto::abi`].
+    /// Every call gets the limits of a tick.
+    pub fn into_bot(self) -> Result<WasmBot> {
+        WasmBot::new(self.into_sandbox()?)
+    }
+
+    fn into_sandbox(self) -> Result<Sandbox> {
         let mut linker = Linker::new(&self.engine);
         wasmtime_wasi::add_to_linker(&mut linker, |s: &mut AppState| &mut s.wasi_ctx)?;
 
@@ -128,46 +373,298 @@ impl WasmStrategyRunner {
         if let Some(stderr) = self.stderr {
             wasi_ctx_builder = wasi_ctx_builder.stderr(stderr);
         }
+        for (name, value) in &self.env {
+            wasi_ctx_builder = wasi_ctx_builder.env(name, value)?;
+        }
         let wasi_ctx = wasi_ctx_builder.build();
+        if let Some(path) = &self.data_dir {
+            let dir = cap_std::fs::Dir::open_ambient_dir(path, cap_std::ambient_authority())
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            let dir = wasmtime_wasi::Dir::from_cap_std(dir);
+            wasi_ctx.push_preopened_dir(Box::new(ReadOnlyDir(Box::new(dir))), DATA_DIR)?;
+        }
 
-        let store_limits = StoreLimitsBuilder::new()
+        let mut store_limits = StoreLimitsBuilder::new()
             .memory_size(self.memory_size_limit)
-            .trap_on_grow_failure(true)
-            .build();
+            .trap_on_grow_failure(true);
+        if let Some(limit) = self.table_limit {
+            store_limits = store_limits.tables(limit);
+        }
+        if let Some(limit) = self.instance_limit {
+            store_limits = store_limits.instances(limit);
+        }
+        let limiter = Limiter::new(store_limits.build());
 
+        let mut ticks = Ticks::new(
+            self.tick_fuel_limit,
+            self.cpu_fuel_limit,
+            self.tick_timeout,
+            self.interrupted.clone(),
+        );
+        let initial_fuel = ticks.initial_fuel(0);
         let mut store = Store::new(
             &self.engine,
             AppState {
                 wasi_ctx,
-                store_limits,
+                limiter,
+                ticks,
             },
         );
-        store.add_fuel(self.cpu_fuel_limit)?;
-        store.limiter(|s| &mut s.store_limits);
+        store.add_fuel(initial_fuel)?;
+        store.limiter(|s| &mut s.limiter);
         store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|store| store.data().ticks.check_deadline());
+        ticks::add_to_linker(&mut linker, &mut store)?;
 
-        let module = Module::from_file(&self.engine, self.path)
-            .map_err(|e| e.context("failed to load wasm file"))?;
-        linker.module(&mut store, "strategy", &module)?;
+        let module = match self.module_source {
+            ModuleSource::File(path) => Module::from_file(&self.engine, path)
+                .map_err(|e| e.context("failed to load wasm file"))?,
+            // SAFETY: the module was serialized by `CompiledStrategy::load`
+            // with an engine of the same configuration.
+            ModuleSource::Compiled(strategy) => unsafe {
+                Module::deserialize(&self.engine, &strategy.serialized_module)?
+            },
+        };
 
-        let result = linker
-            .get_default(&mut store, "strategy")?
-            .typed::<(), ()>(&store)?
-            .call(&mut store, ());
+        // Deadlines are only checked when the epoch changes.
+        let timer = self
+            .tick_timeout
+            .map(|_| EpochTimer::start(self.engine.clone()));
 
-        Ok(RunStatus {
-            fuel_consumed: store.fuel_consumed().unwrap(),
-            result,
+        Ok(Sandbox {
+            store,
+            linker,
+            module,
+            timer,
         })
     }
 }
 
+pub(crate) struct AppState {
+    wasi_ctx: WasiCtx,
+    limiter: Limiter,
+    ticks: Ticks,
+}
+
+impl HasTicks for AppState {
+    fn ticks(&mut self) -> &mut Ticks {
+        &mut self.ticks
+    }
+}
+
+/// Everything a strategy needs to run, with the limits of the runner.
+pub(crate) struct Sandbox {
+    store: Store<AppState>,
+    linker: Linker<AppState>,
+    module: Module,
+    timer: Option<EpochTimer>,
+}
+
+/// Resources and limits of a strategy, see [`WasmStrategyRunner::isolation_report`].
+/// Anything not listed isn't available: no other files, sockets or variables.
+#[derive(Debug)]
+pub struct IsolationReport {
+    /// Names of the environment variables, values may be secret.
+    pub env: Vec<String>,
+    /// Directory mounted read-only at [`DATA_DIR`].
+    pub data_dir: Option<PathBuf>,
+    pub stdin: bool,
+    pub stdout: bool,
+    pub stderr: bool,
+    pub cpu_fuel_limit: u64,
+    pub tick_fuel_limit: Option<u64>,
+    pub tick_timeout: Option<Duration>,
+    pub memory_size_limit: usize,
+    pub table_limit: Option<usize>,
+    pub instance_limit: Option<usize>,
+}
+
+impl fmt::Display for IsolationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn limit(value: Option<impl fmt::Debug>) -> String {
+            value.map_or("unlimited".into(), |value| format!("{value:?}"))
+        }
+
+        let streams = [
+            ("stdin", self.stdin),
+            ("stdout", self.stdout),
+            ("stderr", self.stderr),
+        ];
+        let streams = streams
+            .iter()
+            .filter(|(_, open)| *open)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        writeln!(f, "streams: {}", streams.join(", "))?;
+        writeln!(f, "environment: {}", self.env.join(", "))?;
+        match &self.data_dir {
+            Some(path) => writeln!(f, "files: {} at {DATA_DIR}, read-only", path.display())?,
+            None => writeln!(f, "files: none")?,
+        }
+        writeln!(
+            f,
+            "fuel: {} in total, {} per tick",
+            limit(Some(self.cpu_fuel_limit).filter(|&fuel| fuel != u64::MAX)),
+            limit(self.tick_fuel_limit),
+        )?;
+        writeln!(f, "tick timeout: {}", limit(self.tick_timeout))?;
+        writeln!(
+            f,
+            "memory: {}",
+            limit(Some(self.memory_size_limit).filter(|&size| size != usize::MAX)),
+        )?;
+        write!(
+            f,
+            "tables: {}, instances: {}",
+            limit(self.table_limit),
+            limit(self.instance_limit),
+        )
+    }
+}
+
 pub struct Interrupter {
     engine: Engine,
+    interrupted: Arc<AtomicBool>,
 }
 
 impl Interrupter {
+    /// Stops the strategy as soon as it runs wasm code.
     pub fn interrupt(self) {
+        self.interrupted.store(true, Ordering::Relaxed);
         self.engine.increment_epoch();
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+
+    const PAGE_SIZE: usize = 1 << 16;
+
+    /// Errno of WASI for a denied operation.
+    const ERRNO_PERM: i32 = 63;
+
+    fn runner(wat: &str) -> WasmStrategyRunner {
+        WasmStrategyRunner::from_compiled(&CompiledStrategy::from_wat(wat))
+    }
+
+    /// The code the strategy exited with, zero if it just returned.
+    fn exit_code(runner: WasmStrategyRunner) -> i32 {
+        match runner.run().unwrap().result {
+            Ok(()) => 0,
+            Err(RunError::Exit(code)) => code,
+            Err(err) => panic!("strategy failed: {err}"),
+        }
+    }
+
+    fn instantiates(runner: WasmStrategyRunner) -> bool {
+        runner.run().is_ok_and(|status| status.result.is_ok())
+    }
+
+    /// Opens `weights.bin` in the first preopened directory
+    /// and exits with the errno.
+    fn open_wat(oflags: u32, rights: u64) -> String {
+        format!(
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "path_open"
+                    (func $path_open
+                        (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "weights.bin")
+                (func (export "_start")
+                    (call $proc_exit
+                        (call $path_open
+                            (i32.const 3) (i32.const 0) (i32.const 16) (i32.const 11)
+                            (i32.const {oflags}) (i64.const {rights}) (i64.const 0)
+                            (i32.const 0) (i32.const 8)))))
+            "#
+        )
+    }
+
+    #[test]
+    fn data_dir_is_read_only() {
+        const OFLAGS_CREATE: u32 = 1;
+        const RIGHTS_FD_READ: u64 = 1 << 1;
+        const RIGHTS_FD_WRITE: u64 = 1 << 6;
+
+        let data_dir = std::env::temp_dir().join(format!("paperio-data-{}", std::process::id()));
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("weights.bin"), [1, 2, 3]).unwrap();
+        let open =
+            |oflags, rights| exit_code(runner(&open_wat(oflags, rights)).data_dir(&data_dir));
+
+        assert_eq!(open(0, RIGHTS_FD_READ), 0);
+        assert_eq!(open(0, RIGHTS_FD_WRITE), ERRNO_PERM);
+        assert_eq!(open(OFLAGS_CREATE, RIGHTS_FD_READ), ERRNO_PERM);
+        fs::remove_file(data_dir.join("weights.bin")).unwrap();
+        assert_eq!(open(OFLAGS_CREATE, RIGHTS_FD_WRITE), ERRNO_PERM);
+        assert!(!data_dir.join("weights.bin").exists());
+        fs::remove_dir(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn only_passed_env_is_visible() {
+        // Exits with the number of environment variables.
+        const ENV_COUNT: &str = r#"
+            (module
+                (import "wasi_snapshot_preview1" "environ_sizes_get"
+                    (func $environ_sizes_get (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (call $environ_sizes_get (i32.const 0) (i32.const 4)))
+                    (call $proc_exit (i32.load (i32.const 0)))))
+        "#;
+
+        // Tests run in parallel, so the host environment is only read here.
+        let host_var = std::env::vars_os()
+            .find_map(|(name, value)| value.to_str().and(name.into_string().ok()))
+            .expect("host environment has no unicode variables");
+        let visible = || runner(ENV_COUNT).env("PAPERIO_TEST_VISIBLE", "1");
+        assert_eq!(exit_code(visible()), 1);
+        assert_eq!(exit_code(visible().allow_env("PAPERIO_TEST_UNSET")), 1);
+        assert_eq!(exit_code(visible().allow_env(host_var)), 2);
+    }
+
+    #[test]
+    fn tables_and_instances_are_limited() {
+        const WITH_TABLE: &str = r#"
+            (module
+                (memory (export "memory") 1)
+                (table 1 funcref)
+                (func (export "_start")))
+        "#;
+
+        assert!(instantiates(runner(WITH_TABLE)));
+        assert!(instantiates(runner(WITH_TABLE).table_limit(1)));
+        assert!(!instantiates(runner(WITH_TABLE).table_limit(0)));
+        assert!(instantiates(runner(WITH_TABLE).instance_limit(1)));
+        assert!(!instantiates(runner(WITH_TABLE).instance_limit(0)));
+    }
+
+    #[test]
+    fn peak_memory_follows_growth() {
+        const GROW: &str = r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (memory.grow (i32.const 2)))))
+        "#;
+
+        let status = runner(GROW).run().unwrap();
+        assert!(status.result.is_ok());
+        assert_eq!(status.peak_memory, 3 * PAGE_SIZE);
+
+        let status = runner(GROW).memory_size_limit(2 * PAGE_SIZE).run().unwrap();
+        assert!(matches!(status.result, Err(RunError::Trap(_))));
+        assert_eq!(status.peak_memory, PAGE_SIZE);
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::Result;
+use wasmtime::{ResourceLimiter, StoreLimits};
+
+/// [`StoreLimits`] that also remember the largest memory the strategy had.
+pub(crate) struct Limiter {
+    limits: StoreLimits,
+    pub peak_memory: usize,
+}
+
+impl Limiter {
+    pub fn new(limits: StoreLimits) -> Self {
+        Self {
+            limits,
+            peak_memory: 0,
+        }
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+        if allowed {
+            self.peak_memory = self.peak_memory.max(desired);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
@@ -1,8 +1,18 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use paperio_wasm_launcher::WasmStrategyRunner;
+use paperio_proto::{
+    traits::{Bot, Codec},
+    Command, Message,
+};
+use paperio_wasm_launcher::{pipe::BoundedLog, CompiledStrategy, WasmBot, WasmStrategyRunner};
 
-use std::net::TcpStream;
+use std::{
+    fs,
+    io::{BufReader, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -12,6 +22,39 @@ struct Arguments {
     address: String,
     #[arg(short, long, default_value_t = 8000)]
     port: u16,
+    /// Fuel the strategy may burn during the whole game, unlimited if not set.
+    #[arg(long)]
+    fuel_limit: Option<u64>,
+    /// Fuel the strategy may burn answering a single message, unlimited if not set.
+    #[arg(long)]
+    tick_fuel_limit: Option<u64>,
+    /// Wall-clock time for answering a single message, unlimited if not set.
+    #[arg(long)]
+    tick_timeout_ms: Option<u64>,
+    /// Memory the strategy may use in megabytes, unlimited if not set.
+    #[arg(long)]
+    memory_limit_mb: Option<usize>,
+    /// Maximum number of tables, wasmtime's default if not set.
+    #[arg(long)]
+    max_tables: Option<usize>,
+    /// Maximum number of instances, wasmtime's default if not set.
+    #[arg(long)]
+    max_instances: Option<usize>,
+    /// Environment variable passed to the strategy, can be repeated.
+    #[arg(long)]
+    allow_env: Vec<String>,
+    /// Directory the strategy may read at `/data`, e.g. with weights.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// File to save stderr of the strategy to, it is discarded if not set.
+    #[arg(long)]
+    log: Option<PathBuf>,
+    /// Maximum size of the log in kilobytes, the rest is dropped.
+    #[arg(long, default_value_t = 1024)]
+    log_limit_kb: usize,
+    /// Print what the strategy is allowed to do before running it.
+    #[arg(long)]
+    report: bool,
 }
 
 pub fn main() -> Result<()> {
@@ -21,11 +64,115 @@ pub fn main() -> Result<()> {
     let stdin = TcpStream::connect(&address).with_context(|| format!("failed to {address}"))?;
     let stdout = stdin.try_clone().context("failed to clone tcp stream")?;
 
-    let status = WasmStrategyRunner::new(args.path)
+    let strategy = CompiledStrategy::load(&args.path)?;
+    let mut runner = WasmStrategyRunner::from_compiled(&strategy);
+    if let Some(limit) = args.fuel_limit {
+        runner = runner.cpu_fuel_limit(limit);
+    }
+    if let Some(limit) = args.tick_fuel_limit {
+        runner = runner.tick_fuel_limit(limit);
+    }
+    if let Some(timeout) = args.tick_timeout_ms {
+        runner = runner.tick_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(limit) = args.memory_limit_mb {
+        runner = runner.memory_size_limit(limit << 20);
+    }
+    if let Some(limit) = args.max_tables {
+        runner = runner.table_limit(limit);
+    }
+    if let Some(limit) = args.max_instances {
+        runner = runner.instance_limit(limit);
+    }
+    for name in args.allow_env {
+        runner = runner.allow_env(name);
+    }
+    if let Some(path) = args.data_dir {
+        runner = runner.data_dir(path);
+    }
+    let mut log = None;
+    if let Some(path) = args.log {
+        let (with_log, stderr) = runner.stderr_log(args.log_limit_kb << 10);
+        runner = with_log;
+        log = Some((path, stderr));
+    }
+    if args.report {
+        eprintln!("{}", runner.isolation_report());
+    }
+
+    let result = run(runner, &strategy, stdin, stdout);
+    if let Some((path, log)) = log {
+        save_log(&path, &log)?;
+    }
+    result
+}
+
+fn run(
+    runner: WasmStrategyRunner,
+    strategy: &CompiledStrategy,
+    stdin: TcpStream,
+    stdout: TcpStream,
+) -> Result<()> {
+    if strategy.has_abi() {
+        let bot = runner
+            .into_bot()
+            .context("failed to instantiate strategy")?;
+        return run_bot(bot, stdin, stdout);
+    }
+
+    let status = runner
         .stdin(stdin)
         .stdout(stdout)
         .run()
         .context("failed to run strategy")?;
-
+    print_usage(
+        status.fuel_consumed,
+        &status.fuel_per_tick,
+        status.peak_memory,
+    );
     status.result.context("strategy failed")
 }
+
+/// Talks to the server on behalf of a strategy with the typed interface.
+fn run_bot(mut bot: WasmBot, stdin: TcpStream, mut stdout: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stdin);
+    let mut codec = Codec::new();
+    while let Ok(message) = codec.read_message(&mut reader) {
+        match message {
+            Message::StartGame(params) => bot.on_start(params),
+            Message::Tick(world) => {
+                let command = Command::ChangeDirection(bot.on_tick(world));
+                codec.write_command(&mut stdout, &command)?;
+                stdout.flush()?;
+            }
+            Message::EndGame {} => break,
+        }
+        if bot.error().is_some() {
+            break;
+        }
+    }
+
+    print_usage(bot.fuel_consumed(), bot.fuel_per_tick(), bot.peak_memory());
+    match bot.error() {
+        Some(err) => bail!("strategy failed: {err}"),
+        None => Ok(()),
+    }
+}
+
+fn print_usage(fuel_consumed: u64, fuel_per_tick: &[u64], peak_memory: usize) {
+    eprintln!(
+        "fuel consumed: {} in {} tick(s), at most {} per tick",
+        fuel_consumed,
+        fuel_per_tick.len(),
+        fuel_per_tick.iter().max().unwrap_or(&0),
+    );
+    eprintln!("peak memory: {} KiB", peak_memory >> 10);
+}
+
+fn save_log(path: &Path, log: &BoundedLog) -> Result<()> {
+    let mut contents = log.contents();
+    if log.truncated() > 0 {
+        contents.extend(format!("\n... {} more bytes dropped\n", log.truncated()).bytes());
+    }
+    fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{Arc, Condvar, Mutex},
+};
+
+#[derive(Default)]
+struct PipeState {
+    buffer: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+/// Reading end of an in-memory pipe. Reads block until data arrives,
+/// and return EOF once the writer is dropped and the buffer is drained.
+pub struct PipeReader(Arc<Pipe>);
+
+/// Writing end of an in-memory pipe. Writes fail once the reader is dropped.
+pub struct PipeWriter(Arc<Pipe>);
+
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let pipe = Arc::new(Pipe::default());
+    (PipeWriter(pipe.clone()), PipeReader(pipe))
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.0.state.lock().unwrap();
+        while state.buffer.is_empty() && !state.writer_closed {
+            state = self.0.ready.wait(state).unwrap();
+        }
+
+        let len = buf.len().min(state.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().reader_closed = true;
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.reader_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.buffer.extend(buf);
+        self.0.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().writer_closed = true;
+        self.0.ready.notify_all();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct LogState {
+    contents: Vec<u8>,
+    truncated: usize,
+}
+
+/// Log of bounded size. Writes never fail, whatever doesn't fit is dropped
+/// and only counted. Clones share the same log.
+#[derive(Clone)]
+pub struct BoundedLog {
+    state: Arc<Mutex<LogState>>,
+    limit: usize,
+}
+
+impl BoundedLog {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Default::default(),
+            limit,
+        }
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.state.lock().unwrap().contents.clone()
+    }
+
+    /// Number of bytes that didn't fit into the log.
+    pub fn truncated(&self) -> usize {
+        self.state.lock().unwrap().truncated
+    }
+}
+
+impl Write for BoundedLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let len = buf.len().min(self.limit - state.contents.len());
+        state.contents.extend(&buf[..len]);
+        state.truncated += buf.len() - len;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn log_is_bounded() {
+        let log = BoundedLog::new(8);
+        let mut writer = log.clone();
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b", world").unwrap();
+        assert_eq!(log.contents(), b"hello, w");
+        assert_eq!(log.truncated(), 4);
+    }
+}
@@ -0,0 +1,379 @@
+//! Fuel and time accounting per message the strategy answers.
+//!
+//! A tick starts when the strategy reads its input after having written an answer,
+//! and lasts until the next such read. The strategy doesn't answer `start_game`,
+//! so the first tick also covers the start of the game.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use wasmtime::{AsContextMut, Caller, Engine, Func, Linker, Store, Trap};
+
+use crate::RunError;
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const STDIN: i32 = 0;
+const STDOUT: i32 = 1;
+
+/// How often the timer checks tick deadlines.
+const EPOCH_PERIOD: Duration = Duration::from_millis(1);
+
+pub(crate) struct Ticks {
+    pub tick_fuel_limit: Option<u64>,
+    pub total_fuel_limit: u64,
+    pub tick_timeout: Option<Duration>,
+    pub interrupted: Arc<AtomicBool>,
+    /// Fuel burned by each finished tick.
+    pub fuel_per_tick: Vec<u64>,
+    /// Set by a write to stdout, the next read from stdin starts a new tick.
+    answered: bool,
+    /// Total fuel consumed when the current tick started, `None` before the first tick.
+    tick_start_fuel: Option<u64>,
+    deadline: Option<Instant>,
+    /// Whether the fuel of the current tick is limited by the tick budget
+    /// rather than by what is left of the total one.
+    limited_by_tick: bool,
+}
+
+impl Ticks {
+    pub fn new(
+        tick_fuel_limit: Option<u64>,
+        total_fuel_limit: u64,
+        tick_timeout: Option<Duration>,
+        interrupted: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            tick_fuel_limit,
+            total_fuel_limit,
+            tick_timeout,
+            interrupted,
+            fuel_per_tick: vec![],
+            answered: true,
+            tick_start_fuel: None,
+            deadline: None,
+            limited_by_tick: false,
+        }
+    }
+
+    /// Index of the current tick.
+    pub fn current_tick(&self) -> usize {
+        self.fuel_per_tick.len()
+    }
+
+    /// Fuel the strategy gets for the tick that starts after `consumed` fuel was burned.
+    pub fn initial_fuel(&mut self, consumed: u64) -> u64 {
+        let left = self.total_fuel_limit.saturating_sub(consumed);
+        match self.tick_fuel_limit {
+            Some(limit) if limit < left => {
+                self.limited_by_tick = true;
+                limit
+            }
+            _ => {
+                self.limited_by_tick = false;
+                left
+            }
+        }
+    }
+
+    /// Starts a new tick after `consumed` fuel was burned. Returns the fuel
+    /// for the tick, `None` if fuel isn't limited per tick.
+    pub fn start_tick(&mut self, consumed: u64) -> Option<u64> {
+        self.answered = false;
+        self.finish_tick(consumed);
+        self.tick_start_fuel = Some(consumed);
+        self.deadline = self.tick_timeout.map(|timeout| Instant::now() + timeout);
+        if self.tick_fuel_limit.is_none() {
+            return None;
+        }
+        Some(self.initial_fuel(consumed))
+    }
+
+    pub fn finish_tick(&mut self, consumed: u64) {
+        if let Some(start) = self.tick_start_fuel.take() {
+            self.fuel_per_tick.push(consumed - start);
+        }
+    }
+
+    /// Called on every epoch deadline, stops the strategy if it's time.
+    pub fn check_deadline(&self) -> Result<u64> {
+        if self.interrupted.load(Ordering::Relaxed) {
+            return Err(RunError::Interrupted.into());
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.tick_timeout) {
+            if Instant::now() >= deadline {
+                return Err(RunError::TickTimeout {
+                    tick: self.current_tick(),
+                    timeout,
+                }
+                .into());
+            }
+        }
+        Ok(1)
+    }
+
+    /// Turns an error of the run into the reason the strategy was stopped.
+    pub fn classify(&self, err: anyhow::Error) -> RunError {
+        let err = match err.downcast::<RunError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        if err.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+            return match self.tick_fuel_limit {
+                Some(limit) if self.limited_by_tick => RunError::TickFuelExceeded {
+                    tick: self.current_tick(),
+                    limit,
+                },
+                _ => RunError::FuelExhausted,
+            };
+        }
+        if let Some(exit) = err.downcast_ref::<wasi_common::I32Exit>() {
+            return RunError::Exit(exit.0);
+        }
+        RunError::Trap(err)
+    }
+}
+
+pub(crate) trait HasTicks {
+    fn ticks(&mut self) -> &mut Ticks;
+}
+
+/// Wraps `fd_read` and `fd_write` of WASI to see where ticks start.
+/// Must be called after WASI is added to the linker and before the module is instantiated.
+pub(crate) fn add_to_linker<T: HasTicks + 'static>(
+    linker: &mut Linker<T>,
+    store: &mut Store<T>,
+) -> Result<()> {
+    let fd_read = original_func(linker, store, "fd_read")?;
+    let fd_write = original_func(linker, store, "fd_write")?;
+
+    linker.allow_shadowing(true);
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_read",
+        move |mut caller: Caller<'_, T>, fd: i32, iovs: i32, iovs_len: i32, nread: i32| {
+            let errno = fd_read
+                .typed::<(i32, i32, i32, i32), i32>(&caller)?
+                .call(&mut caller, (fd, iovs, iovs_len, nread))?;
+            if fd == STDIN && caller.data_mut().ticks().answered {
+                start_tick(&mut caller)?;
+            }
+            Ok(errno)
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "fd_write",
+        move |mut caller: Caller<'_, T>, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| {
+            if fd == STDOUT {
+                caller.data_mut().ticks().answered = true;
+            }
+            fd_write
+                .typed::<(i32, i32, i32, i32), i32>(&caller)?
+                .call(&mut caller, (fd, iovs, iovs_len, nwritten))
+        },
+    )?;
+    linker.allow_shadowing(false);
+    Ok(())
+}
+
+fn original_func<T>(linker: &Linker<T>, store: &mut Store<T>, name: &str) -> Result<Func> {
+    linker
+        .get(&mut *store, WASI_MODULE, name)
+        .and_then(|export| export.into_func())
+        .ok_or_else(|| anyhow!("{WASI_MODULE}::{name} is not in the linker"))
+}
+
+/// The new message has just been read: the previous tick is over
+/// and the strategy gets fuel and time for the next one.
+fn start_tick<T: HasTicks>(caller: &mut Caller<'_, T>) -> Result<()> {
+    let consumed = caller.fuel_consumed().unwrap_or(0);
+    // Without a tick limit the fuel added at the start is enough.
+    match caller.data_mut().ticks().start_tick(consumed) {
+        Some(fuel) => set_fuel(caller, fuel),
+        None => Ok(()),
+    }
+}
+
+/// Sets the fuel left in the store.
+pub(crate) fn set_fuel(mut store: impl AsContextMut, fuel: u64) -> Result<()> {
+    let mut store = store.as_context_mut();
+    let remaining = store.consume_fuel(0)?;
+    if fuel > remaining {
+        store.add_fuel(fuel - remaining)?;
+    } else {
+        store.consume_fuel(remaining - fuel)?;
+    }
+    Ok(())
+}
+
+/// Bumps the epoch of the engine, so that deadlines are checked while the strategy runs.
+/// Stops when dropped.
+pub(crate) struct EpochTimer {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EpochTimer {
+    pub fn start(engine: Engine) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(EPOCH_PERIOD);
+                    engine.increment_epoch();
+                }
+            }
+        });
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for EpochTimer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompiledStrategy, RunStatus, WasmStrategyRunner};
+
+    use std::io::Write;
+
+    /// Answers every byte it reads with one byte, after spinning a thousand
+    /// iterations per unit of the byte. Zero makes it spin forever.
+    const SPINNER: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            ;; iovec of the byte at 16, counts of read and written bytes at 8 and 12
+            (data (i32.const 0) "\10\00\00\00\01\00\00\00")
+            (func (export "_start")
+                (local $n i32)
+                (loop $ticks
+                    (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (if (i32.eqz (i32.load (i32.const 8)))
+                        (then (return)))
+                    (local.set $n (i32.mul (i32.load8_u (i32.const 16)) (i32.const 1000)))
+                    (if (i32.eqz (local.get $n))
+                        (then (loop $forever (br $forever))))
+                    (loop $spin
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $spin (local.get $n)))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 12)))
+                    (br $ticks))))
+    "#;
+
+    fn spinner() -> WasmStrategyRunner {
+        WasmStrategyRunner::from_compiled(&CompiledStrategy::from_wat(SPINNER))
+    }
+
+    fn run(runner: WasmStrategyRunner, input: &[u8]) -> RunStatus {
+        let (runner, mut stdin, _stdout) = runner.in_memory_io();
+        stdin.write_all(input).unwrap();
+        drop(stdin);
+        runner.run().unwrap()
+    }
+
+    #[test]
+    fn ticks_account_fuel() {
+        let mut ticks = Ticks::new(Some(100), 250, None, Default::default());
+        assert_eq!(ticks.initial_fuel(0), 100);
+        assert_eq!(ticks.start_tick(10), Some(100));
+        assert_eq!(ticks.current_tick(), 0);
+        assert_eq!(ticks.start_tick(90), Some(100));
+        assert!(matches!(
+            ticks.classify(Trap::OutOfFuel.into()),
+            RunError::TickFuelExceeded {
+                tick: 1,
+                limit: 100
+            }
+        ));
+
+        // Only 50 of the total budget are left.
+        assert_eq!(ticks.start_tick(200), Some(50));
+        assert!(matches!(
+            ticks.classify(Trap::OutOfFuel.into()),
+            RunError::FuelExhausted
+        ));
+        ticks.finish_tick(230);
+        assert_eq!(ticks.fuel_per_tick, [80, 110, 30]);
+        assert_eq!(ticks.current_tick(), 3);
+
+        let mut ticks = Ticks::new(None, 250, None, Default::default());
+        assert_eq!(ticks.initial_fuel(0), 250);
+        assert_eq!(ticks.start_tick(0), None);
+        assert!(matches!(
+            ticks.classify(wasi_common::I32Exit(3).into()),
+            RunError::Exit(3)
+        ));
+        assert!(matches!(
+            ticks.classify(RunError::Interrupted.into()),
+            RunError::Interrupted
+        ));
+    }
+
+    #[test]
+    fn fuel_per_tick() {
+        let status = run(spinner(), &[1, 2, 4]);
+        assert!(status.result.is_ok());
+        let fuel = &status.fuel_per_tick;
+        // The last tick starts with the read that finds the input is over.
+        assert_eq!(fuel.len(), 4);
+        assert!(fuel[3] < fuel[0] && fuel[0] < fuel[1] && fuel[1] < fuel[2]);
+        assert!(fuel.iter().sum::<u64>() <= status.fuel_consumed);
+    }
+
+    #[test]
+    fn tick_fuel_exceeded() {
+        let status = run(spinner().tick_fuel_limit(100_000), &[1, 1, 100, 1]);
+        assert!(matches!(
+            status.result,
+            Err(RunError::TickFuelExceeded {
+                tick: 2,
+                limit: 100_000
+            })
+        ));
+    }
+
+    #[test]
+    fn fuel_exhausted() {
+        let runner = spinner().cpu_fuel_limit(100_000).tick_fuel_limit(1_000_000);
+        let status = run(runner, &[1, 1, 100, 1]);
+        assert!(matches!(status.result, Err(RunError::FuelExhausted)));
+        assert_eq!(status.fuel_per_tick.len(), 3);
+    }
+
+    #[test]
+    fn tick_timeout() {
+        let timeout = Duration::from_millis(50);
+        let status = run(spinner().tick_timeout(timeout), &[1, 1, 0, 1]);
+        match status.result {
+            Err(RunError::TickTimeout { tick, timeout: t }) => {
+                assert_eq!(tick, 2);
+                assert_eq!(t, timeout);
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+}
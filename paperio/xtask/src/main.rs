@@ -1,5 +1,7 @@
 use std::{
-    fs, process,
+    fs,
+    path::PathBuf,
+    process,
     thread::{self, JoinHandle},
     time::Duration,
 };
@@ -18,6 +20,10 @@ struct Args {
     #[arg(long, action)]
     /// Don't capture logs to log/.
     no_logs: bool,
+
+    #[arg(long, action)]
+    /// Run your strategy compiled to wasm, with the same limits as the bots.
+    wasm: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -36,6 +42,16 @@ enum Command {
 
     /// Run you strategy three times against bots (no gui).
     Challenge,
+
+    /// Build your strategy for wasm32-wasip1, so that it can be shared as the bundled bots.
+    BuildWasm,
+
+    /// Run a rated tournament between your strategy and the bundled bots.
+    Tournament {
+        /// Extra arguments for the tournament runner, see `--help` of `paperio-tournament`.
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
 }
 
 #[derive(Clone, Copy)]
@@ -50,15 +66,27 @@ enum Outcome {
     Lost,
 }
 
+/// Limits of wasm strategies, the same for the bundled bots and yours.
+const WASM_FUEL_LIMIT: u64 = 1_000_000_000_000;
+const WASM_MEMORY_LIMIT_MB: usize = 256;
+
+const WASM_TARGET: &str = "wasm32-wasip1";
+
 struct Recipe {
     gui_mode: GuiMode,
     run_strategy: bool,
     capture_logs: bool,
+    wasm_strategy: bool,
 }
 
 impl Recipe {
     fn run(&self) -> Result<()> {
         Self::build_binaries()?;
+        let wasm_path = if self.run_strategy && self.wasm_strategy {
+            Some(build_wasm_strategy()?)
+        } else {
+            None
+        };
 
         let server_handle = match self.gui_mode {
             GuiMode::Spectator => Self::launch_server(true, self.capture_logs),
@@ -74,7 +102,7 @@ impl Recipe {
         };
 
         let strategy_handle = if self.run_strategy {
-            Some(Self::launch_strategy(self.capture_logs))
+            Some(Self::launch_strategy(wasm_path, self.capture_logs))
         } else {
             None
         };
@@ -136,6 +164,7 @@ impl Recipe {
                     "--release",
                     "--",
                 ])
+                .args(wasm_limit_args())
                 .arg(bot_path);
 
                 let log_name = if capture_logs {
@@ -153,17 +182,35 @@ impl Recipe {
         Ok(handles)
     }
 
-    fn launch_strategy(capture_logs: bool) -> JoinHandle<Result<()>> {
+    /// Runs the strategy natively, or under the wasm launcher if `wasm_path` is set.
+    fn launch_strategy(wasm_path: Option<PathBuf>, capture_logs: bool) -> JoinHandle<Result<()>> {
         thread::spawn(move || -> Result<()> {
             let mut cmd = process::Command::new("cargo");
-            cmd.args([
-                "run",
-                "--package",
-                "paperio-strategy",
-                "--release",
-                "--",
-                "8004",
-            ]);
+            match wasm_path {
+                Some(wasm_path) => {
+                    cmd.args([
+                        "run",
+                        "--package",
+                        "paperio-wasm-launcher",
+                        "--release",
+                        "--",
+                        "--port",
+                        "8004",
+                    ])
+                    .args(wasm_limit_args())
+                    .arg(wasm_path);
+                }
+                None => {
+                    cmd.args([
+                        "run",
+                        "--package",
+                        "paperio-strategy",
+                        "--release",
+                        "--",
+                        "8004",
+                    ]);
+                }
+            }
 
             let log_name = if capture_logs { Some("strategy") } else { None };
             Self::run_cmd(cmd, log_name)?;
@@ -208,8 +255,8 @@ impl Recipe {
                 "paperio-server",
                 "--release",
                 "--",
-                "--p4",
-                "8004",
+                "--ports",
+                "8000,8000,8000,8004",
             ]);
 
             if with_spectator {
@@ -252,7 +299,6 @@ impl Recipe {
             "$ {} {}",
             cmd.get_program().to_string_lossy(),
             cmd.get_args()
-                .into_iter()
                 .map(|a| a.to_string_lossy())
                 .collect::<Vec<_>>()
                 .join(" ")
@@ -266,20 +312,50 @@ impl Recipe {
     }
 }
 
+fn wasm_limit_args() -> [String; 4] {
+    [
+        "--fuel-limit".to_string(),
+        WASM_FUEL_LIMIT.to_string(),
+        "--memory-limit-mb".to_string(),
+        WASM_MEMORY_LIMIT_MB.to_string(),
+    ]
+}
+
+/// Returns the path to the built module.
+fn build_wasm_strategy() -> Result<PathBuf> {
+    let target_dir = get_cwd_task_path()?.join("target");
+    let sh = Shell::new()?;
+    cmd!(
+        sh,
+        "cargo build --package paperio-strategy --bin paperio-strategy-wasm --release --target {WASM_TARGET} --target-dir {target_dir}"
+    )
+    .run()
+    .with_context(|| format!("failed to build, try `rustup target add {WASM_TARGET}`"))?;
+
+    let path = target_dir
+        .join(WASM_TARGET)
+        .join("release")
+        .join("paperio-strategy-wasm.wasm");
+    eprintln!("Built {}", path.display());
+    Ok(path)
+}
+
 fn play(no_logs: bool) -> Result<()> {
     Recipe {
         gui_mode: GuiMode::Player,
         run_strategy: false,
         capture_logs: !no_logs,
+        wasm_strategy: false,
     }
     .run()
 }
 
-fn watch(no_logs: bool) -> Result<()> {
+fn watch(no_logs: bool, wasm: bool) -> Result<()> {
     Recipe {
         gui_mode: GuiMode::Spectator,
         run_strategy: true,
         capture_logs: !no_logs,
+        wasm_strategy: wasm,
     }
     .run()
 }
@@ -289,11 +365,12 @@ fn debug(no_logs: bool) -> Result<()> {
         gui_mode: GuiMode::Spectator,
         run_strategy: false,
         capture_logs: !no_logs,
+        wasm_strategy: false,
     }
     .run()
 }
 
-fn challenge(no_logs: bool) -> Result<()> {
+fn challenge(no_logs: bool, wasm: bool) -> Result<()> {
     for i in 1..=3 {
         eprintln!("Running test #{i}...");
 
@@ -301,19 +378,45 @@ fn challenge(no_logs: bool) -> Result<()> {
             gui_mode: GuiMode::None,
             run_strategy: true,
             capture_logs: !no_logs,
+            wasm_strategy: wasm,
         }
         .run()?;
     }
     Ok(())
 }
 
+fn tournament(args: Vec<String>, wasm: bool) -> Result<()> {
+    let mut bot_paths = fs::read_dir(get_cwd_task_path()?.join("bots"))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    bot_paths.retain(|path| path.extension().is_some_and(|ext| ext == "wasm"));
+    bot_paths.sort();
+
+    // Your strategy is linked into the tournament runner and plays in-process,
+    // unless it is asked to play as a wasm module like the bots.
+    let strategy = if wasm {
+        build_wasm_strategy()?.into_os_string()
+    } else {
+        "builtin".into()
+    };
+    let sh = Shell::new()?;
+    cmd!(
+        sh,
+        "cargo run --package paperio-tournament --release -- {strategy} {bot_paths...} {args...}"
+    )
+    .run()?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
     match args.cmd {
         Command::Base(cmd) => xtask_base::run_command(cmd),
         Command::Play => play(args.no_logs),
-        Command::Watch => watch(args.no_logs),
+        Command::Watch => watch(args.no_logs, args.wasm),
         Command::Debug => debug(args.no_logs),
-        Command::Challenge => challenge(args.no_logs),
+        Command::Challenge => challenge(args.no_logs, args.wasm),
+        Command::BuildWasm => build_wasm_strategy().map(drop),
+        Command::Tournament { args: extra_args } => tournament(extra_args, args.wasm),
     }
 }